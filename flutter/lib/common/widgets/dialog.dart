@@ -145,7 +145,15 @@ void changeWhiteList({Function()? callback}) async {
                 }
                 newWhiteList = ips.join(',');
               }
-              await bind.mainSetOption(key: 'whitelist', value: newWhiteList);
+              final err = await bind.mainSetOption(
+                  key: 'whitelist', value: newWhiteList);
+              if (err.isNotEmpty) {
+                setState(() {
+                  msg = err;
+                  isInProgress = false;
+                });
+                return;
+              }
               callback?.call();
               close();
             },
@@ -195,8 +203,12 @@ Future<String> changeDirectAccessPort(
         TextButton(onPressed: close, child: Text(translate("Cancel"))),
         TextButton(
             onPressed: () async {
-              await bind.mainSetOption(
+              final err = await bind.mainSetOption(
                   key: 'direct-access-port', value: controller.text);
+              if (err.isNotEmpty) {
+                showToast(translate(err));
+                return;
+              }
               close();
             },
             child: Text(translate("OK"))),
@@ -1,3 +1,5 @@
+import 'dart:convert';
+
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
 import 'package:flutter_hbb/common/widgets/address_book.dart';
@@ -525,6 +527,60 @@ abstract class BasePeerCard extends StatelessWidget {
     );
   }
 
+  /// Fingerprints of the pinned key of the peer and of a new one to review.
+  Map<String, dynamic> _peerKey(String id) {
+    return jsonDecode(bind.mainGetPeerKey(id: id));
+  }
+
+  @protected
+  MenuEntryBase<String> _reviewKeyAction(String id) {
+    return MenuEntryButton<String>(
+      childBuilder: (TextStyle? style) => Text(
+        translate('Review New Key'),
+        style: style,
+      ),
+      proc: () {
+        _reviewKey(id);
+      },
+      padding: menuPadding,
+      dismissOnClicked: true,
+    );
+  }
+
+  void _reviewKey(String id) {
+    final key = _peerKey(id);
+    gFFI.dialogManager.show((setState, close) {
+      submit() async {
+        final err = await bind.mainAcceptPeerKey(id: id);
+        close();
+        if (err.isNotEmpty) {
+          showToast(err);
+        }
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Review New Key')),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            Text(translate(
+                'The key of the peer changed, accept the new one only if you know why.')),
+            const SizedBox(height: 8),
+            SelectableText('${translate('Pinned key')}: ${key['pinned']}'),
+            SelectableText('${translate('New key')}: ${key['pending']}'),
+          ],
+        ),
+        actions: [
+          TextButton(onPressed: close, child: Text(translate("Cancel"))),
+          TextButton(onPressed: submit, child: Text(translate("Accept"))),
+        ],
+        onSubmit: submit,
+        onCancel: close,
+      );
+    });
+  }
+
   @protected
   MenuEntryBase<String> _addFavAction(String id) {
     return MenuEntryButton<String>(
@@ -657,6 +713,9 @@ class RecentPeerCard extends BasePeerCard {
     if (await bind.mainPeerHasPassword(id: peer.id)) {
       menuItems.add(_unrememberPasswordAction(peer.id));
     }
+    if ((_peerKey(peer.id)['pending'] as String).isNotEmpty) {
+      menuItems.add(_reviewKeyAction(peer.id));
+    }
     menuItems.add(_addFavAction(peer.id));
     return menuItems;
   }
@@ -689,6 +748,9 @@ class FavoritePeerCard extends BasePeerCard {
     if (await bind.mainPeerHasPassword(id: peer.id)) {
       menuItems.add(_unrememberPasswordAction(peer.id));
     }
+    if ((_peerKey(peer.id)['pending'] as String).isNotEmpty) {
+      menuItems.add(_reviewKeyAction(peer.id));
+    }
     menuItems.add(_rmFavAction(peer.id, () async {
       await bind.mainLoadFavPeers();
     }));
@@ -746,6 +808,9 @@ class AddressBookPeerCard extends BasePeerCard {
     if (await bind.mainPeerHasPassword(id: peer.id)) {
       menuItems.add(_unrememberPasswordAction(peer.id));
     }
+    if ((_peerKey(peer.id)['pending'] as String).isNotEmpty) {
+      menuItems.add(_reviewKeyAction(peer.id));
+    }
     if (gFFI.abModel.tags.isNotEmpty) {
       menuItems.add(_editTagAction(peer.id));
     }
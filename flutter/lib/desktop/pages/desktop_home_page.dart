@@ -601,14 +601,13 @@ Future<bool> loginDialog() async {
 }
 
 void setPasswordDialog() async {
-  final pw = await bind.mainGetPermanentPassword();
-  final p0 = TextEditingController(text: pw);
-  final p1 = TextEditingController(text: pw);
+  final p0 = TextEditingController();
+  final p1 = TextEditingController();
   var errMsg0 = "";
   var errMsg1 = "";
 
   gFFI.dialogManager.show((setState, close) {
-    submit() {
+    submit() async {
       setState(() {
         errMsg0 = "";
         errMsg1 = "";
@@ -626,7 +625,13 @@ void setPasswordDialog() async {
         });
         return;
       }
-      bind.mainSetPermanentPassword(password: pass);
+      final err = await bind.mainSetPermanentPassword(password: pass);
+      if (err.isNotEmpty) {
+        setState(() {
+          errMsg0 = translate(err);
+        });
+        return;
+      }
       close();
     }
 
@@ -1389,7 +1389,12 @@ void changeServer() async {
       }
       // ok
       oldOptions['key'] = key;
-      await bind.mainSetOptions(json: jsonEncode(oldOptions));
+      final err = await bind.mainSetOptions(json: jsonEncode(oldOptions));
+      if (err.isNotEmpty) {
+        idServerMsg = translate(err);
+        cancel();
+        return;
+      }
       close();
     }
 
@@ -1577,7 +1582,11 @@ void importServer() async {
     }
     // ok
     oldOptions['key'] = key;
-    await bind.mainSetOptions(json: jsonEncode(oldOptions));
+    final err = await bind.mainSetOptions(json: jsonEncode(oldOptions));
+    if (err.isNotEmpty) {
+      debugPrint(err);
+      return false;
+    }
     debugPrint("set ID/Realy Server Ok");
     return true;
   }
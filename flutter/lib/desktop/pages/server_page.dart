@@ -366,20 +366,36 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
           FittedBox(
               child: Row(
             children: [
-              buildPermissionIcon(client.keyboard, iconKeyboard, (enabled) {
-                bind.cmSwitchPermission(
-                    connId: client.id, name: "keyboard", enabled: enabled);
-                setState(() {
-                  client.keyboard = enabled;
-                });
-              }, translate('Allow using keyboard and mouse')),
-              buildPermissionIcon(client.clipboard, iconClipboard, (enabled) {
-                bind.cmSwitchPermission(
-                    connId: client.id, name: "clipboard", enabled: enabled);
-                setState(() {
-                  client.clipboard = enabled;
-                });
-              }, translate('Allow using clipboard')),
+              buildPermissionIcon(
+                  client.keyboard,
+                  iconKeyboard,
+                  client.viewOnly
+                      ? null
+                      : (enabled) {
+                          bind.cmSwitchPermission(
+                              connId: client.id,
+                              name: "keyboard",
+                              enabled: enabled);
+                          setState(() {
+                            client.keyboard = enabled;
+                          });
+                        },
+                  translate('Allow using keyboard and mouse')),
+              buildPermissionIcon(
+                  client.clipboard,
+                  iconClipboard,
+                  client.viewOnly
+                      ? null
+                      : (enabled) {
+                          bind.cmSwitchPermission(
+                              connId: client.id,
+                              name: "clipboard",
+                              enabled: enabled);
+                          setState(() {
+                            client.clipboard = enabled;
+                          });
+                        },
+                  translate('Allow using clipboard')),
               buildPermissionIcon(client.audio, iconAudio, (enabled) {
                 bind.cmSwitchPermission(
                     connId: client.id, name: "audio", enabled: enabled);
@@ -40,9 +40,8 @@ void showRestartRemoteDevice(
 }
 
 void setPermanentPasswordDialog(OverlayDialogManager dialogManager) async {
-  final pw = await bind.mainGetPermanentPassword();
-  final p0 = TextEditingController(text: pw);
-  final p1 = TextEditingController(text: pw);
+  final p0 = TextEditingController();
+  final p1 = TextEditingController();
   var validateLength = false;
   var validateSame = false;
   dialogManager.show((setState, close) {
@@ -106,12 +105,13 @@ void setPermanentPasswordDialog(OverlayDialogManager dialogManager) async {
               ? () async {
                   close();
                   dialogManager.showLoading(translate("Waiting"));
-                  if (await gFFI.serverModel.setPermanentPassword(p0.text)) {
-                    dialogManager.dismissAll();
+                  final err =
+                      await gFFI.serverModel.setPermanentPassword(p0.text);
+                  dialogManager.dismissAll();
+                  if (err.isEmpty) {
                     showSuccess();
                   } else {
-                    dialogManager.dismissAll();
-                    showError();
+                    showToast(translate(err));
                   }
                 }
               : null,
@@ -156,9 +156,13 @@ void setTemporaryPasswordLengthDialog(
   }, backDismiss: true, clickMaskDismiss: true);
 }
 
-void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
+void enterPasswordDialog(String id, OverlayDialogManager dialogManager,
+    {String text = ''}) async {
   final controller = TextEditingController();
+  final passphraseController = TextEditingController();
   var remember = await bind.sessionGetRemember(id: id) ?? false;
+  final locked = bind.mainIsPasswordStoreLocked();
+  String? passphraseError;
   dialogManager.dismissAll();
   dialogManager.show((setState, close) {
     cancel() {
@@ -166,9 +170,17 @@ void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
       closeConnection();
     }
 
-    submit() {
+    submit() async {
       var text = controller.text.trim();
       if (text == '') return;
+      if (remember && locked) {
+        final err = await bind.mainUnlockPasswordStore(
+            passphrase: passphraseController.text);
+        if (err.isNotEmpty) {
+          setState(() => passphraseError = translate(err));
+          return;
+        }
+      }
       gFFI.login(id, text, remember);
       close();
       dialogManager.showLoading(translate('Logging in...'),
@@ -178,6 +190,7 @@ void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
     return CustomAlertDialog(
       title: Text(translate('Password Required')),
       content: Column(mainAxisSize: MainAxisSize.min, children: [
+        if (text.isNotEmpty) Text(translate(text)),
         PasswordWidget(controller: controller),
         CheckboxListTile(
           contentPadding: const EdgeInsets.all(0),
@@ -193,6 +206,64 @@ void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
             }
           },
         ),
+        if (locked)
+          TextField(
+            controller: passphraseController,
+            obscureText: true,
+            keyboardType: TextInputType.visiblePassword,
+            decoration: InputDecoration(
+              labelText: translate('password_store_passphrase_tip'),
+              errorText: passphraseError,
+            ),
+          ),
+      ]),
+      actions: [
+        TextButton(
+          style: flatButtonStyle,
+          onPressed: cancel,
+          child: Text(translate('Cancel')),
+        ),
+        TextButton(
+          style: flatButtonStyle,
+          onPressed: submit,
+          child: Text(translate('OK')),
+        ),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
+void enter2faDialog(
+    String id, String title, OverlayDialogManager dialogManager) async {
+  final controller = TextEditingController();
+  dialogManager.dismissAll();
+  dialogManager.show((setState, close) {
+    cancel() {
+      close();
+      closeConnection();
+    }
+
+    submit() {
+      var code = controller.text.trim();
+      if (code == '') return;
+      bind.sessionLogin2Fa(id: id, code: code);
+      close();
+      dialogManager.showLoading(translate('Logging in...'),
+          onCancel: closeConnection);
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate(title)),
+      content: Column(mainAxisSize: MainAxisSize.min, children: [
+        Text(translate('Please enter the code of your authenticator app')),
+        TextField(
+          controller: controller,
+          autofocus: true,
+          keyboardType: TextInputType.number,
+          maxLength: 6,
+        ),
       ]),
       actions: [
         TextButton(
@@ -810,6 +810,8 @@ class FileModel extends ChangeNotifier {
       to: isRemote ? to : remote,
       fileNum: fileNum,
     );
+    // the receiver keeps the partial file, resume from its blocks
+    resumeJob(currJobId);
   }
 
   resumeJob(int jobId) {
@@ -229,7 +229,9 @@ class FfiModel with ChangeNotifier {
     if (type == 're-input-password') {
       wrongPasswordDialog(id, dialogManager);
     } else if (type == 'input-password') {
-      enterPasswordDialog(id, dialogManager);
+      enterPasswordDialog(id, dialogManager, text: text);
+    } else if (type == 'input-2fa') {
+      enter2faDialog(id, title, dialogManager);
     } else if (type == 'restarting') {
       showMsgBox(id, type, title, text, link, false, dialogManager, hasCancel: false);
     } else {
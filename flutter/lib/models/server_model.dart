@@ -287,15 +287,9 @@ class ServerModel with ChangeNotifier {
     await parent.target?.invokeMethod("init_input");
   }
 
-  Future<bool> setPermanentPassword(String newPW) async {
-    await bind.mainSetPermanentPassword(password: newPW);
-    await Future.delayed(Duration(milliseconds: 500));
-    final pw = await bind.mainGetPermanentPassword();
-    if (newPW == pw) {
-      return true;
-    } else {
-      return false;
-    }
+  /// Returns the error if the password could not be set.
+  Future<String> setPermanentPassword(String newPW) async {
+    return await bind.mainSetPermanentPassword(password: newPW);
   }
 
   _fetchID() async {
@@ -526,6 +520,7 @@ class Client {
   bool file = false;
   bool restart = false;
   bool recording = false;
+  bool viewOnly = false;
   bool disconnected = false;
 
   Client(this.id, this.authorized, this.isFileTransfer, this.name, this.peerId,
@@ -543,6 +538,7 @@ class Client {
     file = json['file'];
     restart = json['restart'];
     recording = json['recording'];
+    viewOnly = json['view_only'] ?? false;
     disconnected = json['disconnected'];
   }
 
@@ -559,6 +555,7 @@ class Client {
     data['file'] = file;
     data['restart'] = restart;
     data['recording'] = recording;
+    data['view_only'] = viewOnly;
     data['disconnected'] = disconnected;
     return data;
   }
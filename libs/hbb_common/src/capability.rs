@@ -0,0 +1,164 @@
+//! Names of the optional features exchanged in [`Capabilities`].
+//!
+//! A peer that predates capability negotiation sends none, its set is derived from its
+//! version by [`legacy`] once at login, so the rest of the code checks capabilities only.
+//! New features add a name here and are used only if the peer announces it.
+use crate::{
+    get_version_number,
+    message_proto::{Capabilities, LoginRequest, PeerInfo},
+};
+
+pub const CODEC_VP9: &str = "vp9";
+pub const CODEC_H264: &str = "h264";
+pub const CODEC_H265: &str = "h265";
+
+/// Skip files already identical on the receiving side, see [`crate::fs::TransferJob`].
+pub const FILE_TRANSFER_OVERWRITE_DETECTION: &str = "overwrite_detection";
+
+pub const CLIPBOARD_TEXT: &str = "text";
+
+pub const INPUT_MOUSE: &str = "mouse";
+pub const INPUT_KEYBOARD_LEGACY: &str = "keyboard_legacy";
+pub const INPUT_KEYBOARD_MAP: &str = "keyboard_map";
+pub const INPUT_KEYBOARD_TRANSLATE: &str = "keyboard_translate";
+
+pub const SESSION_RESTART: &str = "restart";
+/// In-band rekeying, see [`crate::rekey`].
+pub const SESSION_REKEY: &str = "rekey";
+/// Logical channels inside the session, see [`crate::mux`].
+pub const SESSION_CHANNELS: &str = "channels";
+/// Passwords keyed with the parameters of [`crate::password_security`] given by the host.
+pub const SESSION_PASSWORD_KDF: &str = "password_kdf";
+
+/// The owned `names`, for the fields of [`Capabilities`].
+pub fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+}
+
+/// What a peer of `version` supports, for peers that do not send capabilities.
+pub fn legacy(version: &str) -> Capabilities {
+    let version = get_version_number(version);
+    let mut caps = Capabilities {
+        codecs: names(&[CODEC_VP9]),
+        clipboard: names(&[CLIPBOARD_TEXT]),
+        input: names(&[INPUT_MOUSE, INPUT_KEYBOARD_LEGACY]),
+        ..Default::default()
+    };
+    if version >= get_version_number("1.1.10") {
+        caps.file_transfer = names(&[FILE_TRANSFER_OVERWRITE_DETECTION]);
+        caps.session = names(&[SESSION_RESTART]);
+    }
+    if version >= get_version_number("1.2.0") {
+        caps.input
+            .extend(names(&[INPUT_KEYBOARD_MAP, INPUT_KEYBOARD_TRANSLATE]));
+    }
+    caps
+}
+
+impl Capabilities {
+    #[inline]
+    pub fn has_codec(&self, name: &str) -> bool {
+        self.codecs.iter().any(|x| x == name)
+    }
+
+    #[inline]
+    pub fn has_file_transfer(&self, name: &str) -> bool {
+        self.file_transfer.iter().any(|x| x == name)
+    }
+
+    #[inline]
+    pub fn has_clipboard(&self, name: &str) -> bool {
+        self.clipboard.iter().any(|x| x == name)
+    }
+
+    #[inline]
+    pub fn has_input(&self, name: &str) -> bool {
+        self.input.iter().any(|x| x == name)
+    }
+
+    #[inline]
+    pub fn has_session(&self, name: &str) -> bool {
+        self.session.iter().any(|x| x == name)
+    }
+}
+
+impl LoginRequest {
+    /// Capabilities of the client, derived from its version if it did not send them.
+    pub fn get_capabilities(&self) -> Capabilities {
+        self.capabilities
+            .clone()
+            .into_option()
+            .unwrap_or_else(|| legacy(&self.version))
+    }
+}
+
+impl PeerInfo {
+    /// Capabilities of the host, derived from its version if it did not send them.
+    pub fn get_capabilities(&self) -> Capabilities {
+        self.features
+            .as_ref()
+            .and_then(|x| x.capabilities.clone().into_option())
+            .unwrap_or_else(|| legacy(&self.version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_proto::Features;
+
+    #[test]
+    fn test_legacy() {
+        let caps = legacy("1.1.9");
+        assert!(caps.has_codec(CODEC_VP9));
+        assert!(!caps.has_file_transfer(FILE_TRANSFER_OVERWRITE_DETECTION));
+        assert!(!caps.has_session(SESSION_RESTART));
+        assert!(!caps.has_input(INPUT_KEYBOARD_MAP));
+        let caps = legacy("1.1.10");
+        assert!(caps.has_file_transfer(FILE_TRANSFER_OVERWRITE_DETECTION));
+        assert!(caps.has_session(SESSION_RESTART));
+        assert!(!caps.has_input(INPUT_KEYBOARD_MAP));
+        let caps = legacy("1.2.0");
+        assert!(caps.has_input(INPUT_KEYBOARD_MAP));
+        assert!(caps.has_input(INPUT_KEYBOARD_TRANSLATE));
+        assert!(!caps.has_session(SESSION_REKEY));
+    }
+
+    #[test]
+    fn test_announced_over_version() {
+        let caps = Capabilities {
+            session: names(&[SESSION_REKEY]),
+            ..Default::default()
+        };
+        let lr = LoginRequest {
+            version: "1.2.0".to_owned(),
+            capabilities: Some(caps.clone()).into(),
+            ..Default::default()
+        };
+        assert!(lr.get_capabilities().has_session(SESSION_REKEY));
+        // a peer announcing its set is not assumed to support anything else
+        assert!(!lr.get_capabilities().has_input(INPUT_KEYBOARD_MAP));
+        let lr = LoginRequest {
+            version: "1.2.0".to_owned(),
+            ..Default::default()
+        };
+        assert!(lr.get_capabilities().has_input(INPUT_KEYBOARD_MAP));
+
+        let pi = PeerInfo {
+            version: "1.1.9".to_owned(),
+            features: Some(Features {
+                capabilities: Some(caps).into(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        };
+        assert!(pi.get_capabilities().has_session(SESSION_REKEY));
+        let pi = PeerInfo {
+            version: "1.1.10".to_owned(),
+            features: Some(Features::new()).into(),
+            ..Default::default()
+        };
+        assert!(pi.get_capabilities().has_session(SESSION_RESTART));
+    }
+}
@@ -0,0 +1,385 @@
+//! Capture of the messages of a session, for debugging.
+//!
+//! Enabled by the option "enable-capture", a capture is a JSON lines file in the
+//! "capture" directory next to the logs: a [`Header`] followed by one [`Record`] per
+//! message, as decrypted by [`FramedStream`]. Payloads (screen, audio, files, clipboard,
+//! keystrokes, chat) are redacted unless "capture-payloads" is "Y". Secrets (passwords,
+//! 2FA codes and the session key) are never captured.
+//!
+//! [`replay`] turns the records back into a stream, so that the session can be fed
+//! into the code that handled it, e.g. `--replay <file>` for the client.
+use crate::{
+    bail,
+    config::Config,
+    log,
+    message_proto::*,
+    options,
+    protobuf::{text_format, Message as _},
+    tcp::FramedStream,
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Instant,
+};
+use tokio::time::{self, Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Header {
+    /// Peer id for the client, connection id for the host.
+    pub id: String,
+    pub is_host: bool,
+    pub time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the capture started.
+    pub t: u64,
+    pub dir: Direction,
+    /// The message in protobuf text format, always redacted.
+    pub msg: String,
+    /// Base64 of the message as sent without its secrets, only if payloads are captured.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub raw: String,
+}
+
+impl Record {
+    pub fn message(&self) -> ResultType<Message> {
+        if self.raw.is_empty() {
+            Ok(text_format::parse_from_str(&self.msg)?)
+        } else {
+            Ok(Message::parse_from_bytes(&base64::decode(&self.raw)?)?)
+        }
+    }
+}
+
+/// The records are written by a thread of their own, not to block the session on disk.
+pub struct Capture {
+    tx: Option<mpsc::Sender<String>>,
+    writer: Option<thread::JoinHandle<()>>,
+    start: Instant,
+    payloads: bool,
+}
+
+impl Capture {
+    /// Start a capture if enabled, `id` as in [`Header`].
+    pub fn start(id: &str, is_host: bool) -> Option<Self> {
+        if !options::get_bool("enable-capture") {
+            return None;
+        }
+        let now = crate::chrono::Local::now();
+        let side = if is_host { "host" } else { "client" };
+        let path = Self::dir().join(format!(
+            "{}_{}_{}.jsonl",
+            side,
+            id,
+            now.format("%Y%m%d%H%M%S")
+        ));
+        let header = Header {
+            id: id.to_owned(),
+            is_host,
+            time: now.to_rfc3339(),
+        };
+        let payloads = options::get_bool("capture-payloads");
+        match Self::create(&path, &header, payloads) {
+            Ok(capture) => {
+                log::info!("Capture session into {:?}", path);
+                Some(capture)
+            }
+            Err(err) => {
+                log::error!("Failed to create capture {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+
+    pub fn dir() -> PathBuf {
+        Config::log_path().join("capture")
+    }
+
+    pub fn create(path: &Path, header: &Header, payloads: bool) -> ResultType<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", serde_json::to_string(header)?)?;
+        file.flush()?;
+        let (tx, rx) = mpsc::channel::<String>();
+        let writer = thread::spawn(move || {
+            while let Ok(line) = rx.recv() {
+                // write what is pending and flush, so that the capture can be read while
+                // the session runs
+                let res = std::iter::once(line)
+                    .chain(rx.try_iter())
+                    .try_for_each(|line| writeln!(file, "{}", line))
+                    .and_then(|_| file.flush());
+                if let Err(err) = res {
+                    log::error!("Failed to write capture: {}", err);
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            tx: Some(tx),
+            writer: Some(writer),
+            start: Instant::now(),
+            payloads,
+        })
+    }
+
+    /// Stop the capture and wait for the pending records to be written.
+    pub fn close(mut self) {
+        self.tx.take();
+        if let Some(writer) = self.writer.take() {
+            writer.join().ok();
+        }
+    }
+
+    pub fn record(&mut self, dir: Direction, bytes: &[u8]) {
+        // not a session message, e.g. raw port forwarding data
+        let mut msg = match Message::parse_from_bytes(bytes) {
+            Ok(msg) => msg,
+            Err(_) => return,
+        };
+        let raw = if !self.payloads {
+            "".to_owned()
+        } else if redact_secrets(&mut msg) {
+            match msg.write_to_bytes() {
+                Ok(bytes) => base64::encode(bytes),
+                Err(_) => "".to_owned(),
+            }
+        } else {
+            base64::encode(bytes)
+        };
+        redact(&mut msg);
+        let record = Record {
+            t: self.start.elapsed().as_millis() as _,
+            dir,
+            msg: text_format::print_to_string(&msg),
+            raw,
+        };
+        match serde_json::to_string(&record) {
+            Ok(line) => {
+                if let Some(tx) = self.tx.as_ref() {
+                    // the writer has stopped on an error, already logged
+                    tx.send(line).ok();
+                }
+            }
+            Err(err) => log::error!("Failed to serialize capture: {}", err),
+        }
+    }
+}
+
+/// Clear the secrets in `msg`, returns whether there were any.
+fn redact_secrets(msg: &mut Message) -> bool {
+    match msg.union.as_mut() {
+        Some(message::Union::LoginRequest(lr)) => {
+            lr.password.clear();
+            lr.tfa_code.clear();
+            true
+        }
+        Some(message::Union::PublicKey(pk)) => {
+            pk.symmetric_value.clear();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Clear the contents of the user in `msg`, leaving what the protocol needs to
+/// make sense of it, e.g. sizes, ids and control keys.
+pub fn redact(msg: &mut Message) {
+    match msg.union.as_mut() {
+        Some(message::Union::VideoFrame(vf)) => match vf.union.as_mut() {
+            Some(video_frame::Union::Vp9s(frames))
+            | Some(video_frame::Union::H264s(frames))
+            | Some(video_frame::Union::H265s(frames)) => {
+                for frame in frames.frames.iter_mut() {
+                    frame.data.clear();
+                }
+            }
+            _ => {}
+        },
+        Some(message::Union::AudioFrame(af)) => af.data.clear(),
+        Some(message::Union::CursorData(cd)) => cd.colors.clear(),
+        Some(message::Union::Clipboard(cb)) => cb.content.clear(),
+        Some(message::Union::LoginRequest(_)) | Some(message::Union::PublicKey(_)) => {
+            redact_secrets(msg);
+        }
+        Some(message::Union::KeyEvent(ke)) => {
+            if !ke.has_control_key() {
+                ke.union = None;
+            }
+        }
+        Some(message::Union::FileResponse(fr)) => {
+            if let Some(file_response::Union::Block(block)) = fr.union.as_mut() {
+                block.data.clear();
+            }
+        }
+        Some(message::Union::Cliprdr(clip)) => match clip.union.as_mut() {
+            Some(cliprdr::Union::FormatDataResponse(res)) => res.format_data.clear(),
+            Some(cliprdr::Union::FileContentsResponse(res)) => res.requested_data.clear(),
+            _ => {}
+        },
+        Some(message::Union::Misc(misc)) => {
+            if let Some(misc::Union::ChatMessage(chat)) = misc.union.as_mut() {
+                chat.text.clear();
+            }
+        }
+        Some(message::Union::Channel(ch)) => {
+            if let Some(channel::Union::Data(data)) = ch.union.as_mut() {
+                data.clear();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Read a capture written by [`Capture`].
+pub fn load(path: &str) -> ResultType<(Header, Vec<Record>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => bail!("Empty capture"),
+    };
+    let mut records = Vec::new();
+    for line in lines {
+        let line = line?;
+        if !line.is_empty() {
+            records.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok((header, records))
+}
+
+/// A stream receiving the messages of `dir` in `records` with their original timing,
+/// whatever is sent to it is discarded. It stays open after the last message until
+/// it is dropped, so that the resulting state can be inspected.
+pub fn replay(records: Vec<Record>, dir: Direction) -> ResultType<FramedStream> {
+    let mut messages = Vec::new();
+    for record in records.iter().filter(|x| x.dir == dir) {
+        messages.push((record.t, record.message()?));
+    }
+    let (local, remote) = tokio::io::duplex(1 << 20);
+    let mut peer = FramedStream::from(remote, Config::get_any_listen_addr());
+    tokio::spawn(async move {
+        let start = time::Instant::now();
+        for (t, msg) in messages {
+            let at = start + Duration::from_millis(t);
+            loop {
+                tokio::select! {
+                    _ = time::sleep_until(at) => break,
+                    res = peer.next() => if res.is_none() {
+                        return;
+                    }
+                }
+            }
+            if peer.send(&msg).await.is_err() {
+                return;
+            }
+        }
+        log::info!("Replay done");
+        while peer.next().await.is_some() {}
+    });
+    Ok(FramedStream::from(local, Config::get_any_listen_addr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_msg(chr: u32) -> Message {
+        let mut key_event = KeyEvent::new();
+        key_event.set_chr(chr);
+        let mut msg = Message::new();
+        msg.set_key_event(key_event);
+        msg
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut msg = key_msg('a' as _);
+        redact(&mut msg);
+        assert!(msg.key_event().union.is_none());
+        let mut key_event = KeyEvent::new();
+        key_event.set_control_key(ControlKey::Return);
+        let mut msg = Message::new();
+        msg.set_key_event(key_event.clone());
+        redact(&mut msg);
+        assert_eq!(msg.key_event(), &key_event);
+
+        let mut msg = Message::new();
+        msg.set_login_request(LoginRequest {
+            password: vec![1u8; 32].into(),
+            my_id: "123".to_owned(),
+            tfa_code: "123456".to_owned(),
+            ..Default::default()
+        });
+        redact(&mut msg);
+        assert!(msg.login_request().password.is_empty());
+        assert!(msg.login_request().tfa_code.is_empty());
+        assert_eq!(msg.login_request().my_id, "123");
+    }
+
+    #[tokio::test]
+    async fn test_capture_and_replay() {
+        let path = std::env::temp_dir().join(format!("capture_{}.jsonl", std::process::id()));
+        for payloads in [false, true].iter().cloned() {
+            let header = Header {
+                id: "123456789".to_owned(),
+                ..Default::default()
+            };
+            let mut capture = Capture::create(&path, &header, payloads).unwrap();
+            capture.record(Direction::In, &key_msg('a' as _).write_to_bytes().unwrap());
+            capture.record(Direction::Out, &key_msg('b' as _).write_to_bytes().unwrap());
+            capture.record(Direction::In, &key_msg('c' as _).write_to_bytes().unwrap());
+            let mut login = Message::new();
+            login.set_login_request(LoginRequest {
+                password: vec![1u8; 32].into(),
+                my_id: "123".to_owned(),
+                tfa_code: "123456".to_owned(),
+                ..Default::default()
+            });
+            capture.record(Direction::Out, &login.write_to_bytes().unwrap());
+            capture.close();
+
+            let (header, mut records) = load(path.to_str().unwrap()).unwrap();
+            assert_eq!(header.id, "123456789");
+            assert_eq!(records.len(), 4);
+            assert_eq!(records[1].dir, Direction::Out);
+            assert_eq!(records[0].raw.is_empty(), !payloads);
+            let login = records.pop().unwrap().message().unwrap();
+            assert!(login.login_request().password.is_empty());
+            assert!(login.login_request().tfa_code.is_empty());
+            assert_eq!(login.login_request().my_id, "123");
+
+            let mut stream = replay(records, Direction::In).unwrap();
+            for chr in ['a', 'c'].iter().cloned() {
+                let bytes = stream.next_timeout(1_000).await.unwrap().unwrap();
+                let msg = Message::parse_from_bytes(&bytes).unwrap();
+                if payloads {
+                    assert_eq!(msg, key_msg(chr as _));
+                } else {
+                    assert!(msg.key_event().union.is_none());
+                }
+            }
+            // sending to a replay is fine, and nothing more is received
+            stream.send(&key_msg('d' as _)).await.unwrap();
+            assert!(stream.next_timeout(100).await.is_none());
+        }
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -1,9 +1,107 @@
-use std::cell::RefCell;
-use zstd::block::{Compressor, Decompressor};
+use crate::{bail, config::Config, message_proto::Capabilities, ResultType};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use zstd::{block::Compressor, dict, stream, zstd_safe};
 
 thread_local! {
     static COMPRESSOR: RefCell<Compressor> = RefCell::new(Compressor::new());
-    static DECOMPRESSOR: RefCell<Decompressor> = RefCell::new(Decompressor::new());
+}
+
+/// What a dictionary is trained on, named as its file in the "dictionaries"
+/// config directory, e.g. "clipboard.dict".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Clipboard,
+    FileDirectory,
+    FileBlock,
+}
+
+impl Kind {
+    const ALL: [Kind; 3] = [Kind::Clipboard, Kind::FileDirectory, Kind::FileBlock];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Clipboard => "clipboard",
+            Kind::FileDirectory => "dir",
+            Kind::FileBlock => "file",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Dictionaries {
+    by_id: HashMap<u32, Arc<Vec<u8>>>,
+    by_kind: HashMap<Kind, u32>,
+}
+
+impl Dictionaries {
+    fn load(dir: &Path) -> Self {
+        let mut res = Self::default();
+        for kind in Kind::ALL.iter() {
+            let path = dir.join(format!("{}.dict", kind.name()));
+            if let Ok(data) = std::fs::read(&path) {
+                match res.add(*kind, data) {
+                    Ok(id) => crate::log::info!("Loaded zstd dictionary {:?} as {}", path, id),
+                    Err(err) => crate::log::error!("Invalid zstd dictionary {:?}: {}", path, err),
+                }
+            }
+        }
+        res
+    }
+
+    fn add(&mut self, kind: Kind, data: Vec<u8>) -> ResultType<u32> {
+        // 0 is a raw content dictionary, which is not identified in frames
+        let id = zstd_safe::get_dict_id_from_dict(&data);
+        if id == 0 {
+            bail!("No dictionary id");
+        }
+        self.by_id.insert(id, Arc::new(data));
+        self.by_kind.insert(kind, id);
+        Ok(id)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DICTIONARIES: RwLock<Dictionaries> =
+        RwLock::new(Dictionaries::load(&Config::path("dictionaries")));
+}
+
+/// Use `data` for `kind`, in addition to the dictionaries of the config directory.
+pub fn add_dictionary(kind: Kind, data: Vec<u8>) -> ResultType<u32> {
+    DICTIONARIES.write().unwrap().add(kind, data)
+}
+
+/// Ids of all dictionaries, announced in [`Capabilities`].
+pub fn dictionary_ids() -> Vec<u32> {
+    DICTIONARIES.read().unwrap().by_id.keys().cloned().collect()
+}
+
+/// The dictionary for `kind` if the peer has it too.
+pub fn negotiate(kind: Kind, peer: &Capabilities) -> Option<u32> {
+    let id = *DICTIONARIES.read().unwrap().by_kind.get(&kind)?;
+    if peer.zstd_dictionaries.contains(&id) {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+fn get_dictionary(id: u32) -> ResultType<Arc<Vec<u8>>> {
+    match DICTIONARIES.read().unwrap().by_id.get(&id) {
+        Some(data) => Ok(data.clone()),
+        None => bail!("Unknown zstd dictionary {}", id),
+    }
+}
+
+/// Train a dictionary for [`add_dictionary`] on typical payloads, `max_size` is usually
+/// about 100KB.
+pub fn train(samples: &[Vec<u8>], max_size: usize) -> ResultType<Vec<u8>> {
+    Ok(dict::from_samples(samples, max_size)?)
 }
 
 /// The library supports regular compression levels from 1 up to ZSTD_maxCLevel(),
@@ -25,26 +123,123 @@ pub fn compress(data: &[u8], level: i32) -> Vec<u8> {
     out
 }
 
-pub fn decompress(data: &[u8]) -> Vec<u8> {
+/// [`compress`] with a dictionary from [`negotiate`], only the peer can decompress it.
+pub fn compress_with(data: &[u8], level: i32, dictionary: Option<u32>) -> Vec<u8> {
+    if dictionary.is_none() {
+        return compress(data, level);
+    }
     let mut out = Vec::new();
-    DECOMPRESSOR.with(|d| {
-        if let Ok(mut d) = d.try_borrow_mut() {
-            const MAX: usize = 1024 * 1024 * 64;
-            const MIN: usize = 1024 * 1024;
-            let mut n = 30 * data.len();
-            if n > MAX {
-                n = MAX;
-            }
-            if n < MIN {
-                n = MIN;
-            }
-            match d.decompress(data, n) {
-                Ok(res) => out = res,
-                Err(err) => {
-                    crate::log::debug!("Failed to decompress: {}", err);
-                }
+    if let Err(err) = compress_stream(data, &mut out, level, dictionary) {
+        crate::log::debug!("Failed to compress: {}", err);
+        out.clear();
+    }
+    out
+}
+
+/// Compress all of `reader` into one frame, which is written to `writer` as it goes.
+pub fn compress_stream(
+    mut reader: impl Read,
+    writer: impl Write,
+    level: i32,
+    dictionary: Option<u32>,
+) -> ResultType<()> {
+    let mut encoder = match dictionary {
+        Some(id) => stream::Encoder::with_dictionary(writer, level, &get_dictionary(id)?)?,
+        None => stream::Encoder::new(writer, level)?,
+    };
+    std::io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Decompress the frames of `reader` into `writer` as they come, returning the size,
+/// which fails beyond `limit` if any. The dictionary a frame was compressed with is
+/// found by its id.
+pub fn decompress_stream(
+    reader: impl Read,
+    mut writer: impl Write,
+    limit: Option<u64>,
+) -> ResultType<u64> {
+    let mut reader = BufReader::new(reader);
+    let id = zstd_safe::get_dict_id_from_frame(reader.fill_buf()?);
+    let dictionary = if id == 0 {
+        Default::default()
+    } else {
+        get_dictionary(id)?
+    };
+    let mut decoder = stream::read::Decoder::with_dictionary(reader, &dictionary)?;
+    match limit {
+        Some(limit) => {
+            let n = std::io::copy(&mut (&mut decoder).take(limit + 1), &mut writer)?;
+            if n > limit {
+                bail!("Decompressed size exceeds {} bytes", limit);
             }
+            Ok(n)
         }
-    });
+        None => Ok(std::io::copy(&mut decoder, &mut writer)?),
+    }
+}
+
+/// Decompress all of `data`, whatever its size, empty if it is not valid.
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    if let Err(err) = decompress_stream(data, &mut out, None) {
+        crate::log::error!("Failed to decompress: {}", err);
+        out.clear();
+    }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(i: usize) -> Vec<u8> {
+        format!(
+            "{{\"name\":\"report-{}.docx\",\"size\":{},\"modified\":16{:08},\"hidden\":false}}",
+            i,
+            i * 977,
+            i * 31
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_decompress_not_capped() {
+        // at least 1MB, or 30 times the compressed size used to be the limit
+        let data = vec![b'a'; 4 * 1024 * 1024];
+        let compressed = compress(&data, 3);
+        assert!(compressed.len() * 30 < 1024 * 1024);
+        assert_eq!(decompress(&compressed), data);
+
+        let mut out = Vec::new();
+        assert!(decompress_stream(&compressed[..], &mut out, Some(1024)).is_err());
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let samples: Vec<Vec<u8>> = (0..2000).map(listing).collect();
+        let trained = train(&samples, 4 * 1024).unwrap();
+        let id = add_dictionary(Kind::FileDirectory, trained).unwrap();
+        assert!(dictionary_ids().contains(&id));
+
+        let mut peer = Capabilities::new();
+        assert_eq!(negotiate(Kind::FileDirectory, &peer), None);
+        peer.zstd_dictionaries.push(id);
+        assert_eq!(negotiate(Kind::FileDirectory, &peer), Some(id));
+
+        let data = listing(1234);
+        let plain = compress_with(&data, 3, None);
+        let with_dictionary = compress_with(&data, 3, Some(id));
+        assert!(with_dictionary.len() < plain.len());
+        assert_eq!(decompress(&with_dictionary), data);
+        assert_eq!(decompress(&plain), data);
+
+        let mut streamed = Vec::new();
+        compress_stream(&data[..], &mut streamed, 3, Some(id)).unwrap();
+        let mut out = Vec::new();
+        let n = decompress_stream(&streamed[..], &mut out, None).unwrap();
+        assert_eq!(n as usize, data.len());
+        assert_eq!(out, data);
+    }
+}
@@ -11,16 +11,22 @@ use anyhow::Result;
 use directories_next::ProjectDirs;
 use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
-use sodiumoxide::crypto::sign;
+use sodiumoxide::crypto::{hash::sha256, sign};
 
 use crate::{
-    log,
+    bail, log, options,
     password_security::{
-        decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
-        encrypt_vec_or_original,
+        self, decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
     },
+    secret_store,
 };
 
+pub mod address_book;
+pub mod authorized_keys;
+pub mod bundle;
+pub mod migration;
+pub mod peer_acl;
+
 pub const RENDEZVOUS_TIMEOUT: u64 = 12_000;
 pub const CONNECT_TIMEOUT: u64 = 18_000;
 pub const READ_TIMEOUT: u64 = 30_000;
@@ -54,6 +60,7 @@ lazy_static::lazy_static! {
 lazy_static::lazy_static! {
     pub static ref APP_DIR: Arc<RwLock<String>> = Default::default();
     pub static ref APP_HOME_DIR: Arc<RwLock<String>> = Default::default();
+    static ref PROFILE: Arc<RwLock<String>> = Default::default();
 }
 
 // #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -83,6 +90,7 @@ pub const RELAY_PORT: i32 = 21117;
 pub enum NetworkType {
     Direct,
     ProxySocks,
+    ProxyHttp,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
@@ -92,9 +100,26 @@ pub struct Config {
     #[serde(default)]
     enc_id: String, // store
     #[serde(default)]
-    password: String,
-    #[serde(default)]
     salt: String,
+    /// Base64 of sha256(password + salt), for clients without the password key, only
+    /// kept if "allow-legacy-password" is set or given by a config bundle.
+    #[serde(default)]
+    password_hash: String,
+    /// Parameters of the password key, see [`password_security::derive_password_key`].
+    #[serde(default)]
+    password_kdf: String,
+    /// Base64 of the password key, the permanent password itself not being stored.
+    #[serde(default)]
+    password_key: String,
+    /// Base32 secret of the two-factor authentication, none if empty.
+    #[serde(default)]
+    totp_secret: String,
+    /// Step of the last accepted two-factor code, so that a code is used once only.
+    #[serde(default)]
+    totp_last_step: u64,
+    /// A signed license has been used, unsigned ones being refused from then on.
+    #[serde(default)]
+    license_signed: bool,
     #[serde(default)]
     key_pair: (Vec<u8>, Vec<u8>), // sk, pk
     #[serde(default)]
@@ -113,6 +138,39 @@ pub struct Socks5Server {
     pub password: String,
 }
 
+impl Socks5Server {
+    /// A proxy given as `http://host:port` is an HTTP CONNECT proxy,
+    /// `socks5://host:port` or a bare `host:port` is a SOCKS5 proxy.
+    pub fn is_http(&self) -> bool {
+        self.proxy.trim().to_lowercase().starts_with("http://")
+    }
+
+    /// Refuse the schemes of the proxies not supported, e.g. `https://` which needs TLS
+    /// to the proxy.
+    pub fn check(&self) -> ResultType<()> {
+        let proxy = self.proxy.trim().to_lowercase();
+        if let Some(i) = proxy.find("://") {
+            let scheme = &proxy[..i];
+            if scheme != "http" && scheme != "socks5" {
+                bail!(
+                    "Unsupported proxy scheme {}://, expecting http:// or socks5://",
+                    scheme
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The proxy address without the scheme.
+    pub fn proxy_addr(&self) -> &str {
+        let proxy = self.proxy.trim();
+        match proxy.find("://") {
+            Some(i) => proxy[i + 3..].trim_end_matches('/'),
+            None => proxy,
+        }
+    }
+}
+
 // more variable configs
 #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Config2 {
@@ -125,6 +183,9 @@ pub struct Config2 {
 
     #[serde(default)]
     socks: Option<Socks5Server>,
+    /// Offset of the default listening ports of a named profile, given when first used.
+    #[serde(default)]
+    port_offset: i32,
 
     // the other scalar value must before this
     #[serde(default)]
@@ -165,6 +226,13 @@ pub struct PeerConfig {
     pub enable_file_transfer: bool,
     #[serde(default)]
     pub show_quality_monitor: bool,
+    /// Base64 of the public key of the peer, pinned at the first connection, see
+    /// [`PeerConfig::check_key`].
+    #[serde(default)]
+    pub pinned_key: String,
+    /// A key differing from the pinned one, kept for review until accepted.
+    #[serde(default)]
+    pub pending_key: String,
 
     // the other scalar value must before this
     #[serde(default)]
@@ -173,6 +241,9 @@ pub struct PeerConfig {
     pub info: PeerInfoSerde,
     #[serde(default)]
     pub transfer: TransferSerde,
+    /// The stored password while the password store is locked, kept as is.
+    #[serde(skip)]
+    locked_password: Vec<u8>,
 }
 
 #[derive(Debug, PartialEq, Default, Serialize, Deserialize, Clone)]
@@ -268,6 +339,14 @@ impl Config2 {
 pub fn load_path<T: serde::Serialize + serde::de::DeserializeOwned + Default + std::fmt::Debug>(
     file: PathBuf,
 ) -> T {
+    if !file.exists() {
+        // created by us rather than confy, so that it has a version
+        let cfg = T::default();
+        if let Err(err) = store_path(file, &cfg) {
+            log::error!("Failed to store config: {}", err);
+        }
+        return cfg;
+    }
     let cfg = match confy::load_path(&file) {
         Ok(config) => config,
         Err(err) => {
@@ -278,9 +357,30 @@ pub fn load_path<T: serde::Serialize + serde::de::DeserializeOwned + Default + s
     cfg
 }
 
+/// Store `cfg`, marked with the [`migration::VERSION`] of this release.
 #[inline]
 pub fn store_path<T: serde::Serialize>(path: PathBuf, cfg: T) -> crate::ResultType<()> {
-    Ok(confy::store_path(path, cfg)?)
+    match toml::Value::try_from(cfg)? {
+        toml::Value::Table(mut value) => {
+            migration::stamp(&mut value);
+            Ok(confy::store_path(path, value)?)
+        }
+        value => Ok(confy::store_path(path, value)?),
+    }
+}
+
+/// Upgrade the config file at `path` if it was written by an older release.
+fn upgrade(path: &Path, kind: migration::Kind) {
+    match migration::upgrade_file(path, kind) {
+        Ok(Some(version)) => log::info!(
+            "Upgraded {} from version {}, backup in {}",
+            path.display(),
+            version,
+            migration::backup_path(path, version).display()
+        ),
+        Ok(None) => {}
+        Err(err) => log::error!("Failed to upgrade {}: {}", path.display(), err),
+    }
 }
 
 impl Config {
@@ -289,6 +389,13 @@ impl Config {
     ) -> T {
         let file = Self::file_(suffix);
         log::debug!("Configuration path: {}", file.display());
+        let kind = match suffix {
+            "" => migration::Kind::Main,
+            "2" => migration::Kind::Options,
+            "_local" => migration::Kind::Local,
+            _ => migration::Kind::Other,
+        };
+        upgrade(&file, kind);
         let cfg = load_path(file);
         if suffix.is_empty() {
             log::trace!("{:?}", cfg);
@@ -306,8 +413,17 @@ impl Config {
     fn load() -> Config {
         let mut config = Config::load_::<Config>("");
         let mut store = false;
-        let (password, _, store1) = decrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION);
-        config.password = password;
+        let (password_key, _, store1) =
+            decrypt_str_or_original(&config.password_key, PASSWORD_ENC_VERSION);
+        config.password_key = password_key;
+        store |= store1;
+        let (password_hash, _, store1) =
+            decrypt_str_or_original(&config.password_hash, PASSWORD_ENC_VERSION);
+        config.password_hash = password_hash;
+        store |= store1;
+        let (totp_secret, _, store1) =
+            decrypt_str_or_original(&config.totp_secret, PASSWORD_ENC_VERSION);
+        config.totp_secret = totp_secret;
         store |= store1;
         let mut id_valid = false;
         let (id, encrypted, store2) = decrypt_str_or_original(&config.enc_id, PASSWORD_ENC_VERSION);
@@ -349,7 +465,9 @@ impl Config {
 
     fn store(&self) {
         let mut config = self.clone();
-        config.password = encrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION);
+        config.password_key = encrypt_str_or_original(&config.password_key, PASSWORD_ENC_VERSION);
+        config.password_hash = encrypt_str_or_original(&config.password_hash, PASSWORD_ENC_VERSION);
+        config.totp_secret = encrypt_str_or_original(&config.totp_secret, PASSWORD_ENC_VERSION);
         config.enc_id = encrypt_str_or_original(&config.id, PASSWORD_ENC_VERSION);
         config.id = "".to_owned();
         Config::store_(&config, "");
@@ -381,11 +499,25 @@ impl Config {
     }
 
     pub fn path<P: AsRef<Path>>(p: P) -> PathBuf {
+        let mut path = Self::profile_dir(&PROFILE.read().unwrap());
+        path.push(p);
+        path
+    }
+
+    /// Config directory of `profile`, the default profile if empty.
+    fn profile_dir(profile: &str) -> PathBuf {
+        let mut path = Self::dir();
+        if !profile.is_empty() {
+            path.push(PROFILES);
+            path.push(profile);
+        }
+        path
+    }
+
+    fn dir() -> PathBuf {
         #[cfg(any(target_os = "android", target_os = "ios"))]
         {
-            let mut path: PathBuf = APP_DIR.read().unwrap().clone().into();
-            path.push(p);
-            return path;
+            return APP_DIR.read().unwrap().clone().into();
         }
         #[cfg(not(target_os = "macos"))]
         let org = "";
@@ -393,13 +525,107 @@ impl Config {
         let org = ORG.read().unwrap().clone();
         // /var/root for root
         if let Some(project) = ProjectDirs::from("", &org, &*APP_NAME.read().unwrap()) {
-            let mut path = patch(project.config_dir().to_path_buf());
-            path.push(p);
-            return path;
+            return patch(project.config_dir().to_path_buf());
         }
         return "".into();
     }
 
+    /// Use the config files, peers and ipc of the profile `name` in this process, which
+    /// must be done before any config is loaded. The default profile has no name.
+    pub fn set_profile(name: &str) -> Result<()> {
+        if !name.is_empty() && !Self::is_valid_profile(name) {
+            bail!(
+                "Invalid profile name {:?}, expecting up to 32 letters, digits, - or _",
+                name
+            );
+        }
+        *PROFILE.write().unwrap() = name.to_owned();
+        Ok(())
+    }
+
+    pub fn get_profile() -> String {
+        PROFILE.read().unwrap().clone()
+    }
+
+    pub fn is_valid_profile(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 32
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Named profiles having a config directory.
+    pub fn get_profiles() -> Vec<String> {
+        let mut profiles: Vec<String> = match Self::dir().join(PROFILES).read_dir() {
+            Ok(dirs) => dirs
+                .filter_map(|x| x.ok())
+                .filter(|x| x.path().is_dir())
+                .filter_map(|x| x.file_name().into_string().ok())
+                .filter(|x| Self::is_valid_profile(x))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        profiles.sort();
+        profiles
+    }
+
+    /// Offset of the default listening ports of the current profile, 0 for the default
+    /// profile, so that the servers of several profiles do not conflict. A named profile
+    /// is given the lowest multiple of 100 no other profile has when first used, and
+    /// keeps it whatever profiles are added or removed later.
+    pub fn get_profile_port_offset() -> i32 {
+        let profile = Self::get_profile();
+        if profile.is_empty() {
+            return 0;
+        }
+        let mut config = CONFIG2.write().unwrap();
+        if config.port_offset == 0 {
+            let used: Vec<i32> = Self::get_profiles()
+                .iter()
+                .filter(|x| **x != profile)
+                .map(|x| Self::stored_port_offset(x))
+                .collect();
+            let mut offset = 100;
+            while used.contains(&offset) {
+                offset += 100;
+            }
+            config.port_offset = offset;
+            config.store();
+        }
+        config.port_offset
+    }
+
+    /// The port offset in the options of another `profile`, 0 if not given yet.
+    fn stored_port_offset(profile: &str) -> i32 {
+        let name = format!("{}2", *APP_NAME.read().unwrap());
+        let file = Config::with_extension(Self::profile_dir(profile).join(name));
+        if !file.exists() {
+            return 0;
+        }
+        confy::load_path::<Config2>(&file)
+            .map(|x| x.port_offset)
+            .unwrap_or_default()
+    }
+
+    /// Profiles whose servers are run along with the one of the default profile, from
+    /// the option "profiles" of the default profile.
+    pub fn get_active_profiles() -> Vec<String> {
+        Self::get_option(PROFILES)
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| Self::is_valid_profile(x))
+            .map(|x| x.to_owned())
+            .collect()
+    }
+
+    pub fn set_active_profiles(profiles: &[String]) -> Result<()> {
+        if let Some(name) = profiles.iter().find(|x| !Self::is_valid_profile(x)) {
+            bail!("Invalid profile name {:?}", name);
+        }
+        Self::try_set_option(PROFILES.to_owned(), profiles.join(","))
+    }
+
     #[allow(unreachable_code)]
     pub fn log_path() -> PathBuf {
         #[cfg(target_os = "macos")]
@@ -431,8 +657,9 @@ impl Config {
             // where ServerName is either the name of a remote computer or a period, to specify the local computer.
             // https://docs.microsoft.com/en-us/windows/win32/ipc/pipe-names
             format!(
-                "\\\\.\\pipe\\{}\\query{}",
+                "\\\\.\\pipe\\{}\\query{}{}",
                 *APP_NAME.read().unwrap(),
+                Self::ipc_profile(),
                 postfix
             )
         }
@@ -446,11 +673,22 @@ impl Config {
             let mut path: PathBuf = format!("/tmp/{}", *APP_NAME.read().unwrap()).into();
             fs::create_dir(&path).ok();
             fs::set_permissions(&path, fs::Permissions::from_mode(0o0777)).ok();
-            path.push(format!("ipc{}", postfix));
+            path.push(format!("ipc{}{}", Self::ipc_profile(), postfix));
             path.to_str().unwrap_or("").to_owned()
         }
     }
 
+    /// Every profile has its own ipc, so that its server and the processes using it
+    /// work as if it was alone.
+    fn ipc_profile() -> String {
+        let profile = PROFILE.read().unwrap();
+        if profile.is_empty() {
+            "".to_owned()
+        } else {
+            format!("@{}", profile)
+        }
+    }
+
     pub fn icon_path() -> PathBuf {
         let mut path = Self::path("icons");
         if fs::create_dir_all(&path).is_err() {
@@ -675,13 +913,33 @@ impl Config {
         CONFIG2.read().unwrap().options.clone()
     }
 
-    pub fn set_options(v: HashMap<String, String>) {
+    /// Invalid values are not applied, the others are, and the error lists them, see
+    /// [`Config::set_option`].
+    pub fn set_options(mut v: HashMap<String, String>) -> Result<()> {
         let mut config = CONFIG2.write().unwrap();
-        if config.options == v {
-            return;
+        let mut errors = Vec::new();
+        for (k, x) in v.iter() {
+            if config.options.get(k) != Some(x) {
+                if let Err(err) = check_option(k, x) {
+                    errors.push((k.clone(), err));
+                }
+            }
         }
-        config.options = v;
-        config.store();
+        for (k, _) in errors.iter() {
+            match config.options.get(k) {
+                Some(old) => v.insert(k.clone(), old.clone()),
+                None => v.remove(k),
+            };
+        }
+        if config.options != v {
+            config.options = v;
+            config.store();
+        }
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(|(_, err)| err.to_string()).collect();
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(())
     }
 
     pub fn get_option(k: &str) -> String {
@@ -692,10 +950,14 @@ impl Config {
         }
     }
 
-    pub fn set_option(k: String, v: String) {
+    /// An invalid value of an option in [`options::OPTIONS`] is not applied but
+    /// returned as the error, unknown options are accepted, unlike with
+    /// [`Config::try_set_option`].
+    pub fn set_option(k: String, v: String) -> Result<()> {
         let mut config = CONFIG2.write().unwrap();
         let v2 = if v.is_empty() { None } else { Some(&v) };
         if v2 != config.options.get(&k) {
+            check_option(&k, &v)?;
             if v2.is_none() {
                 config.options.remove(&k);
             } else {
@@ -703,6 +965,13 @@ impl Config {
             }
             config.store();
         }
+        Ok(())
+    }
+
+    /// Set a known option, failing on unknown options and invalid values.
+    pub fn try_set_option(k: String, v: String) -> Result<()> {
+        options::validate(&k, &v)?;
+        Self::set_option(k, v)
     }
 
     pub fn update_id() {
@@ -714,17 +983,89 @@ impl Config {
         log::info!("id updated from {} to {}", id, new_id);
     }
 
-    pub fn set_permanent_password(password: &str) {
+    /// Set the permanent password, of which only its key is stored, and its hash for
+    /// older clients if "allow-legacy-password" is set. As the password itself is not
+    /// kept, enabling that option later needs the password to be set again.
+    pub fn set_permanent_password(password: &str) -> Result<()> {
+        let mut hash = bundle::PasswordHash::default();
+        if !password.is_empty() {
+            let kdf = password_security::new_password_kdf();
+            let key = password_security::derive_password_key(password, &kdf)?;
+            hash.kdf = kdf;
+            hash.key = base64::encode(key);
+            if options::get_bool("allow-legacy-password") {
+                hash.salt = Config::get_salt();
+                let data = [password.as_bytes(), hash.salt.as_bytes()].concat();
+                hash.hash = base64::encode(&sha256::hash(&data)[..]);
+            }
+        }
+        Config::set_permanent_password_hash(&hash);
+        Ok(())
+    }
+
+    pub fn has_permanent_password() -> bool {
+        let config = CONFIG.read().unwrap();
+        !config.password_key.is_empty() || !config.password_hash.is_empty()
+    }
+
+    /// Set the permanent password by its verifiers, the salt being kept if empty.
+    pub fn set_permanent_password_hash(hash: &bundle::PasswordHash) {
+        let mut config = CONFIG.write().unwrap();
+        if !hash.salt.is_empty() {
+            config.salt = hash.salt.clone();
+        }
+        config.password_hash = hash.hash.clone();
+        config.password_kdf = hash.kdf.clone();
+        config.password_key = hash.key.clone();
+        config.store();
+    }
+
+    pub fn get_permanent_password_hash() -> bundle::PasswordHash {
+        let config = CONFIG.read().unwrap();
+        bundle::PasswordHash {
+            salt: config.salt.clone(),
+            hash: config.password_hash.clone(),
+            kdf: config.password_kdf.clone(),
+            key: config.password_key.clone(),
+        }
+    }
+
+    pub fn set_totp_secret(secret: &str) {
         let mut config = CONFIG.write().unwrap();
-        if password == config.password {
+        if secret == config.totp_secret {
             return;
         }
-        config.password = password.into();
+        config.totp_secret = secret.into();
         config.store();
     }
 
-    pub fn get_permanent_password() -> String {
-        CONFIG.read().unwrap().password.clone()
+    pub fn get_totp_secret() -> String {
+        CONFIG.read().unwrap().totp_secret.clone()
+    }
+
+    /// Record `step` as the one of the last accepted two-factor code, failing if it is
+    /// not newer than the recorded one.
+    pub fn set_totp_last_step(step: u64) -> bool {
+        let mut config = CONFIG.write().unwrap();
+        if step <= config.totp_last_step {
+            return false;
+        }
+        config.totp_last_step = step;
+        config.store();
+        true
+    }
+
+    pub fn get_license_signed() -> bool {
+        CONFIG.read().unwrap().license_signed
+    }
+
+    pub fn set_license_signed() {
+        let mut config = CONFIG.write().unwrap();
+        if config.license_signed {
+            return;
+        }
+        config.license_signed = true;
+        config.store();
     }
 
     pub fn set_salt(salt: &str) {
@@ -761,6 +1102,7 @@ impl Config {
     pub fn get_network_type() -> NetworkType {
         match &CONFIG2.read().unwrap().socks {
             None => NetworkType::Direct,
+            Some(socks) if socks.is_http() => NetworkType::ProxyHttp,
             Some(_) => NetworkType::ProxySocks,
         }
     }
@@ -791,18 +1133,27 @@ impl Config {
 }
 
 const PEERS: &str = "peers";
+const PROFILES: &str = "profiles";
+
+/// `SHA256:` and the base64 of the hash of the base64 `key`, as shown for review.
+pub fn key_fingerprint(key: &str) -> String {
+    match base64::decode(key) {
+        Ok(key) if !key.is_empty() => format!(
+            "SHA256:{}",
+            base64::encode_config(&sha256::hash(&key)[..], base64::STANDARD_NO_PAD)
+        ),
+        _ => "none".to_owned(),
+    }
+}
 
 impl PeerConfig {
     pub fn load(id: &str) -> PeerConfig {
         let _ = CONFIG.read().unwrap(); // for lock
+        upgrade(&Self::path(id), migration::Kind::Peer);
         match confy::load_path(&Self::path(id)) {
             Ok(config) => {
                 let mut config: PeerConfig = config;
-                let mut store = false;
-                let (password, _, store2) =
-                    decrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
-                config.password = password;
-                store = store || store2;
+                let mut store = config.open_password(id);
                 config.options.get_mut("rdp_password").map(|v| {
                     let (password, _, store2) = decrypt_str_or_original(v, PASSWORD_ENC_VERSION);
                     *v = password;
@@ -828,7 +1179,15 @@ impl PeerConfig {
     pub fn store(&self, id: &str) {
         let _ = CONFIG.read().unwrap(); // for lock
         let mut config = self.clone();
-        config.password = encrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
+        config.password = match secret_store::seal(&self.password) {
+            Ok(v) => v,
+            Err(err) => {
+                if self.locked_password.is_empty() {
+                    log::error!("Password of {} not remembered: {}", id, err);
+                }
+                self.locked_password.clone()
+            }
+        };
         config
             .options
             .get_mut("rdp_password")
@@ -842,6 +1201,118 @@ impl PeerConfig {
         }
     }
 
+    /// Open the stored password, returning if it is to be sealed again. Passwords of
+    /// older releases are sealed as soon as the password store is unlocked.
+    fn open_password(&mut self, id: &str) -> bool {
+        if self.password.is_empty() {
+            return false;
+        }
+        if secret_store::is_sealed(&self.password) {
+            match secret_store::open(&self.password) {
+                Ok(password) => {
+                    self.password = password;
+                    return false;
+                }
+                Err(err) if err.is::<secret_store::Locked>() => {
+                    log::warn!("Password of {} not available: {}", id, err);
+                    self.locked_password = std::mem::take(&mut self.password);
+                    return false;
+                }
+                // a legacy password which happens to look sealed
+                Err(_) => {}
+            }
+        }
+        let (password, _, _) = decrypt_vec_or_original(&self.password, PASSWORD_ENC_VERSION);
+        if secret_store::is_locked() {
+            // keep it as is until it can be sealed
+            self.locked_password = std::mem::replace(&mut self.password, password);
+            false
+        } else {
+            self.password = password;
+            true
+        }
+    }
+
+    /// Whether a password is remembered, even if the password store is locked.
+    pub fn has_password(&self) -> bool {
+        !self.password.is_empty() || !self.locked_password.is_empty()
+    }
+
+    /// Whether a password is remembered but the password store is locked.
+    pub fn is_password_locked(&self) -> bool {
+        self.password.is_empty() && !self.locked_password.is_empty()
+    }
+
+    pub fn forget_password(&mut self) {
+        self.password.clear();
+        self.locked_password.clear();
+    }
+
+    /// Trust on first use of the key of the peer `id`, the base64 of its public key,
+    /// empty if it sent none. The first key is pinned, and a different one refused
+    /// unless "allow-peer-key-change", being kept until [`Self::accept_key`].
+    pub fn check_key(id: &str, key: &str) -> Result<()> {
+        let mut config = Self::load(id);
+        if config.pinned_key == key {
+            if !config.pending_key.is_empty() {
+                config.pending_key.clear();
+                config.store(id);
+            }
+            return Ok(());
+        }
+        if config.pinned_key.is_empty() {
+            log::info!("Pinned the key {} of {}", key_fingerprint(key), id);
+            config.pinned_key = key.to_owned();
+            config.store(id);
+            return Ok(());
+        }
+        let msg = if key.is_empty() {
+            format!(
+                "Peer {} sent no key, its pinned key being {}.",
+                id,
+                key_fingerprint(&config.pinned_key)
+            )
+        } else {
+            config.pending_key = key.to_owned();
+            config.store(id);
+            format!(
+                "The key of peer {} changed from {} to {}. Accept the new key only if you know why it changed, e.g. a reinstall.",
+                id,
+                key_fingerprint(&config.pinned_key),
+                key_fingerprint(key)
+            )
+        };
+        log::error!("{}", msg);
+        if options::get_bool("allow-peer-key-change") {
+            return Ok(());
+        }
+        bail!(msg);
+    }
+
+    /// Pin the key [`Self::check_key`] refused last for `id`.
+    pub fn accept_key(id: &str) -> Result<()> {
+        let mut config = Self::load(id);
+        if config.pending_key.is_empty() {
+            bail!("No new key of {} to accept", id);
+        }
+        log::info!(
+            "Accepted the key {} of {}",
+            key_fingerprint(&config.pending_key),
+            id
+        );
+        config.pinned_key = std::mem::take(&mut config.pending_key);
+        config.store(id);
+        Ok(())
+    }
+
+    /// Forget the pinned key of `id`, the next one being pinned.
+    pub fn forget_key(id: &str) {
+        let mut config = Self::load(id);
+        config.pinned_key.clear();
+        config.pending_key.clear();
+        config.store(id);
+    }
+
     pub fn remove(id: &str) {
         fs::remove_file(&Self::path(id)).ok();
     }
@@ -969,6 +1440,18 @@ impl LocalConfig {
     }
 }
 
+/// Fail if `v` of `k` does not match its [`options::Spec`], unknown options are
+/// accepted with a warning.
+fn check_option(k: &str, v: &str) -> Result<()> {
+    match options::find(k) {
+        Some(spec) => spec.validate(v),
+        None => {
+            log::warn!("Unknown option {}", k);
+            Ok(())
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct DiscoveryPeer {
     #[serde(default)]
@@ -1069,4 +1552,17 @@ mod tests {
         let res = toml::to_string_pretty(&cfg);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_profile() {
+        assert!(Config::is_valid_profile("work_2-b"));
+        assert!(!Config::is_valid_profile(""));
+        assert!(!Config::is_valid_profile("../work"));
+        assert!(!Config::is_valid_profile(&"a".repeat(33)));
+        assert_eq!(Config::profile_dir(""), Config::dir());
+        assert_eq!(
+            Config::profile_dir("work"),
+            Config::dir().join(PROFILES).join("work")
+        );
+    }
 }
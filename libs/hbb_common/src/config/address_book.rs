@@ -0,0 +1,315 @@
+//! Local address book: aliases, tags, groups and notes of peers, including peers never
+//! connected to, which the peer store drops.
+//!
+//! It is stored next to the peers as `address_book.toml`, and imported or exported as
+//! JSON, the list of [`Entry`], or CSV with the columns of [`CSV_HEADER`], tags and
+//! groups being separated by `;`.
+use super::{load_path, store_path, Config};
+use crate::{bail, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeSet, path::PathBuf};
+
+pub const CSV_HEADER: &str = "id,alias,tags,groups,note";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    #[serde(default)]
+    pub alias: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub note: String,
+}
+
+impl Entry {
+    fn matches(&self, filter: &Filter) -> bool {
+        let text = filter.text.to_lowercase();
+        (text.is_empty()
+            || [&self.id, &self.alias, &self.note]
+                .iter()
+                .any(|x| x.to_lowercase().contains(&text))
+            || self.tags.iter().any(|x| x.to_lowercase() == text))
+            && filter.tags.iter().all(|x| self.tags.contains(x))
+            && (filter.group.is_empty() || self.groups.contains(&filter.group))
+    }
+
+    fn normalize(&mut self) {
+        self.id = self.id.trim().to_owned();
+        for list in [&mut self.tags, &mut self.groups].iter_mut() {
+            let set: BTreeSet<String> = list
+                .iter()
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect();
+            **list = set.into_iter().collect();
+        }
+    }
+}
+
+/// What [`AddressBook::search`] returns, empty fields matching everything.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Filter {
+    /// Part of the id, alias or note, or a whole tag, case insensitive.
+    #[serde(default)]
+    pub text: String,
+    /// Tags all required.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub group: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressBook {
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+}
+
+impl AddressBook {
+    fn path() -> PathBuf {
+        Config::with_extension(Config::path("address_book"))
+    }
+
+    pub fn load() -> Self {
+        load_path(Self::path())
+    }
+
+    pub fn store(&self) {
+        if let Err(err) = store_path(Self::path(), self) {
+            log::error!("Failed to store address book: {}", err);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Entry> {
+        self.entries.iter().find(|x| x.id == id)
+    }
+
+    /// Add `entry`, or replace the one with its id.
+    pub fn set(&mut self, mut entry: Entry) -> ResultType<()> {
+        entry.normalize();
+        if entry.id.is_empty() {
+            bail!("Empty id");
+        }
+        match self.entries.iter_mut().find(|x| x.id == entry.id) {
+            Some(x) => *x = entry,
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let n = self.entries.len();
+        self.entries.retain(|x| x.id != id);
+        n != self.entries.len()
+    }
+
+    pub fn search(&self, filter: &Filter) -> Vec<Entry> {
+        let mut res: Vec<Entry> = self
+            .entries
+            .iter()
+            .filter(|x| x.matches(filter))
+            .cloned()
+            .collect();
+        res.sort_by(|a, b| {
+            let a = if a.alias.is_empty() { &a.id } else { &a.alias };
+            let b = if b.alias.is_empty() { &b.id } else { &b.alias };
+            a.to_lowercase().cmp(&b.to_lowercase())
+        });
+        res
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        let set: BTreeSet<&String> = self.entries.iter().flat_map(|x| x.tags.iter()).collect();
+        set.into_iter().cloned().collect()
+    }
+
+    pub fn groups(&self) -> Vec<String> {
+        let set: BTreeSet<&String> = self.entries.iter().flat_map(|x| x.groups.iter()).collect();
+        set.into_iter().cloned().collect()
+    }
+
+    /// Add or replace the entries of `other`, returning how many.
+    pub fn merge(&mut self, other: Vec<Entry>) -> ResultType<usize> {
+        let n = other.len();
+        for entry in other {
+            self.set(entry)?;
+        }
+        Ok(n)
+    }
+
+    pub fn to_json(&self) -> ResultType<String> {
+        Ok(serde_json::to_string_pretty(&self.entries)?)
+    }
+
+    pub fn from_json(data: &str) -> ResultType<Vec<Entry>> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut res = CSV_HEADER.to_owned() + "\n";
+        for x in self.entries.iter() {
+            let fields = [
+                x.id.clone(),
+                x.alias.clone(),
+                x.tags.join(";"),
+                x.groups.join(";"),
+                x.note.clone(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|x| csv_field(x)).collect();
+            res += &fields.join(",");
+            res += "\n";
+        }
+        res
+    }
+
+    pub fn from_csv(data: &str) -> ResultType<Vec<Entry>> {
+        let mut rows = parse_csv(data)?.into_iter();
+        match rows.next() {
+            Some(header) if header.join(",") == CSV_HEADER => {}
+            _ => bail!("Expecting the header {}", CSV_HEADER),
+        }
+        let split = |x: &str| -> Vec<String> { x.split(';').map(|x| x.to_owned()).collect() };
+        let mut res = Vec::new();
+        for (i, row) in rows.enumerate() {
+            if row.len() == 1 && row[0].is_empty() {
+                continue;
+            }
+            if row.len() != 5 {
+                bail!("Expecting 5 fields in row {}", i + 2);
+            }
+            res.push(Entry {
+                id: row[0].clone(),
+                alias: row[1].clone(),
+                tags: split(&row[2]),
+                groups: split(&row[3]),
+                note: row[4].clone(),
+            });
+        }
+        Ok(res)
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Rows of RFC 4180 CSV, quoted fields may have commas, quotes and newlines.
+fn parse_csv(data: &str) -> ResultType<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(c);
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("Unterminated quoted field");
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> AddressBook {
+        let mut book = AddressBook::default();
+        book.set(Entry {
+            id: "123456789".to_owned(),
+            alias: "Front desk".to_owned(),
+            tags: vec![
+                "windows".to_owned(),
+                "lobby".to_owned(),
+                "windows".to_owned(),
+            ],
+            groups: vec!["Site A".to_owned()],
+            note: "Ask for \"Sam\", ext. 12,\nweekdays only".to_owned(),
+        })
+        .unwrap();
+        book.set(Entry {
+            id: " 987654321 ".to_owned(),
+            tags: vec!["linux".to_owned()],
+            groups: vec!["Site B".to_owned()],
+            ..Default::default()
+        })
+        .unwrap();
+        book
+    }
+
+    #[test]
+    fn test_search() {
+        let book = book();
+        assert_eq!(book.get("987654321").unwrap().tags, vec!["linux"]);
+        assert_eq!(
+            book.get("123456789").unwrap().tags,
+            vec!["lobby", "windows"]
+        );
+        assert_eq!(book.tags(), vec!["linux", "lobby", "windows"]);
+        assert_eq!(book.groups(), vec!["Site A", "Site B"]);
+        let search = |text: &str, tags: &[&str], group: &str| -> Vec<String> {
+            let filter = Filter {
+                text: text.to_owned(),
+                tags: tags.iter().map(|x| x.to_string()).collect(),
+                group: group.to_owned(),
+            };
+            book.search(&filter).into_iter().map(|x| x.id).collect()
+        };
+        assert_eq!(search("", &[], ""), vec!["987654321", "123456789"]);
+        assert_eq!(search("desk", &[], ""), vec!["123456789"]);
+        assert_eq!(search("SAM", &[], ""), vec!["123456789"]);
+        assert_eq!(search("", &["windows", "lobby"], ""), vec!["123456789"]);
+        assert!(search("", &["windows", "linux"], "").is_empty());
+        assert_eq!(search("", &[], "Site B"), vec!["987654321"]);
+        assert!(book.clone().remove("987654321"));
+        assert!(book.clone().set(Entry::default()).is_err());
+    }
+
+    #[test]
+    fn test_import_export() {
+        let book = book();
+        let entries = AddressBook::from_json(&book.to_json().unwrap()).unwrap();
+        assert_eq!(entries, book.entries);
+        let mut entries = AddressBook::from_csv(&book.to_csv()).unwrap();
+        for x in entries.iter_mut() {
+            x.normalize();
+        }
+        assert_eq!(entries, book.entries);
+        assert!(AddressBook::from_csv("id,name\n1,2\n").is_err());
+        assert!(AddressBook::from_csv(&format!("{}\n1,2\n", CSV_HEADER)).is_err());
+        assert!(AddressBook::from_csv(&format!("{}\n1,\"2\n", CSV_HEADER)).is_err());
+    }
+}
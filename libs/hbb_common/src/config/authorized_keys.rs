@@ -0,0 +1,185 @@
+//! Client keys a host trusts instead of a password.
+//!
+//! A client signs the [`Hash`](crate::message_proto::Hash) challenge of the host and the
+//! id it connects to with the key pair of [`Config::get_key_pair`], see [`sign`]. If
+//! the public key is one of the [`AuthorizedKeys`] and not revoked, the host lets it in
+//! with the permissions of the key, each also needing its "enable-*" option.
+//!
+//! They are stored as `authorized_keys.toml` next to the config of the service.
+use super::{load_path, store_path, Config};
+use crate::{bail, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use std::{convert::TryFrom, path::PathBuf};
+
+/// What a key may be granted, "tunnel" covering RDP too.
+pub const PERMISSIONS: &[&str] = &[
+    "keyboard",
+    "clipboard",
+    "audio",
+    "file",
+    "restart",
+    "recording",
+    "tunnel",
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedKey {
+    /// Base64 of the public key of the client.
+    pub key: String,
+    #[serde(default)]
+    pub name: String,
+    /// The granted ones of [`PERMISSIONS`].
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// When it was added, for the listing.
+    #[serde(default)]
+    pub added: String,
+    /// Kept so that it is still listed, but refused.
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+impl AuthorizedKey {
+    /// A key granted all [`PERMISSIONS`].
+    pub fn new(key: &str, name: &str) -> Self {
+        Self {
+            key: key.trim().to_owned(),
+            name: name.to_owned(),
+            permissions: PERMISSIONS.iter().map(|x| x.to_string()).collect(),
+            added: crate::chrono::Local::now().to_rfc3339(),
+            revoked: false,
+        }
+    }
+
+    pub fn has_permission(&self, name: &str) -> bool {
+        self.permissions.iter().any(|x| x == name)
+    }
+
+    fn check(&self) -> ResultType<()> {
+        match base64::decode(&self.key) {
+            Ok(key) if key.len() == sign::PUBLICKEYBYTES => {}
+            _ => bail!("Invalid public key {}", self.key),
+        }
+        if let Some(x) = self
+            .permissions
+            .iter()
+            .find(|x| !PERMISSIONS.contains(&x.as_str()))
+        {
+            bail!(
+                "Unknown permission {}, expecting one of {:?}",
+                x,
+                PERMISSIONS
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedKeys {
+    #[serde(default)]
+    pub keys: Vec<AuthorizedKey>,
+}
+
+impl AuthorizedKeys {
+    fn path() -> PathBuf {
+        Config::with_extension(Config::path("authorized_keys"))
+    }
+
+    pub fn load() -> Self {
+        load_path(Self::path())
+    }
+
+    /// Store `keys` after checking them, a key being listed once only.
+    pub fn store(keys: Vec<AuthorizedKey>) -> ResultType<()> {
+        let mut res = Self::default();
+        for key in keys {
+            key.check()?;
+            if res.keys.iter().any(|x| x.key == key.key) {
+                bail!("Key {} listed twice", key.key);
+            }
+            res.keys.push(key);
+        }
+        store_path(Self::path(), res)
+    }
+
+    /// The key which made `signature` of the login, if authorized and not revoked.
+    pub fn find(
+        &self,
+        key: &[u8],
+        signature: &[u8],
+        challenge: &str,
+        id: &str,
+    ) -> Option<&AuthorizedKey> {
+        if key.is_empty() || !verify(key, signature, challenge, id) {
+            return None;
+        }
+        let key = base64::encode(key);
+        let res = self.keys.iter().find(|x| x.key == key)?;
+        if res.revoked {
+            log::warn!("Refused the revoked key {} ({})", res.key, res.name);
+            return None;
+        }
+        Some(res)
+    }
+}
+
+fn signed_data(challenge: &str, id: &str) -> Vec<u8> {
+    [challenge.as_bytes(), b":", id.as_bytes()].concat()
+}
+
+/// The signature of the login to `id` with the secret key `sk`, empty if invalid.
+pub fn sign(sk: &[u8], challenge: &str, id: &str) -> Vec<u8> {
+    match sign::SecretKey::from_slice(sk) {
+        Some(sk) if !challenge.is_empty() => sign::sign_detached(&signed_data(challenge, id), &sk)
+            .to_bytes()
+            .to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn verify(pk: &[u8], signature: &[u8], challenge: &str, id: &str) -> bool {
+    let pk = match sign::PublicKey::from_slice(pk) {
+        Some(pk) => pk,
+        None => return false,
+    };
+    match sign::Signature::try_from(signature) {
+        Ok(signature) => sign::verify_detached(&signature, &signed_data(challenge, id), &pk),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let (pk, sk) = sign::gen_keypair();
+        let (other, _) = sign::gen_keypair();
+        let mut keys = AuthorizedKeys {
+            keys: vec![AuthorizedKey::new(&base64::encode(&pk[..]), "laptop")],
+        };
+        let signature = sign(&sk[..], "challenge", "123456789");
+        let find = |keys: &AuthorizedKeys, pk: &[u8], challenge: &str, id: &str| {
+            keys.find(pk, &signature, challenge, id)
+                .map(|x| x.name.clone())
+        };
+        assert_eq!(
+            find(&keys, &pk[..], "challenge", "123456789"),
+            Some("laptop".to_owned())
+        );
+        assert_eq!(find(&keys, &pk[..], "other", "123456789"), None);
+        assert_eq!(find(&keys, &pk[..], "challenge", "987654321"), None);
+        assert_eq!(find(&keys, &other[..], "challenge", "123456789"), None);
+        assert!(sign(&sk[..], "", "123456789").is_empty());
+        keys.keys[0].revoked = true;
+        assert_eq!(find(&keys, &pk[..], "challenge", "123456789"), None);
+
+        assert!(keys.keys[0].check().is_ok());
+        keys.keys[0].permissions.push("admin".to_owned());
+        assert!(keys.keys[0].check().is_err());
+        assert!(AuthorizedKey::new("AAAA", "").check().is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Signed configuration bundles, for deploying the same settings to many machines.
+//!
+//! A [`Bundle`] is signed with the secret key of an admin, see [`gen_keypair`], and only
+//! applied if its signature is valid for one of the public keys of the option
+//! "admin-keys". The file is the JSON of [`Signed`], whose payload is the JSON of the
+//! bundle, so that the signed bytes are exactly the ones verified.
+use super::Config;
+use crate::{anyhow::anyhow, bail, log, options, password_security, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use std::{collections::HashMap, convert::TryFrom};
+
+pub const VERSION: u32 = 1;
+pub const ADMIN_KEYS: &str = "admin-keys";
+
+/// The permanent password by its verifiers, which is all a host needs.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordHash {
+    #[serde(default)]
+    pub salt: String,
+    /// Base64 of sha256(password + salt), for older clients, may be empty.
+    #[serde(default)]
+    pub hash: String,
+    /// Parameters of the password key, see
+    /// [`password_security::derive_password_key`].
+    #[serde(default)]
+    pub kdf: String,
+    /// Base64 of the password key, may be empty for a hash of an older release.
+    #[serde(default)]
+    pub key: String,
+}
+
+impl PasswordHash {
+    fn check(&self) -> ResultType<()> {
+        if self.hash.is_empty() && self.key.is_empty() {
+            bail!("Empty password hash");
+        }
+        if !self.hash.is_empty()
+            && (self.salt.is_empty() || base64::decode(&self.hash)?.len() != 32)
+        {
+            bail!("Invalid password hash");
+        }
+        if !self.key.is_empty() {
+            password_security::check_password_kdf(&self.kdf)?;
+            if base64::decode(&self.key)?.len() != 32 {
+                bail!("Invalid password key");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    /// When it was exported, for the logs.
+    #[serde(default)]
+    pub created: String,
+    /// Option "custom-rendezvous-server", not changed if empty.
+    #[serde(default)]
+    pub rendezvous_server: String,
+    /// Option "key", not changed if empty.
+    #[serde(default)]
+    pub key: String,
+    /// "access-mode" and the "enable-*" options.
+    #[serde(default)]
+    pub permissions: HashMap<String, String>,
+    /// Any other option, an empty value resetting it.
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+    #[serde(default)]
+    pub password: Option<PasswordHash>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Signed {
+    pub payload: String,
+    /// Base64 of the detached signature of the payload.
+    pub signature: String,
+    /// Base64 of the public key it was signed with.
+    pub key: String,
+}
+
+/// A new admin key pair, as base64 of the (public, secret) keys.
+pub fn gen_keypair() -> (String, String) {
+    let (pk, sk) = sign::gen_keypair();
+    (base64::encode(&pk[..]), base64::encode(&sk[..]))
+}
+
+pub fn is_permission(name: &str) -> bool {
+    name == "access-mode" || name.starts_with("enable-")
+}
+
+impl Bundle {
+    /// A bundle of `options`, e.g. of [`Config::get_options`], and of the hash of the
+    /// permanent password if it is to be deployed too.
+    pub fn new(mut options: HashMap<String, String>, password: Option<PasswordHash>) -> Self {
+        let rendezvous_server = options
+            .remove("custom-rendezvous-server")
+            .unwrap_or_default();
+        let key = options.remove("key").unwrap_or_default();
+        let permissions = options
+            .iter()
+            .filter(|(k, _)| is_permission(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<HashMap<_, _>>();
+        options.retain(|k, _| !is_permission(k));
+        // local to this machine
+        options.remove("local-ip-addr");
+        Self {
+            version: VERSION,
+            created: crate::chrono::Local::now().to_rfc3339(),
+            rendezvous_server,
+            key,
+            permissions,
+            options,
+            password,
+        }
+    }
+
+    fn check(&self) -> ResultType<()> {
+        if self.version > VERSION {
+            bail!("Bundle version {} is newer than {}", self.version, VERSION);
+        }
+        if let Some(name) = self.permissions.keys().find(|x| !is_permission(x)) {
+            bail!("{} is not a permission", name);
+        }
+        for (k, v) in self.all_options() {
+            if options::find(&k).is_some() {
+                options::validate(&k, &v)?;
+            } else {
+                log::warn!("Unknown option {} in bundle", k);
+            }
+        }
+        if let Some(password) = self.password.as_ref() {
+            password.check()?;
+        }
+        Ok(())
+    }
+
+    /// The options to set, "custom-rendezvous-server" and "key" included.
+    pub fn all_options(&self) -> HashMap<String, String> {
+        let mut res = self.options.clone();
+        res.extend(self.permissions.clone());
+        if !self.rendezvous_server.is_empty() {
+            res.insert(
+                "custom-rendezvous-server".to_owned(),
+                self.rendezvous_server.clone(),
+            );
+        }
+        if !self.key.is_empty() {
+            res.insert("key".to_owned(), self.key.clone());
+        }
+        res
+    }
+
+    /// Sign with `sk`, the base64 of a secret key of [`gen_keypair`].
+    pub fn sign(&self, sk: &str) -> ResultType<String> {
+        let sk = sign::SecretKey::from_slice(&base64::decode(sk.trim())?)
+            .ok_or(anyhow!("Invalid secret key"))?;
+        let pk = sk.public_key();
+        let payload = serde_json::to_string(self)?;
+        let signature = sign::sign_detached(payload.as_bytes(), &sk);
+        let signed = Signed {
+            payload,
+            signature: base64::encode(signature.to_bytes()),
+            key: base64::encode(&pk[..]),
+        };
+        Ok(serde_json::to_string_pretty(&signed)?)
+    }
+
+    /// Parse a signed bundle, failing unless it is signed by one of `trusted` and
+    /// valid.
+    pub fn verify(data: &str, trusted: &[String]) -> ResultType<Self> {
+        let signed: Signed = serde_json::from_str(data)?;
+        if !trusted.iter().any(|x| x.trim() == signed.key) {
+            if trusted.is_empty() {
+                bail!("No trusted admin key, see the option {}", ADMIN_KEYS);
+            }
+            bail!("Bundle signed by an untrusted key {}", signed.key);
+        }
+        let pk = sign::PublicKey::from_slice(&base64::decode(&signed.key)?)
+            .ok_or(anyhow!("Invalid public key"))?;
+        let signature = sign::Signature::try_from(&base64::decode(&signed.signature)?[..])
+            .map_err(|_| anyhow!("Invalid signature"))?;
+        if !sign::verify_detached(&signature, signed.payload.as_bytes(), &pk) {
+            bail!("Bad signature, the bundle was modified");
+        }
+        let bundle: Self = serde_json::from_str(&signed.payload)?;
+        bundle.check()?;
+        Ok(bundle)
+    }
+
+    /// Verify `data` with the admin keys of this machine and apply it.
+    pub fn import(data: &str) -> ResultType<Self> {
+        let bundle = Self::verify(data, &trusted_keys())?;
+        bundle.apply();
+        Ok(bundle)
+    }
+
+    fn apply(&self) {
+        let mut options = Config::get_options();
+        for (k, v) in self.all_options() {
+            if v.is_empty() {
+                options.remove(&k);
+            } else {
+                options.insert(k, v);
+            }
+        }
+        // validated by the import
+        if let Err(err) = Config::set_options(options) {
+            log::error!("{}", err);
+        }
+        if let Some(password) = self.password.as_ref() {
+            Config::set_permanent_password_hash(password);
+        }
+        log::info!("Config bundle of {} applied", self.created);
+    }
+}
+
+pub fn trusted_keys() -> Vec<String> {
+    Config::get_option(ADMIN_KEYS)
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle() -> Bundle {
+        let mut bundle = Bundle {
+            version: VERSION,
+            rendezvous_server: "rs.example.com".to_owned(),
+            key: "OeVuKk5nlHiXp+APNn0Y3pC1Iwpwn44JGqrQCsWqmBw=".to_owned(),
+            password: Some(PasswordHash {
+                salt: "n4qwvs".to_owned(),
+                hash: base64::encode([1u8; 32]),
+                kdf: "m=19456,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA==".to_owned(),
+                key: base64::encode([2u8; 32]),
+            }),
+            ..Default::default()
+        };
+        bundle
+            .permissions
+            .insert("enable-keyboard".to_owned(), "N".to_owned());
+        bundle
+            .options
+            .insert("direct-access-port".to_owned(), "21200".to_owned());
+        bundle
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (pk, sk) = gen_keypair();
+        let (other, _) = gen_keypair();
+        let data = bundle().sign(&sk).unwrap();
+        assert_eq!(
+            Bundle::verify(&data, &[other.clone(), pk.clone()]).unwrap(),
+            bundle()
+        );
+        assert!(Bundle::verify(&data, &[other]).is_err());
+        assert!(Bundle::verify(&data, &[]).is_err());
+        assert_eq!(
+            bundle().all_options()["custom-rendezvous-server"],
+            "rs.example.com"
+        );
+
+        let mut signed: Signed = serde_json::from_str(&data).unwrap();
+        signed.payload = signed.payload.replace("21200", "21201");
+        let tampered = serde_json::to_string(&signed).unwrap();
+        assert!(Bundle::verify(&tampered, &[pk.clone()]).is_err());
+
+        let mut invalid = bundle();
+        invalid
+            .options
+            .insert("direct-access-port".to_owned(), "0".to_owned());
+        assert!(Bundle::verify(&invalid.sign(&sk).unwrap(), &[pk.clone()]).is_err());
+        let mut invalid = bundle();
+        invalid
+            .permissions
+            .insert("whitelist".to_owned(), "".to_owned());
+        assert!(Bundle::verify(&invalid.sign(&sk).unwrap(), &[pk]).is_err());
+    }
+}
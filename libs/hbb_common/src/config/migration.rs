@@ -0,0 +1,370 @@
+//! Upgrade of config files written by older releases.
+//!
+//! Every config file stores the [`VERSION`] it was written with, files without one
+//! predate this and are version 0. A file of an older version goes through the
+//! [`MIGRATIONS`] after it, in order, on the TOML values before it is deserialized.
+//! If that changes anything, the original is kept next to it as `<file>.v<version>.bak`,
+//! unless a migration drops a secret, which removes the older backups too.
+use super::PASSWORD_ENC_VERSION;
+use crate::{
+    bail, log, options,
+    password_security::{
+        self, decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
+        encrypt_vec_or_original,
+    },
+    ResultType,
+};
+use sodiumoxide::crypto::hash::sha256;
+use std::path::{Path, PathBuf};
+use toml::{value::Table, Value};
+
+pub const VERSION_KEY: &str = "version";
+/// Version of the config files written by this release.
+pub const VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// `<app>.toml`, [`super::Config`].
+    Main,
+    /// `<app>2.toml`, [`super::Config2`].
+    Options,
+    /// `<app>_local.toml`, [`super::LocalConfig`].
+    Local,
+    /// `peers/<id>.toml`, [`super::PeerConfig`].
+    Peer,
+    /// Files without migrations, e.g. caches.
+    Other,
+}
+
+pub struct Migration {
+    /// Version of the files after it.
+    pub version: u32,
+    pub kind: Kind,
+    pub description: &'static str,
+    /// No copy of the file before it is to be kept.
+    pub drops_secret: bool,
+    run: fn(&mut Table),
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        kind: Kind::Main,
+        description: "Encrypt the permanent password stored in plain text",
+        drops_secret: false,
+        run: encrypt_main_password,
+    },
+    Migration {
+        version: 1,
+        kind: Kind::Options,
+        description: "Encrypt the proxy password stored in plain text",
+        drops_secret: false,
+        run: encrypt_socks_password,
+    },
+    Migration {
+        version: 1,
+        kind: Kind::Peer,
+        description: "Encrypt the peer passwords stored in plain text",
+        drops_secret: false,
+        run: encrypt_peer_passwords,
+    },
+    Migration {
+        version: 2,
+        kind: Kind::Options,
+        description: "Drop option values the option schema rejects",
+        drops_secret: false,
+        run: drop_invalid_options,
+    },
+    Migration {
+        version: 3,
+        kind: Kind::Main,
+        description: "Replace the permanent password by its argon2 key",
+        drops_secret: true,
+        run: hash_main_password,
+    },
+];
+
+pub fn version(value: &Table) -> u32 {
+    value
+        .get(VERSION_KEY)
+        .and_then(|x| x.as_integer())
+        .unwrap_or(0) as _
+}
+
+/// Mark `value` as written by this release.
+pub fn stamp(value: &mut Table) {
+    value.insert(VERSION_KEY.to_owned(), Value::Integer(VERSION as _));
+}
+
+/// Upgrade `value` of `kind` to [`VERSION`], returning the version it had.
+pub fn migrate(kind: Kind, value: &mut Table) -> ResultType<u32> {
+    let from = version(value);
+    if from > VERSION {
+        bail!("Config version {} is newer than {}", from, VERSION);
+    }
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|x| x.kind == kind && x.version > from)
+    {
+        log::info!(
+            "Migrate {:?} config to version {}: {}",
+            kind,
+            migration.version,
+            migration.description
+        );
+        (migration.run)(value);
+    }
+    stamp(value);
+    Ok(from)
+}
+
+fn read(path: &Path) -> ResultType<Table> {
+    match toml::from_str(&std::fs::read_to_string(path)?)? {
+        Value::Table(table) => Ok(table),
+        _ => bail!("Not a table"),
+    }
+}
+
+/// Read `path` and upgrade it in memory only, e.g. for an imported file.
+pub fn load<T: serde::de::DeserializeOwned>(path: &Path, kind: Kind) -> ResultType<T> {
+    let mut value = read(path)?;
+    migrate(kind, &mut value)?;
+    Ok(Value::Table(value).try_into()?)
+}
+
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+/// Upgrade the file at `path` if it is older than [`VERSION`], returning the version
+/// it had. The original is backed up if any migration changed it, a missing file is
+/// left to be created with the defaults.
+pub fn upgrade_file(path: &Path, kind: Kind) -> ResultType<Option<u32>> {
+    if kind == Kind::Other || !path.exists() {
+        return Ok(None);
+    }
+    let mut value = read(path)?;
+    if version(&value) >= VERSION {
+        return Ok(None);
+    }
+    let mut original = value.clone();
+    let from = migrate(kind, &mut value)?;
+    original.insert(VERSION_KEY.to_owned(), Value::Integer(VERSION as _));
+    if MIGRATIONS
+        .iter()
+        .any(|x| x.kind == kind && x.version > from && x.drops_secret)
+    {
+        for version in 0..VERSION {
+            std::fs::remove_file(backup_path(path, version)).ok();
+        }
+    } else if original != value {
+        std::fs::copy(path, backup_path(path, from))?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, toml::to_string(&value)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(Some(from))
+}
+
+fn encrypt_str(table: &mut Table, key: &str) {
+    if let Some(Value::String(s)) = table.get_mut(key) {
+        if !s.is_empty() && !decrypt_str_or_original(s, PASSWORD_ENC_VERSION).1 {
+            *s = encrypt_str_or_original(s, PASSWORD_ENC_VERSION);
+        }
+    }
+}
+
+fn encrypt_main_password(value: &mut Table) {
+    encrypt_str(value, "password");
+}
+
+fn encrypt_socks_password(value: &mut Table) {
+    if let Some(Value::Table(socks)) = value.get_mut("socks") {
+        encrypt_str(socks, "password");
+    }
+}
+
+fn encrypt_peer_passwords(value: &mut Table) {
+    if let Some(Value::Array(password)) = value.get_mut("password") {
+        let v: Vec<u8> = password
+            .iter()
+            .filter_map(|x| x.as_integer())
+            .map(|x| x as u8)
+            .collect();
+        if !v.is_empty() && !decrypt_vec_or_original(&v, PASSWORD_ENC_VERSION).1 {
+            *password = encrypt_vec_or_original(&v, PASSWORD_ENC_VERSION)
+                .into_iter()
+                .map(|x| Value::Integer(x as _))
+                .collect();
+        }
+    }
+    if let Some(Value::Table(options)) = value.get_mut("options") {
+        encrypt_str(options, "rdp_password");
+        encrypt_str(options, "os-password");
+    }
+}
+
+/// Keep the hash of the password for older clients only if "allow-legacy-password" is
+/// set, the password having to be set again for them if it is enabled later.
+fn hash_main_password(value: &mut Table) {
+    let password = match value.remove("password") {
+        Some(Value::String(s)) => decrypt_str_or_original(&s, PASSWORD_ENC_VERSION).0,
+        _ => return,
+    };
+    if password.is_empty() {
+        return;
+    }
+    let kdf = password_security::new_password_kdf();
+    let key = match password_security::derive_password_key(&password, &kdf) {
+        Ok(key) => key,
+        Err(err) => {
+            log::error!("Failed to hash the permanent password, dropped: {}", err);
+            return;
+        }
+    };
+    value.insert("password_kdf".to_owned(), Value::String(kdf));
+    value.insert(
+        "password_key".to_owned(),
+        Value::String(encrypt_str_or_original(
+            &base64::encode(key),
+            PASSWORD_ENC_VERSION,
+        )),
+    );
+    let salt = value
+        .get("salt")
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_owned();
+    if options::get_bool("allow-legacy-password") && !salt.is_empty() {
+        let hash = sha256::hash(&[password.as_bytes(), salt.as_bytes()].concat());
+        value.insert(
+            "password_hash".to_owned(),
+            Value::String(encrypt_str_or_original(
+                &base64::encode(&hash[..]),
+                PASSWORD_ENC_VERSION,
+            )),
+        );
+    }
+}
+
+fn drop_invalid_options(value: &mut Table) {
+    if let Some(Value::Table(values)) = value.get_mut("options") {
+        let invalid: Vec<String> = values
+            .iter()
+            .filter_map(|(k, v)| {
+                let err = options::find(k)?.validate(v.as_str()?).err()?;
+                log::warn!("{}, dropped", err);
+                Some(k.clone())
+            })
+            .collect();
+        for k in invalid {
+            values.remove(&k);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Config, Config2, LocalConfig, PeerConfig};
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        [
+            env!("CARGO_MANIFEST_DIR"),
+            "tests/fixtures/config/1.1.9",
+            name,
+        ]
+        .iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_migrations_ordered() {
+        assert!(MIGRATIONS.windows(2).all(|x| x[0].version <= x[1].version));
+        assert_eq!(MIGRATIONS.last().map(|x| x.version), Some(VERSION));
+    }
+
+    #[test]
+    fn test_main() {
+        let config: Config = load(&fixture("RustDesk.toml"), Kind::Main).unwrap();
+        assert_eq!(config.id, "123456789");
+        // only the key of the password is left
+        let (key, encrypted, _) =
+            decrypt_str_or_original(&config.password_key, PASSWORD_ENC_VERSION);
+        assert!(encrypted);
+        assert_eq!(
+            base64::decode(key).unwrap(),
+            password_security::derive_password_key("plainpass", &config.password_kdf).unwrap()
+        );
+        let mut value = read(&fixture("RustDesk.toml")).unwrap();
+        migrate(Kind::Main, &mut value).unwrap();
+        assert!(value.get("password").is_none());
+        assert_eq!(config.salt, "n4qwvs");
+        assert!(config.key_confirmed);
+    }
+
+    #[test]
+    fn test_options() {
+        let config: Config2 = load(&fixture("RustDesk2.toml"), Kind::Options).unwrap();
+        assert_eq!(config.nat_type, 1);
+        let socks = config.socks.unwrap();
+        assert_eq!(socks.username, "user");
+        assert_eq!(
+            decrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION).0,
+            "secret"
+        );
+        assert_eq!(config.options["custom-rendezvous-server"], "rs.example.com");
+        assert_eq!(config.options["enable-keyboard"], "N");
+        // invalid values are dropped, unknown options are kept
+        assert!(config.options.get("direct-access-port").is_none());
+        assert!(config.options.get("stop-service").is_none());
+        assert_eq!(config.options["remote-cursor-color"], "red");
+    }
+
+    #[test]
+    fn test_peer_and_local() {
+        let config: PeerConfig = load(&fixture("peer.toml"), Kind::Peer).unwrap();
+        let (password, encrypted, _) =
+            decrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
+        assert!(encrypted);
+        assert_eq!(password, vec![1, 2, 3, 4]);
+        for key in ["os-password", "rdp_password"].iter() {
+            assert!(decrypt_str_or_original(&config.options[*key], PASSWORD_ENC_VERSION).1);
+        }
+        assert_eq!(config.options["alias"], "office");
+        assert_eq!(config.info.platform, "Windows");
+        assert_eq!(config.view_style, "original");
+
+        let config: LocalConfig = load(&fixture("RustDesk_local.toml"), Kind::Local).unwrap();
+        assert_eq!(config.remote_id, "987654321");
+        assert_eq!(config.fav, vec!["987654321".to_owned()]);
+    }
+
+    #[test]
+    fn test_upgrade_file() {
+        let dir = std::env::temp_dir().join(format!("config_migration_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("RustDesk2.toml");
+        std::fs::copy(fixture("RustDesk2.toml"), &path).unwrap();
+
+        assert_eq!(upgrade_file(&path, Kind::Options).unwrap(), Some(0));
+        let backup = backup_path(&path, 0);
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            std::fs::read_to_string(fixture("RustDesk2.toml")).unwrap()
+        );
+        let value = read(&path).unwrap();
+        assert_eq!(version(&value), VERSION);
+        let config: Config2 = Value::Table(value).try_into().unwrap();
+        assert!(config.options.get("stop-service").is_none());
+        // done once only
+        assert_eq!(upgrade_file(&path, Kind::Options).unwrap(), None);
+
+        let mut value = read(&path).unwrap();
+        value.insert(VERSION_KEY.to_owned(), Value::Integer(VERSION as i64 + 1));
+        assert!(migrate(Kind::Options, &mut value).is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
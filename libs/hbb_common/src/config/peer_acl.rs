@@ -0,0 +1,178 @@
+//! Access rules of the peers connecting to this machine.
+//!
+//! A [`Rule`] is for a peer id, a client key (see [`super::authorized_keys`]) or any
+//! peer. It may refuse the connection, force it to view only, replace the default
+//! permissions, i.e. the "enable-*" options, which the connection manager can still
+//! toggle during the session, or have its own access windows. The rule of the key wins
+//! over the one of the id, which wins over the one of any peer.
+//!
+//! They are stored as `peer_acl.toml` next to the config of the service.
+use super::{authorized_keys::PERMISSIONS, load_path, store_path, Config};
+use crate::{bail, schedule::Schedule, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use std::path::PathBuf;
+
+/// The peer of the rule applying to any peer.
+pub const ANY: &str = "*";
+/// Prefix of the peer of a rule for a client key.
+pub const KEY_PREFIX: &str = "key:";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// A peer id, [`KEY_PREFIX`] followed by the base64 of a client key, or [`ANY`].
+    pub peer: String,
+    #[serde(default)]
+    pub deny: bool,
+    /// No keyboard, mouse nor clipboard, whatever the permissions.
+    #[serde(default)]
+    pub view_only: bool,
+    /// The granted ones of [`PERMISSIONS`], the options deciding if `None`.
+    #[serde(default)]
+    pub permissions: Option<Vec<String>>,
+    /// Access windows of [`crate::schedule`], the option "access-windows" applying if
+    /// empty.
+    #[serde(default)]
+    pub windows: String,
+    #[serde(default)]
+    pub timezone: String,
+}
+
+impl Rule {
+    /// Whether `name` of [`PERMISSIONS`] is granted, `default` being the option.
+    pub fn permission(&self, name: &str, default: bool) -> bool {
+        if self.view_only && (name == "keyboard" || name == "clipboard") {
+            return false;
+        }
+        match self.permissions.as_ref() {
+            Some(x) => x.iter().any(|x| x == name),
+            None => default,
+        }
+    }
+
+    fn check(&self) -> ResultType<()> {
+        if self.peer.trim().is_empty() {
+            bail!("Empty peer");
+        }
+        if let Some(key) = self.peer.strip_prefix(KEY_PREFIX) {
+            if base64::decode(key).map(|x| x.len()).ok() != Some(sign::PUBLICKEYBYTES) {
+                bail!("Invalid public key {}", key);
+            }
+        }
+        if let Some(x) = self
+            .permissions
+            .iter()
+            .flatten()
+            .find(|x| !PERMISSIONS.contains(&x.as_str()))
+        {
+            bail!(
+                "Unknown permission {}, expecting one of {:?}",
+                x,
+                PERMISSIONS
+            );
+        }
+        self.schedule()?;
+        Ok(())
+    }
+
+    /// The access windows of the peer, `None` if the ones of the options apply.
+    pub fn schedule(&self) -> ResultType<Option<Schedule>> {
+        if self.windows.trim().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Schedule::parse(&self.windows, &self.timezone)?))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerAcl {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl PeerAcl {
+    fn path() -> PathBuf {
+        Config::with_extension(Config::path("peer_acl"))
+    }
+
+    pub fn load() -> Self {
+        load_path(Self::path())
+    }
+
+    /// Store `rules` after checking them, a peer having one rule only.
+    pub fn store(rules: Vec<Rule>) -> ResultType<()> {
+        let mut res = Self::default();
+        for mut rule in rules {
+            rule.peer = rule.peer.trim().to_owned();
+            rule.check()?;
+            if res.rules.iter().any(|x| x.peer == rule.peer) {
+                bail!("Peer {} listed twice", rule.peer);
+            }
+            res.rules.push(rule);
+        }
+        store_path(Self::path(), res)
+    }
+
+    /// The rule of the peer `id`, `key` being the base64 of its client key if it proved
+    /// having it.
+    pub fn find(&self, id: &str, key: Option<&str>) -> Option<&Rule> {
+        let find = |peer: &str| self.rules.iter().find(|x| x.peer == peer);
+        key.and_then(|key| find(&format!("{}{}", KEY_PREFIX, key)))
+            .or_else(|| find(id))
+            .or_else(|| find(ANY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let key = base64::encode([1u8; 32]);
+        let acl = PeerAcl {
+            rules: vec![
+                Rule {
+                    peer: ANY.to_owned(),
+                    deny: true,
+                    ..Default::default()
+                },
+                Rule {
+                    peer: "123456789".to_owned(),
+                    view_only: true,
+                    ..Default::default()
+                },
+                Rule {
+                    peer: format!("{}{}", KEY_PREFIX, key),
+                    permissions: Some(vec!["keyboard".to_owned(), "file".to_owned()]),
+                    ..Default::default()
+                },
+            ],
+        };
+        assert!(acl.find("987654321", None).unwrap().deny);
+        let rule = acl.find("123456789", None).unwrap();
+        assert!(!rule.deny);
+        assert!(!rule.permission("keyboard", true));
+        assert!(rule.permission("audio", true));
+        assert!(!rule.permission("audio", false));
+        let rule = acl.find("123456789", Some(&key)).unwrap();
+        assert!(rule.permission("keyboard", false));
+        assert!(!rule.permission("audio", true));
+        assert!(PeerAcl::default().find("123456789", None).is_none());
+
+        assert!(acl.rules.iter().all(|x| x.check().is_ok()));
+        let mut rule = acl.rules[2].clone();
+        rule.permissions = Some(vec!["admin".to_owned()]);
+        assert!(rule.check().is_err());
+        rule.peer = format!("{}AAAA", KEY_PREFIX);
+        rule.permissions = None;
+        assert!(rule.check().is_err());
+        let mut rule = acl.rules[1].clone();
+        rule.windows = "mon-fri 08:00-18:00".to_owned();
+        assert!(rule.schedule().unwrap().is_some());
+        rule.timezone = "Europe/Paris".to_owned();
+        assert!(rule.check().is_ok());
+        rule.timezone = "Europe/Nowhere".to_owned();
+        assert!(rule.check().is_err());
+    }
+}
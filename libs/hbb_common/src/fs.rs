@@ -4,12 +4,16 @@ use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
-use tokio::{fs::File, io::*};
+use sodiumoxide::crypto::hash::sha256;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::*,
+};
 
 use crate::{bail, get_version_number, message_proto::*, ResultType, Stream};
 // https://doc.rust-lang.org/std/os/windows/fs/trait.MetadataExt.html
 use crate::{
-    compress::{compress, decompress},
+    compress::{compress_with, decompress},
     config::{Config, COMPRESS_LEVEL},
 };
 
@@ -198,6 +202,20 @@ pub fn can_enable_overwrite_detection(version: i64) -> bool {
     version >= get_version_number("1.1.10")
 }
 
+/// Size of the blocks read, all but the last of a file being full.
+const BLOCK_SIZE: usize = 128 * 1024;
+/// Blocks hashed at most per read while checking a resumption.
+const RESUME_BLOCKS_PER_READ: u32 = 64;
+
+/// The blocks the receiver already has of the current file, see
+/// [`FileTransferSendConfirmRequest::checksum`].
+struct Resume {
+    blocks: u32,
+    checksum: Vec<u8>,
+    state: sha256::State,
+    hashed: u32,
+}
+
 #[derive(Default)]
 pub struct TransferJob {
     pub id: i32,
@@ -217,6 +235,10 @@ pub struct TransferJob {
     file_confirmed: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    dictionary: Option<u32>,
+    /// Of the next block read from the current file.
+    blk_id: u32,
+    resume: Option<Resume>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -355,6 +377,12 @@ impl TransferJob {
         self.file_num
     }
 
+    /// Compress the blocks read with the dictionary negotiated with the peer.
+    #[inline]
+    pub fn set_dictionary(&mut self, dictionary: Option<u32>) {
+        self.dictionary = dictionary;
+    }
+
     pub fn modify_time(&self) {
         let file_num = self.file_num as usize;
         if file_num < self.files.len() {
@@ -400,7 +428,12 @@ impl TransferJob {
                 std::fs::create_dir_all(p).ok();
             }
             let path = format!("{}.download", get_string(&path));
-            self.file = Some(File::create(&path).await?);
+            if block.blk_id > 0 {
+                self.file = Some(open_resumed(&path, block.blk_id).await?);
+                self.finished_size += block.blk_id as u64 * BLOCK_SIZE as u64;
+            } else {
+                self.file = Some(File::create(&path).await?);
+            }
         }
         let data = if let Some(data) = raw {
             data
@@ -441,6 +474,8 @@ impl TransferJob {
                     self.file = Some(file);
                     self.file_confirmed = false;
                     self.file_is_waiting = false;
+                    self.blk_id = 0;
+                    self.resume = None;
                 }
                 Err(err) => {
                     self.file_num += 1;
@@ -459,10 +494,13 @@ impl TransferJob {
                 return Ok(None);
             }
         }
-        const BUF_SIZE: usize = 128 * 1024;
-        let mut buf: Vec<u8> = Vec::with_capacity(BUF_SIZE);
+        if self.resume.is_some() {
+            self.check_resume().await?;
+            return Ok(None);
+        }
+        let mut buf: Vec<u8> = Vec::with_capacity(BLOCK_SIZE);
         unsafe {
-            buf.set_len(BUF_SIZE);
+            buf.set_len(BLOCK_SIZE);
         }
         let mut compressed = false;
         let mut offset: usize = 0;
@@ -477,22 +515,24 @@ impl TransferJob {
                 }
                 Ok(n) => {
                     offset += n;
-                    if n == 0 || offset == BUF_SIZE {
+                    if n == 0 || offset == BLOCK_SIZE {
                         break;
                     }
                 }
             }
         }
         unsafe { buf.set_len(offset) };
+        let blk_id = self.blk_id;
         if offset == 0 {
             self.file_num += 1;
             self.file = None;
             self.file_confirmed = false;
             self.file_is_waiting = false;
         } else {
+            self.blk_id += 1;
             self.finished_size += offset as u64;
             if !is_compressed_file(name) {
-                let tmp = compress(&buf, COMPRESS_LEVEL);
+                let tmp = compress_with(&buf, COMPRESS_LEVEL, self.dictionary);
                 if tmp.len() < buf.len() {
                     buf = tmp;
                     compressed = true;
@@ -505,10 +545,60 @@ impl TransferJob {
             file_num: file_num as _,
             data: buf.into(),
             compressed,
+            blk_id,
             ..Default::default()
         }))
     }
 
+    /// Hash the next blocks the receiver has, then continue after them if they are the
+    /// same, else from the start of the file.
+    async fn check_resume(&mut self) -> ResultType<()> {
+        let (file, resume) = match (self.file.as_mut(), self.resume.as_mut()) {
+            (Some(file), Some(resume)) => (file, resume),
+            _ => return Ok(()),
+        };
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut same = true;
+        for _ in 0..RESUME_BLOCKS_PER_READ {
+            if resume.hashed == resume.blocks {
+                break;
+            }
+            if file.read_exact(&mut buf).await.is_err() {
+                same = false;
+                break;
+            }
+            resume.state.update(&buf);
+            resume.hashed += 1;
+        }
+        if same && resume.hashed < resume.blocks {
+            return Ok(());
+        }
+        let resume = match self.resume.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let blocks = resume.blocks;
+        same = same && resume.state.finalize()[..] == resume.checksum[..];
+        if same {
+            self.blk_id = blocks;
+            self.finished_size += blocks as u64 * BLOCK_SIZE as u64;
+            log::info!(
+                "id: {}, file_num: {}, resumed from block {}",
+                self.id,
+                self.file_num,
+                blocks
+            );
+        } else {
+            file.seek(SeekFrom::Start(0)).await?;
+            log::info!(
+                "id: {}, file_num: {}, not resumed, the receiver has other data",
+                self.id,
+                self.file_num
+            );
+        }
+        Ok(())
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -581,7 +671,15 @@ impl TransferJob {
                         self.set_file_confirmed(true);
                     }
                 }
-                Some(file_transfer_send_confirm_request::Union::OffsetBlk(_offset)) => {
+                Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset)) => {
+                    if offset > 0 && !r.checksum.is_empty() {
+                        self.resume = Some(Resume {
+                            blocks: offset,
+                            checksum: r.checksum.to_vec(),
+                            state: sha256::State::new(),
+                            hashed: 0,
+                        });
+                    }
                     self.set_file_confirmed(true);
                 }
                 _ => {}
@@ -805,3 +903,85 @@ pub fn is_write_need_confirmation(
         Ok(DigestCheckResult::NoSuchFile)
     }
 }
+
+/// The confirmation to write `file_num` of the job `id` to `file_path`, which does not
+/// exist, resuming its partial download if any. Hashing a large partial download takes
+/// a while, so this is to be awaited off the loop of the session.
+pub async fn new_write_confirm(
+    id: i32,
+    file_num: i32,
+    file_path: &str,
+) -> FileTransferSendConfirmRequest {
+    let mut req = FileTransferSendConfirmRequest {
+        id,
+        file_num,
+        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+        ..Default::default()
+    };
+    if let Some((blocks, checksum)) = get_resume_point(file_path).await {
+        req.set_offset_blk(blocks);
+        req.checksum = checksum.into();
+    }
+    req
+}
+
+/// The full blocks of the partial download of `file_path` and their sha256, if any.
+async fn get_resume_point(file_path: &str) -> Option<(u32, Vec<u8>)> {
+    let path = format!("{}.download", file_path);
+    let res = tokio::task::spawn_blocking(move || -> ResultType<Option<(u32, Vec<u8>)>> {
+        use std::io::Read;
+        let mut file = std::fs::File::open(&path)?;
+        let blocks = (file.metadata()?.len() / BLOCK_SIZE as u64).min(u32::MAX as _) as u32;
+        if blocks == 0 {
+            return Ok(None);
+        }
+        let mut state = sha256::State::new();
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        for _ in 0..blocks {
+            file.read_exact(&mut buf)?;
+            state.update(&buf);
+        }
+        Ok(Some((blocks, state.finalize()[..].to_vec())))
+    })
+    .await;
+    match res {
+        Ok(Ok(x)) => x,
+        _ => None,
+    }
+}
+
+/// Open the partial download `path` to write after its first `blk_id` blocks.
+async fn open_resumed(path: &str, blk_id: u32) -> ResultType<File> {
+    let offset = blk_id as u64 * BLOCK_SIZE as u64;
+    let mut file = OpenOptions::new().write(true).open(path).await?;
+    if file.metadata().await?.len() < offset {
+        bail!("Failed to resume {}, shorter than {} blocks", path, blk_id);
+    }
+    file.set_len(offset).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume() {
+        let dir = std::env::temp_dir().join(format!("fs_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = get_string(&dir.join("a"));
+        let download = format!("{}.download", path);
+        assert!(get_resume_point(&path).await.is_none());
+        let data: Vec<u8> = (0..BLOCK_SIZE * 5 / 2).map(|x| x as u8).collect();
+        std::fs::write(&download, &data).unwrap();
+        let (blocks, checksum) = get_resume_point(&path).await.unwrap();
+        assert_eq!(blocks, 2);
+        assert_eq!(checksum, sha256::hash(&data[..BLOCK_SIZE * 2])[..].to_vec());
+        drop(open_resumed(&download, 2).await.unwrap());
+        let len = std::fs::metadata(&download).unwrap().len();
+        assert_eq!(len, BLOCK_SIZE as u64 * 2);
+        assert!(open_resumed(&download, 3).await.is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -22,12 +22,16 @@ pub mod udp;
 pub use env_logger;
 pub use log;
 pub mod bytes_codec;
+pub mod capability;
+pub mod capture;
 #[cfg(feature = "quic")]
 pub mod quic;
 pub use anyhow::{self, bail};
 pub use futures_util;
 pub mod config;
 pub mod fs;
+pub mod options;
+pub mod schedule;
 pub use lazy_static;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub use mac_address;
@@ -38,12 +42,13 @@ pub use tokio_socks;
 pub use tokio_socks::IntoTargetAddr;
 pub use tokio_socks::TargetAddr;
 pub mod password_security;
+pub mod secret_store;
+pub mod rekey;
+pub mod mux;
+pub mod websocket;
 pub use chrono;
 pub use directories_next;
 
-#[cfg(feature = "quic")]
-pub type Stream = quic::Connection;
-#[cfg(not(feature = "quic"))]
 pub type Stream = tcp::FramedStream;
 
 #[inline]
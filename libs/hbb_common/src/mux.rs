@@ -0,0 +1,327 @@
+//! Logical channels inside one authorized session.
+//!
+//! A channel is a byte stream wrapped in [`Channel`] messages of the session, each end
+//! gets a [`FramedStream`] of its own, so e.g. a file transfer can run the usual protocol
+//! next to the desktop view without a second connection and login.
+//!
+//! Flow control is per channel: a sender may have at most [`WINDOW`] bytes unconfirmed,
+//! the receiver returns credit once the data is in the buffer of the channel reader.
+//! A peer exceeding the window is a protocol violation and ends the session.
+use crate::{
+    bail,
+    config::Config,
+    message_proto::{channel, Channel, ChannelOpen, Message},
+    tcp::FramedStream,
+    ResultType,
+};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    sync::{mpsc, Semaphore},
+};
+
+pub const WINDOW: usize = 256 * 1024;
+const CHUNK: usize = 16 * 1024;
+
+struct ChannelState {
+    // send window, permits are taken when sending and returned by credit
+    credit: Arc<Semaphore>,
+    // received but not yet in the buffer of the reader
+    unconfirmed: Arc<AtomicUsize>,
+    tx_data: mpsc::UnboundedSender<Bytes>,
+}
+
+type Channels = Arc<Mutex<HashMap<u32, ChannelState>>>;
+
+/// Channel table of a session, cheap to clone. The outgoing messages are read from the
+/// receiver returned by [`Mux::new`] and sent on the session stream.
+#[derive(Clone)]
+pub struct Mux {
+    initiator: bool,
+    next_id: Arc<AtomicU32>,
+    channels: Channels,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl Mux {
+    /// `initiator` is the side of the client, so that both sides can open channels
+    /// without id conflicts.
+    pub fn new(initiator: bool) -> (Self, mpsc::UnboundedReceiver<Message>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mux = Self {
+            initiator,
+            next_id: Arc::new(AtomicU32::new(if initiator { 1 } else { 2 })),
+            channels: Default::default(),
+            tx,
+        };
+        (mux, rx)
+    }
+
+    #[inline]
+    pub fn ptr_eq(&self, other: &Mux) -> bool {
+        Arc::ptr_eq(&self.channels, &other.channels)
+    }
+
+    /// Open a channel, the peer closes it again if it does not accept `open`.
+    pub fn open(&self, open: ChannelOpen) -> FramedStream {
+        let id = self.next_id.fetch_add(2, Ordering::SeqCst);
+        let stream = self.add(id);
+        self.send(id, channel::Union::Open(open));
+        stream
+    }
+
+    pub fn close(&self, id: u32, reason: &str) {
+        if self.channels.lock().unwrap().remove(&id).is_some() {
+            self.send(id, channel::Union::Close(reason.to_owned()));
+        }
+    }
+
+    /// Handle a [`Channel`] message of the peer, a new channel is returned for
+    /// the caller to serve or [`Mux::close`].
+    pub fn handle(&self, ch: Channel) -> ResultType<Option<(u32, ChannelOpen, FramedStream)>> {
+        let id = ch.id;
+        match ch.union {
+            Some(channel::Union::Open(open)) => {
+                if (id % 2 == 1) != !self.initiator {
+                    bail!("Invalid channel id {}", id);
+                }
+                if self.channels.lock().unwrap().contains_key(&id) {
+                    bail!("Channel {} already open", id);
+                }
+                let stream = self.add(id);
+                return Ok(Some((id, open, stream)));
+            }
+            Some(channel::Union::Data(data)) => {
+                // data racing a local close is dropped
+                if let Some(state) = self.channels.lock().unwrap().get(&id) {
+                    let unconfirmed = state.unconfirmed.fetch_add(data.len(), Ordering::SeqCst);
+                    if unconfirmed + data.len() > WINDOW {
+                        bail!("Channel {} window exceeded", id);
+                    }
+                    state.tx_data.send(data).ok();
+                }
+            }
+            Some(channel::Union::Credit(n)) => {
+                if let Some(state) = self.channels.lock().unwrap().get(&id) {
+                    if state.credit.available_permits() + n as usize > WINDOW {
+                        bail!("Channel {} credit exceeded", id);
+                    }
+                    state.credit.add_permits(n as _);
+                }
+            }
+            Some(channel::Union::Close(reason)) => {
+                if let Some(state) = self.channels.lock().unwrap().remove(&id) {
+                    if !reason.is_empty() {
+                        log::info!("Channel {} closed by peer: {}", id, reason);
+                    }
+                    state.credit.close();
+                }
+            }
+            None => {}
+        }
+        Ok(None)
+    }
+
+    fn add(&self, id: u32) -> FramedStream {
+        let (local, remote) = tokio::io::duplex(WINDOW);
+        let (reader, writer) = tokio::io::split(remote);
+        let (tx_data, rx_data) = mpsc::unbounded_channel();
+        let credit = Arc::new(Semaphore::new(WINDOW));
+        let unconfirmed = Arc::new(AtomicUsize::new(0));
+        self.channels.lock().unwrap().insert(
+            id,
+            ChannelState {
+                credit: credit.clone(),
+                unconfirmed: unconfirmed.clone(),
+                tx_data,
+            },
+        );
+        tokio::spawn(forward(
+            id,
+            reader,
+            credit,
+            Arc::downgrade(&self.channels),
+            self.tx.clone(),
+        ));
+        tokio::spawn(deliver(id, writer, rx_data, unconfirmed, self.tx.clone()));
+        FramedStream::from(local, Config::get_any_listen_addr())
+    }
+
+    fn send(&self, id: u32, union: channel::Union) {
+        self.tx.send(new_msg(id, union)).ok();
+    }
+}
+
+fn new_msg(id: u32, union: channel::Union) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_channel(Channel {
+        id,
+        union: Some(union),
+        ..Default::default()
+    });
+    msg_out
+}
+
+/// Local writes to the channel, sent to the peer as far as the window allows.
+async fn forward(
+    id: u32,
+    mut reader: ReadHalf<DuplexStream>,
+    credit: Arc<Semaphore>,
+    channels: Weak<Mutex<HashMap<u32, ChannelState>>>,
+    tx: mpsc::UnboundedSender<Message>,
+) {
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(n) if n > 0 => n,
+            _ => break,
+        };
+        match credit.acquire_many(n as _).await {
+            Ok(permit) => permit.forget(),
+            // closed by the peer
+            Err(_) => return,
+        }
+        let data = Bytes::copy_from_slice(&buf[..n]);
+        if tx.send(new_msg(id, channel::Union::Data(data))).is_err() {
+            return;
+        }
+    }
+    if let Some(channels) = channels.upgrade() {
+        if channels.lock().unwrap().remove(&id).is_some() {
+            tx.send(new_msg(id, channel::Union::Close("".to_owned())))
+                .ok();
+        }
+    }
+}
+
+/// Data of the peer, credited once it is in the buffer of the local reader.
+async fn deliver(
+    id: u32,
+    mut writer: WriteHalf<DuplexStream>,
+    mut rx_data: mpsc::UnboundedReceiver<Bytes>,
+    unconfirmed: Arc<AtomicUsize>,
+    tx: mpsc::UnboundedSender<Message>,
+) {
+    while let Some(data) = rx_data.recv().await {
+        if writer.write_all(&data).await.is_err() {
+            break;
+        }
+        unconfirmed.fetch_sub(data.len(), Ordering::SeqCst);
+        tx.send(new_msg(id, channel::Union::Credit(data.len() as _)))
+            .ok();
+    }
+    // the channel is closed or the session ended, the local reader sees eof
+    writer.shutdown().await.ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_proto::{message, FileTransfer};
+
+    /// Hand the messages of one mux to the other, as the session loop does.
+    fn pipe(mut rx: mpsc::UnboundedReceiver<Message>, peer: Mux) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let Some(message::Union::Channel(ch)) = msg.union {
+                    peer.handle(ch).unwrap();
+                }
+            }
+        })
+    }
+
+    fn file_transfer_open() -> ChannelOpen {
+        let mut open = ChannelOpen::new();
+        open.set_file_transfer(FileTransfer {
+            dir: "/tmp".to_owned(),
+            ..Default::default()
+        });
+        open
+    }
+
+    #[tokio::test]
+    async fn test_channel_flow_control() {
+        let (client, mut rx_client) = Mux::new(true);
+        let (host, rx_host) = Mux::new(false);
+        let mut client_stream = client.open(file_transfer_open());
+
+        // the open message comes first
+        let (id, open, mut host_stream) = match rx_client.recv().await.unwrap().union {
+            Some(message::Union::Channel(ch)) => host.handle(ch).unwrap().unwrap(),
+            _ => panic!("expecting channel"),
+        };
+        assert_eq!(id, 1);
+        assert_eq!(open.file_transfer().dir, "/tmp");
+
+        // the client writes more than a window while nothing is credited
+        let payload = vec![7u8; 64 * 1024];
+        let writer = tokio::spawn(async move {
+            for _ in 0..12 {
+                client_stream
+                    .send_bytes(payload.clone().into())
+                    .await
+                    .unwrap();
+            }
+            client_stream
+        });
+        let mut sent = 0;
+        while let Ok(Some(msg)) = crate::timeout(500, rx_client.recv()).await {
+            if let Some(message::Union::Channel(ch)) = msg.union {
+                if let Some(channel::Union::Data(data)) = &ch.union {
+                    sent += data.len();
+                }
+                host.handle(ch).unwrap();
+            }
+        }
+        assert!(sent <= WINDOW);
+        assert!(!writer.is_finished());
+
+        // reading on the host returns credit, so the client can go on
+        let credits = pipe(rx_host, client.clone());
+        let forward = pipe(rx_client, host.clone());
+        for _ in 0..12 {
+            let bytes = host_stream.next_timeout(3_000).await.unwrap().unwrap();
+            assert_eq!(bytes.len(), 64 * 1024);
+        }
+        let mut client_stream = writer.await.unwrap();
+
+        // closing one end is seen as eof on the other
+        drop(host_stream);
+        assert!(client_stream.next_timeout(3_000).await.is_none());
+        credits.abort();
+        forward.abort();
+    }
+
+    #[tokio::test]
+    async fn test_channel_violations() {
+        let (host, _rx) = Mux::new(false);
+        let open = |id| Channel {
+            id,
+            union: Some(channel::Union::Open(file_transfer_open())),
+            ..Default::default()
+        };
+        // the client opens odd ids only
+        assert!(host.handle(open(2)).is_err());
+        assert!(host.handle(open(1)).unwrap().is_some());
+        assert!(host.handle(open(1)).is_err());
+        let data = Channel {
+            id: 1,
+            union: Some(channel::Union::Data(vec![0u8; WINDOW + 1].into())),
+            ..Default::default()
+        };
+        assert!(host.handle(data).is_err());
+        let credit = Channel {
+            id: 1,
+            union: Some(channel::Union::Credit(1)),
+            ..Default::default()
+        };
+        assert!(host.handle(credit).is_err());
+    }
+}
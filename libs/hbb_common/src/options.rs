@@ -0,0 +1,450 @@
+//! Schema of the options in `Config2.options`.
+//!
+//! Options are still stored as strings, an empty value meaning the default, but every
+//! known option is declared in [`OPTIONS`] with its type, so that
+//! [`crate::config::Config::set_option`] can reject invalid values and the CLI and IPC
+//! can list them. Options not declared here are kept, but reported as unknown.
+use crate::{
+    bail,
+    config::Config,
+    schedule::{self, Schedule},
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// "Y" or "N".
+    Bool,
+    Number {
+        min: i64,
+        max: i64,
+    },
+    Choice(&'static [&'static str]),
+    /// Comma separated ip addresses, "0.0.0.0" allowing all.
+    IpList,
+    /// Access windows of [`crate::schedule`].
+    Schedule,
+    /// Timezone of [`crate::schedule`].
+    TimeZone,
+    Text,
+}
+
+impl Kind {
+    pub fn describe(&self) -> String {
+        match self {
+            Kind::Bool => "Y|N".to_owned(),
+            Kind::Number { min, max } => format!("{}-{}", min, max),
+            Kind::Choice(values) => values.join("|"),
+            Kind::IpList => "ip,ip,...".to_owned(),
+            Kind::Schedule => "days hh:mm-hh:mm;...".to_owned(),
+            Kind::TimeZone => "UTC[+|-hh:mm]".to_owned(),
+            Kind::Text => "text".to_owned(),
+        }
+    }
+
+    fn check(&self, value: &str) -> bool {
+        match self {
+            Kind::Bool => value == "Y" || value == "N",
+            Kind::Number { min, max } => match value.parse::<i64>() {
+                Ok(v) => v >= *min && v <= *max,
+                Err(_) => false,
+            },
+            Kind::Choice(values) => values.contains(&value),
+            Kind::IpList => value
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .all(|x| x.parse::<IpAddr>().is_ok()),
+            Kind::Schedule => Schedule::parse(value, "").is_ok(),
+            Kind::TimeZone => schedule::parse_timezone(value).is_ok(),
+            Kind::Text => true,
+        }
+    }
+}
+
+pub struct Spec {
+    pub name: &'static str,
+    pub kind: Kind,
+    /// What an empty value means.
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+impl Spec {
+    pub fn validate(&self, value: &str) -> ResultType<()> {
+        if !value.is_empty() && !self.kind.check(value) {
+            bail!(
+                "Invalid value {:?} of option {}, expecting {}",
+                value,
+                self.name,
+                self.kind.describe()
+            );
+        }
+        Ok(())
+    }
+}
+
+const fn spec(
+    name: &'static str,
+    kind: Kind,
+    default: &'static str,
+    description: &'static str,
+) -> Spec {
+    Spec {
+        name,
+        kind,
+        default,
+        description,
+    }
+}
+
+pub const OPTIONS: &[Spec] = &[
+    spec(
+        "custom-rendezvous-server",
+        Kind::Text,
+        "",
+        "ID server, host[:port]",
+    ),
+    spec("relay-server", Kind::Text, "", "Relay server, host[:port]"),
+    spec("api-server", Kind::Text, "", "API server URL"),
+    spec("key", Kind::Text, "", "Public key of the ID server"),
+    spec(
+        "rendezvous-servers",
+        Kind::Text,
+        "",
+        "Comma separated public ID servers",
+    ),
+    spec("direct-server", Kind::Bool, "N", "Accept direct IP access"),
+    spec(
+        "direct-access-port",
+        Kind::Number { min: 1, max: 65535 },
+        "21118",
+        "Port of direct IP access",
+    ),
+    spec("enable-quic", Kind::Bool, "N", "Accept QUIC connections"),
+    spec("stop-service", Kind::Bool, "N", "Stop the service"),
+    spec(
+        "stop-rendezvous-service",
+        Kind::Bool,
+        "N",
+        "Stop registering to the ID server",
+    ),
+    spec(
+        "enable-lan-discovery",
+        Kind::Bool,
+        "Y",
+        "Answer LAN discovery",
+    ),
+    spec(
+        "access-mode",
+        Kind::Choice(&["full", "view", "deny"]),
+        "",
+        "Permissions of incoming sessions, empty for the enable-* options",
+    ),
+    spec(
+        "enable-keyboard",
+        Kind::Bool,
+        "Y",
+        "Allow keyboard and mouse input",
+    ),
+    spec("enable-clipboard", Kind::Bool, "Y", "Allow clipboard sync"),
+    spec(
+        "enable-file-transfer",
+        Kind::Bool,
+        "Y",
+        "Allow file transfer",
+    ),
+    spec("enable-audio", Kind::Bool, "Y", "Allow audio"),
+    spec("enable-tunnel", Kind::Bool, "Y", "Allow TCP tunneling"),
+    spec("enable-rdp", Kind::Bool, "Y", "Allow RDP forwarding"),
+    spec(
+        "enable-remote-restart",
+        Kind::Bool,
+        "Y",
+        "Allow remote restart",
+    ),
+    spec(
+        "enable-record-session",
+        Kind::Bool,
+        "Y",
+        "Allow the client to record",
+    ),
+    spec(
+        "allow-remote-config-modification",
+        Kind::Bool,
+        "N",
+        "Allow remote configuration",
+    ),
+    spec(
+        "allow-auto-record-incoming",
+        Kind::Bool,
+        "N",
+        "Record incoming sessions",
+    ),
+    spec(
+        "allow-always-relay",
+        Kind::Bool,
+        "N",
+        "Always connect via relay",
+    ),
+    spec(
+        "allow-legacy-password",
+        Kind::Bool,
+        "N",
+        "Also store a weaker hash of the permanent password for older clients, from the next time it is set",
+    ),
+    spec("allow-darktheme", Kind::Bool, "N", "Dark theme"),
+    spec("enable-hwcodec", Kind::Bool, "Y", "Use hardware codecs"),
+    spec("enable-abr", Kind::Bool, "Y", "Adaptive bitrate"),
+    spec(
+        "verification-method",
+        Kind::Choice(&[
+            "use-temporary-password",
+            "use-permanent-password",
+            "use-both-passwords",
+        ]),
+        "use-both-passwords",
+        "Passwords accepted",
+    ),
+    spec(
+        "temporary-password-length",
+        Kind::Choice(&["6", "8", "10"]),
+        "6",
+        "Length of the temporary password",
+    ),
+    spec(
+        "whitelist",
+        Kind::IpList,
+        "",
+        "Ip addresses allowed to connect",
+    ),
+    spec(
+        "audio-input",
+        Kind::Text,
+        "",
+        "Audio input device, \"Mute\" for none",
+    ),
+    spec(
+        "video-save-directory",
+        Kind::Text,
+        "",
+        "Directory of recordings",
+    ),
+    spec(
+        "incoming-record-directory",
+        Kind::Text,
+        "",
+        "Directory of the recordings of incoming sessions, \"incoming\" in the one of recordings if empty",
+    ),
+    spec(
+        "incoming-record-max-days",
+        Kind::Number { min: 0, max: 36500 },
+        "0",
+        "Days recordings of incoming sessions are kept, forever if 0",
+    ),
+    spec(
+        "incoming-record-max-size",
+        Kind::Number {
+            min: 0,
+            max: 100_000_000,
+        },
+        "0",
+        "Megabytes of recordings of incoming sessions kept, the oldest deleted first, no limit if 0",
+    ),
+    spec(
+        "local-ip-addr",
+        Kind::Text,
+        "",
+        "Last local ip address, set automatically",
+    ),
+    spec(
+        "enable-capture",
+        Kind::Bool,
+        "N",
+        "Capture the messages of sessions",
+    ),
+    spec(
+        "capture-payloads",
+        Kind::Bool,
+        "N",
+        "Keep payloads in captures",
+    ),
+    spec(
+        "enable-audit-log",
+        Kind::Bool,
+        "Y",
+        "Write the security events of the connections to the local audit log",
+    ),
+    spec(
+        "audit-log-max-size",
+        Kind::Number { min: 1, max: 10240 },
+        "10",
+        "Megabytes of the audit log before it is rotated",
+    ),
+    spec(
+        "audit-log-max-files",
+        Kind::Number { min: 1, max: 1000 },
+        "5",
+        "Rotated audit logs kept",
+    ),
+    spec(
+        "audit-syslog",
+        Kind::Bool,
+        "N",
+        "Also send the audit events to syslog and journald, on Linux and macOS",
+    ),
+    spec(
+        "profiles",
+        Kind::Text,
+        "",
+        "Comma separated profiles whose servers run along, default profile only",
+    ),
+    spec(
+        "access-windows",
+        Kind::Schedule,
+        "",
+        "When unattended access is allowed, e.g. mon-fri 08:00-18:00, any time if empty",
+    ),
+    spec(
+        "access-windows-timezone",
+        Kind::TimeZone,
+        "",
+        "Timezone of the access windows, e.g. Europe/Paris or +02:00, the local time if empty",
+    ),
+    spec(
+        "login-max-failures",
+        Kind::Number { min: 1, max: 1000 },
+        "5",
+        "Wrong logins of an ip or id before it is banned",
+    ),
+    spec(
+        "login-ban-time",
+        Kind::Number { min: 1, max: 86400 },
+        "60",
+        "Seconds of the first ban, doubled for every further wrong login",
+    ),
+    spec(
+        "login-max-ban-time",
+        Kind::Number {
+            min: 60,
+            max: 2592000,
+        },
+        "86400",
+        "Longest ban in seconds, failures are forgotten after it",
+    ),
+    spec(
+        "admin-keys",
+        Kind::Text,
+        "",
+        "Comma separated public keys trusted to sign config bundles",
+    ),
+    spec(
+        "allow-peer-key-change",
+        Kind::Bool,
+        "N",
+        "Connect to peers whose key changed, only logging it",
+    ),
+];
+
+pub fn find(name: &str) -> Option<&'static Spec> {
+    OPTIONS.iter().find(|x| x.name == name)
+}
+
+/// Check `value` of `name`, an empty value being the default of any option.
+pub fn validate(name: &str, value: &str) -> ResultType<()> {
+    match find(name) {
+        Some(spec) => spec.validate(value),
+        None => bail!("Unknown option {}", name),
+    }
+}
+
+/// The value of a [`Kind::Bool`] option, its default if not set.
+pub fn get_bool(name: &str) -> bool {
+    let value = Config::get_option(name);
+    if value.is_empty() {
+        find(name).map(|x| x.default == "Y").unwrap_or(false)
+    } else {
+        value == "Y"
+    }
+}
+
+/// The value of a [`Kind::Number`] option, its default if not set or invalid.
+pub fn get_number(name: &str) -> i64 {
+    let default = || find(name).and_then(|x| x.default.parse().ok()).unwrap_or(0);
+    let value = Config::get_option(name);
+    if value.is_empty() {
+        default()
+    } else {
+        value.parse().unwrap_or_else(|_| default())
+    }
+}
+
+/// An option as listed by the CLI and IPC.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OptionValue {
+    pub name: String,
+    /// [`Kind::describe`], empty for unknown options.
+    pub kind: String,
+    pub default: String,
+    pub description: String,
+    pub value: String,
+}
+
+/// All known options with their values, followed by any unknown option that is set.
+pub fn list() -> Vec<OptionValue> {
+    let mut options = Config::get_options();
+    let mut res: Vec<OptionValue> = OPTIONS
+        .iter()
+        .map(|x| OptionValue {
+            name: x.name.to_owned(),
+            kind: x.kind.describe(),
+            default: x.default.to_owned(),
+            description: x.description.to_owned(),
+            value: options.remove(x.name).unwrap_or_default(),
+        })
+        .collect();
+    let mut unknown: Vec<_> = options.into_iter().collect();
+    unknown.sort();
+    res.extend(unknown.into_iter().map(|(name, value)| OptionValue {
+        name,
+        value,
+        ..Default::default()
+    }));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("enable-keyboard", "N").is_ok());
+        assert!(validate("enable-keyboard", "").is_ok());
+        assert!(validate("enable-keyboard", "no").is_err());
+        assert!(validate("direct-access-port", "21118").is_ok());
+        assert!(validate("direct-access-port", "0").is_err());
+        assert!(validate("direct-access-port", "port").is_err());
+        assert!(validate("verification-method", "use-permanent-password").is_ok());
+        assert!(validate("verification-method", "use-no-password").is_err());
+        assert!(validate("whitelist", "192.168.1.2,::1,0.0.0.0").is_ok());
+        assert!(validate("whitelist", "192.168.1.0/24").is_err());
+        assert!(validate("no-such-option", "Y").is_err());
+    }
+
+    #[test]
+    fn test_schema() {
+        for (i, spec) in OPTIONS.iter().enumerate() {
+            assert!(find(spec.name).map(|x| std::ptr::eq(x, spec)) == Some(true));
+            assert!(
+                OPTIONS[i + 1..].iter().all(|x| x.name != spec.name),
+                "{} declared twice",
+                spec.name
+            );
+            if spec.kind != Kind::Text {
+                assert!(spec.validate(spec.default).is_ok(), "{}", spec.name);
+            }
+        }
+    }
+}
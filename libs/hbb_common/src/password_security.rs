@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::{anyhow::anyhow, bail, config::Config, ResultType};
 use sodiumoxide::base64;
 use std::sync::{Arc, RwLock};
 
@@ -55,7 +55,67 @@ pub fn permanent_enabled() -> bool {
 
 pub fn has_valid_password() -> bool {
     temporary_enabled() && !temporary_password().is_empty()
-        || permanent_enabled() && !Config::get_permanent_password().is_empty()
+        || permanent_enabled() && Config::has_permanent_password()
+}
+
+/// Argon2id cost of new password keys: memory in KiB, passes and lanes.
+const KDF_PARAMS: (u32, u32, u32) = (19 * 1024, 2, 1);
+/// Most memory in KiB a peer may ask for.
+const KDF_MAX_MEMORY: u32 = 256 * 1024;
+const KDF_MAX_PASSES: u32 = 16;
+
+/// Parameters of a new password key, `m=<KiB>,t=<passes>,p=<lanes>$<base64 salt>`.
+pub fn new_password_kdf() -> String {
+    let (m, t, p) = KDF_PARAMS;
+    let salt = sodiumoxide::randombytes::randombytes(16);
+    format!(
+        "m={},t={},p={}${}",
+        m,
+        t,
+        p,
+        base64::encode(&salt, base64::Variant::Original)
+    )
+}
+
+fn parse_kdf(kdf: &str) -> ResultType<(argon2::Params, Vec<u8>)> {
+    let (params, salt) = match kdf.split_once('$') {
+        Some(x) => x,
+        None => bail!("Invalid password kdf {}", kdf),
+    };
+    let (mut m, mut t, mut p) = (0, 0, 0);
+    for x in params.split(',') {
+        match x.split_once('=') {
+            Some(("m", v)) => m = v.parse()?,
+            Some(("t", v)) => t = v.parse()?,
+            Some(("p", v)) => p = v.parse()?,
+            _ => bail!("Invalid password kdf parameter {}", x),
+        }
+    }
+    // they may come from a peer
+    if m > KDF_MAX_MEMORY || t > KDF_MAX_PASSES {
+        bail!("Password kdf too costly: {}", params);
+    }
+    let salt = base64::decode(salt, base64::Variant::Original)
+        .map_err(|_| anyhow!("Invalid password kdf salt"))?;
+    let params = argon2::Params::new(m, t, p, Some(32))
+        .map_err(|err| anyhow!("Invalid password kdf: {}", err))?;
+    Ok((params, salt))
+}
+
+pub fn check_password_kdf(kdf: &str) -> ResultType<()> {
+    parse_kdf(kdf).map(|_| ())
+}
+
+/// The argon2id key of `password` with `kdf` of [`new_password_kdf`]. The host stores
+/// it instead of the password, and the client hashes it with the challenge instead of
+/// sha256(password + salt).
+pub fn derive_password_key(password: &str, kdf: &str) -> ResultType<Vec<u8>> {
+    let (params, salt) = parse_kdf(kdf)?;
+    let mut key = vec![0; 32];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), &salt, &mut key)
+        .map_err(|err| anyhow!("Failed to derive password key: {}", err))?;
+    Ok(key)
 }
 
 const VERSION_LEN: usize = 2;
@@ -215,4 +275,23 @@ mod test {
         assert_eq!(store, false);
         assert_eq!(succ, false);
     }
+
+    #[test]
+    fn test_password_key() {
+        use super::*;
+
+        let kdf = new_password_kdf();
+        let key = derive_password_key("password", &kdf).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(derive_password_key("password", &kdf).unwrap(), key);
+        assert_ne!(derive_password_key("Password", &kdf).unwrap(), key);
+        assert_ne!(
+            derive_password_key("password", &new_password_kdf()).unwrap(),
+            key
+        );
+        let salt = kdf.split_once('$').unwrap().1;
+        assert!(check_password_kdf(&format!("m=1048576,t=2,p=1${}", salt)).is_err());
+        assert!(check_password_kdf(&format!("m=19456,x=2,p=1${}", salt)).is_err());
+        assert!(check_password_kdf("m=19456,t=2,p=1").is_err());
+    }
 }
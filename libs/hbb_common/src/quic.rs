@@ -1,135 +1,278 @@
-use crate::{allow_err, anyhow::anyhow, ResultType};
-use protobuf::Message;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::{self, stream::StreamExt, sync::mpsc};
+use crate::{anyhow::anyhow, bail, ResultType};
+use futures::StreamExt;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
-const QUIC_HBB: &[&[u8]] = &[b"hbb"];
+const QUIC_HBB: &[u8] = b"hbb";
 const SERVER_NAME: &str = "hbb";
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
 
-type Sender = mpsc::UnboundedSender<Value>;
-type Receiver = mpsc::UnboundedReceiver<Value>;
-
-pub fn new_server(socket: std::net::UdpSocket) -> ResultType<(Server, SocketAddr)> {
-    let mut transport_config = quinn::TransportConfig::default();
-    transport_config.stream_window_uni(0);
-    let mut server_config = quinn::ServerConfig::default();
-    server_config.transport = Arc::new(transport_config);
-    let mut server_config = quinn::ServerConfigBuilder::new(server_config);
-    server_config.protocols(QUIC_HBB);
-    // server_config.enable_keylog();
-    // server_config.use_stateless_retry(true);
-    let mut endpoint = quinn::Endpoint::builder();
-    endpoint.listen(server_config.build());
-    let (end, incoming) = endpoint.with_socket(socket)?;
-    Ok((Server { incoming }, end.local_addr()?))
+fn transport_config() -> quinn::TransportConfig {
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    transport
 }
 
-pub async fn new_client(local_addr: &SocketAddr, peer: &SocketAddr) -> ResultType<Connection> {
-    let mut endpoint = quinn::Endpoint::builder();
-    let mut client_config = quinn::ClientConfigBuilder::default();
-    client_config.protocols(QUIC_HBB);
-    //client_config.enable_keylog();
-    endpoint.default_client_config(client_config.build());
-    let (endpoint, _) = endpoint.bind(local_addr)?;
-    let new_conn = endpoint.connect(peer, SERVER_NAME)?.await?;
-    Connection::new_for_client(new_conn.connection).await
+/// The TLS layer of QUIC is only used as a transport here, peers are authenticated
+/// by the signed id / secretbox handshake that runs on top of it, the same as with TCP.
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
 }
 
 pub struct Server {
+    endpoint: quinn::Endpoint,
     incoming: quinn::Incoming,
 }
 
+pub fn new_server(addr: SocketAddr) -> ResultType<Server> {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let cert = rustls::Certificate(cert.serialize_der()?);
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)?;
+    crypto.alpn_protocols = vec![QUIC_HBB.to_vec()];
+    let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    server_config.transport = Arc::new(transport_config());
+    let (endpoint, incoming) = quinn::Endpoint::server(server_config, addr)?;
+    Ok(Server { endpoint, incoming })
+}
+
 impl Server {
     #[inline]
-    pub async fn next(&mut self) -> ResultType<Option<Connection>> {
-        Connection::new_for_server(&mut self.incoming).await
+    pub fn local_addr(&self) -> ResultType<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
     }
-}
 
-pub struct Connection {
-    conn: quinn::Connection,
-    tx: quinn::SendStream,
-    rx: Receiver,
-}
-
-type Value = ResultType<Vec<u8>>;
-
-impl Connection {
-    async fn new_for_server(incoming: &mut quinn::Incoming) -> ResultType<Option<Self>> {
-        if let Some(conn) = incoming.next().await {
+    /// Wait for the connection from `peer`, connections from other addresses are dropped.
+    pub async fn accept(&mut self, peer: IpAddr) -> ResultType<Connection> {
+        while let Some(connecting) = self.incoming.next().await {
+            let remote = connecting.remote_address();
             let quinn::NewConnection {
-                connection: conn,
-                // uni_streams,
+                connection,
                 mut bi_streams,
                 ..
-            } = conn.await?;
-            let (tx, rx) = mpsc::unbounded_channel::<Value>();
-            tokio::spawn(async move {
-                loop {
-                    let stream = bi_streams.next().await;
-                    if let Some(stream) = stream {
-                        let stream = match stream {
-                            Err(e) => {
-                                tx.send(Err(e.into())).ok();
-                                break;
-                            }
-                            Ok(s) => s,
-                        };
-                        let cloned = tx.clone();
-                        tokio::spawn(async move {
-                            allow_err!(handle_request(stream.1, cloned).await);
-                        });
-                    } else {
-                        tx.send(Err(anyhow!("Reset by the peer"))).ok();
-                        break;
-                    }
+            } = match connecting.await {
+                Ok(x) => x,
+                Err(err) => {
+                    log::warn!("Failed quic handshake from {}: {}", remote, err);
+                    continue;
                 }
-                log::info!("Exit connection outer loop");
+            };
+            if connection.remote_address().ip() != peer {
+                log::warn!(
+                    "Unexpected quic connection from {}, expecting {}",
+                    connection.remote_address(),
+                    peer
+                );
+                continue;
+            }
+            let (send, mut recv) = bi_streams
+                .next()
+                .await
+                .ok_or_else(|| anyhow!("Reset by the peer"))??;
+            let mut hello = [0u8; QUIC_HBB.len()];
+            recv.read_exact(&mut hello).await?;
+            if hello != QUIC_HBB {
+                bail!("Invalid quic hello");
+            }
+            return Ok(Connection {
+                endpoint: self.endpoint.clone(),
+                conn: connection,
+                send,
+                recv,
             });
-            let tx = conn.open_uni().await?;
-            Ok(Some(Self { conn, tx, rx }))
-        } else {
-            Ok(None)
         }
+        bail!("Quic endpoint closed");
     }
+}
 
-    async fn new_for_client(conn: quinn::Connection) -> ResultType<Self> {
-        let (tx, rx_quic) = conn.open_bi().await?;
-        let (tx_mpsc, rx) = mpsc::unbounded_channel::<Value>();
-        tokio::spawn(async move {
-            allow_err!(handle_request(rx_quic, tx_mpsc).await);
-        });
-        Ok(Self { conn, tx, rx })
-    }
+pub async fn new_client(local_addr: SocketAddr, peer: SocketAddr) -> ResultType<Connection> {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![QUIC_HBB.to_vec()];
+    let mut client_config = quinn::ClientConfig::new(Arc::new(crypto));
+    client_config.transport = Arc::new(transport_config());
+    let mut endpoint = quinn::Endpoint::client(local_addr)?;
+    endpoint.set_default_client_config(client_config);
+    let quinn::NewConnection { connection, .. } = endpoint.connect(peer, SERVER_NAME)?.await?;
+    let (mut send, recv) = connection.open_bi().await?;
+    // the peer does not see the stream until something is written on it
+    send.write_all(QUIC_HBB).await?;
+    Ok(Connection {
+        endpoint,
+        conn: connection,
+        send,
+        recv,
+    })
+}
 
-    #[inline]
-    pub async fn next(&mut self) -> Option<Value> {
-        // None is returned when all Sender halves have dropped,
-        // indicating that no further values can be sent on the channel.
-        self.rx.recv().await
-    }
+/// One bidirectional stream of a quic connection, it is framed and encrypted
+/// by [`crate::tcp::FramedStream`] the same way as a tcp stream.
+pub struct Connection {
+    endpoint: quinn::Endpoint,
+    conn: quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
 
+impl Connection {
     #[inline]
     pub fn remote_address(&self) -> SocketAddr {
         self.conn.remote_address()
     }
 
     #[inline]
-    pub async fn send_raw(&mut self, bytes: &[u8]) -> ResultType<()> {
-        self.tx.write_all(bytes).await?;
-        Ok(())
+    pub fn local_addr(&self) -> SocketAddr {
+        self.endpoint
+            .local_addr()
+            .unwrap_or_else(|_| crate::config::Config::get_any_listen_addr())
     }
+}
 
-    #[inline]
-    pub async fn send(&mut self, msg: &dyn Message) -> ResultType<()> {
-        match msg.write_to_bytes() {
-            Ok(bytes) => self.send_raw(&bytes).await?,
-            err => allow_err!(err),
-        }
-        Ok(())
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
     }
 }
 
-async fn handle_request(rx: quinn::RecvStream, tx: Sender) -> ResultType<()> {
-    Ok(())
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message_proto::*, protobuf::Message as _, tcp::FramedStream};
+    use sodiumoxide::crypto::secretbox;
+
+    #[tokio::test]
+    async fn test_login_and_video_over_quic() {
+        let mut server = new_server("127.0.0.1:0".parse().unwrap()).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let key = secretbox::gen_key();
+
+        let server_key = key.clone();
+        let server_task = tokio::spawn(async move {
+            let conn = server.accept("127.0.0.1".parse().unwrap()).await.unwrap();
+            let addr = conn.local_addr();
+            let mut stream = FramedStream::from(conn, addr);
+            stream.set_key(server_key);
+            let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+            let msg_in = Message::parse_from_bytes(&bytes).unwrap();
+            match msg_in.union {
+                Some(message::Union::LoginRequest(lr)) => {
+                    assert_eq!(lr.username, "123456789");
+                    assert_eq!(&lr.password[..], b"password");
+                }
+                _ => panic!("expecting login request"),
+            }
+            let mut msg_out = Message::new();
+            msg_out.set_login_response(LoginResponse {
+                union: Some(login_response::Union::PeerInfo(PeerInfo {
+                    username: "test".to_owned(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            });
+            stream.send(&msg_out).await.unwrap();
+            for i in 0..10u8 {
+                let mut msg_out = Message::new();
+                msg_out.set_video_frame(VideoFrame {
+                    union: Some(video_frame::Union::Vp9s(EncodedVideoFrames {
+                        frames: vec![EncodedVideoFrame {
+                            data: vec![i; 64 * 1024].into(),
+                            key: i == 0,
+                            pts: i as _,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                });
+                stream.send(&msg_out).await.unwrap();
+            }
+        });
+
+        let conn = new_client("127.0.0.1:0".parse().unwrap(), server_addr)
+            .await
+            .unwrap();
+        assert_eq!(conn.remote_address(), server_addr);
+        let addr = conn.local_addr();
+        let mut stream = FramedStream::from(conn, addr);
+        stream.set_key(key);
+        let mut msg_out = Message::new();
+        msg_out.set_login_request(LoginRequest {
+            username: "123456789".to_owned(),
+            password: b"password".to_vec().into(),
+            ..Default::default()
+        });
+        stream.send(&msg_out).await.unwrap();
+        let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+        let msg_in = Message::parse_from_bytes(&bytes).unwrap();
+        match msg_in.union {
+            Some(message::Union::LoginResponse(lr)) => match lr.union {
+                Some(login_response::Union::PeerInfo(pi)) => assert_eq!(pi.username, "test"),
+                _ => panic!("expecting peer info"),
+            },
+            _ => panic!("expecting login response"),
+        }
+        for i in 0..10u8 {
+            let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+            let msg_in = Message::parse_from_bytes(&bytes).unwrap();
+            match msg_in.union {
+                Some(message::Union::VideoFrame(vf)) => match vf.union {
+                    Some(video_frame::Union::Vp9s(evfs)) => {
+                        assert_eq!(evfs.frames.len(), 1);
+                        assert_eq!(evfs.frames[0].pts, i as i64);
+                        assert_eq!(evfs.frames[0].data.len(), 64 * 1024);
+                        assert!(evfs.frames[0].data.iter().all(|x| *x == i));
+                    }
+                    _ => panic!("expecting vp9 frames"),
+                },
+                _ => panic!("expecting video frame"),
+            }
+        }
+        server_task.await.unwrap();
+    }
 }
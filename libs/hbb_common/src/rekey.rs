@@ -0,0 +1,245 @@
+//! In-band rekeying of a secured [`FramedStream`].
+//!
+//! The host initiates, the client responds. Both sides exchange ephemeral curve25519 keys
+//! inside the current secretbox channel and derive the next key from the shared secret,
+//! so a key leaked later does not expose the traffic sealed with earlier keys.
+//!
+//! ```text
+//! host                                     client
+//!  Rekey { pk_h }            ------>
+//!                            <------      Rekey { pk_c }, then sends with the new key
+//!  receives with the new key
+//!  Rekey { }                 ------>
+//!  then sends with the new key            receives with the new key
+//! ```
+//!
+//! Any message out of this order is a protocol violation and ends the session.
+use crate::{
+    bail,
+    message_proto::{Message, Rekey},
+    tcp::FramedStream,
+    timeout, ResultType,
+};
+use sodiumoxide::crypto::{box_, hash::sha256, secretbox};
+use std::time::{Duration, Instant};
+
+const REKEY_INTERVAL: Duration = Duration::from_secs(3600);
+const REKEY_BYTES: u64 = 1 << 30;
+const REKEY_TIMEOUT: Duration = Duration::from_secs(30);
+const SEND_TIMEOUT: u64 = 12_000;
+
+enum State {
+    Idle,
+    Initiated {
+        pk: box_::PublicKey,
+        sk: box_::SecretKey,
+        at: Instant,
+    },
+    Responded(secretbox::Key),
+}
+
+pub struct KeyRotation {
+    initiator: bool,
+    state: State,
+    since: Instant,
+}
+
+impl KeyRotation {
+    pub fn new(initiator: bool) -> Self {
+        Self {
+            initiator,
+            state: State::Idle,
+            since: Instant::now(),
+        }
+    }
+
+    /// Start a rekey once the current keys are old enough or have carried enough data,
+    /// and give up on a peer that does not answer. Called periodically by the host.
+    pub async fn check(&mut self, stream: &mut FramedStream) -> ResultType<()> {
+        if !self.initiator {
+            return Ok(());
+        }
+        if let State::Initiated { at, .. } = &self.state {
+            if at.elapsed() >= REKEY_TIMEOUT {
+                bail!("Rekey failed: timeout");
+            }
+        } else if self.since.elapsed() >= REKEY_INTERVAL || stream.key_usage() >= REKEY_BYTES {
+            self.initiate(stream).await?;
+        }
+        Ok(())
+    }
+
+    async fn initiate(&mut self, stream: &mut FramedStream) -> ResultType<()> {
+        let (pk, sk) = box_::gen_keypair();
+        send_rekey(stream, pk.0.to_vec()).await?;
+        self.state = State::Initiated {
+            pk,
+            sk,
+            at: Instant::now(),
+        };
+        Ok(())
+    }
+
+    pub async fn handle(&mut self, rk: Rekey, stream: &mut FramedStream) -> ResultType<()> {
+        if !stream.is_secured() {
+            bail!("Rekey failed: connection not secured");
+        }
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => {
+                if self.initiator {
+                    bail!("Rekey failed: unexpected rekey request");
+                }
+                let their_pk = parse_public_key(&rk.public_key)?;
+                let (pk, sk) = box_::gen_keypair();
+                let key = derive_key(&their_pk, &sk, &their_pk, &pk);
+                send_rekey(stream, pk.0.to_vec()).await?;
+                stream.set_send_key(key.clone());
+                self.state = State::Responded(key);
+            }
+            State::Initiated { pk, sk, .. } => {
+                let their_pk = parse_public_key(&rk.public_key)?;
+                let key = derive_key(&their_pk, &sk, &pk, &their_pk);
+                stream.set_recv_key(key.clone());
+                send_rekey(stream, Vec::new()).await?;
+                stream.set_send_key(key);
+                self.since = Instant::now();
+                log::info!("Session rekeyed");
+            }
+            State::Responded(key) => {
+                if !rk.public_key.is_empty() {
+                    bail!("Rekey failed: unexpected public key");
+                }
+                stream.set_recv_key(key);
+                self.since = Instant::now();
+                log::info!("Session rekeyed");
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn send_rekey(stream: &mut FramedStream, public_key: Vec<u8>) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_rekey(Rekey {
+        public_key: public_key.into(),
+        ..Default::default()
+    });
+    timeout(SEND_TIMEOUT, stream.send(&msg_out)).await??;
+    Ok(())
+}
+
+fn parse_public_key(bytes: &[u8]) -> ResultType<box_::PublicKey> {
+    match box_::PublicKey::from_slice(bytes) {
+        Some(pk) => Ok(pk),
+        None => bail!("Rekey failed: invalid public key"),
+    }
+}
+
+fn derive_key(
+    their_pk: &box_::PublicKey,
+    our_sk: &box_::SecretKey,
+    initiator_pk: &box_::PublicKey,
+    responder_pk: &box_::PublicKey,
+) -> secretbox::Key {
+    let shared = box_::precompute(their_pk, our_sk);
+    let mut state = sha256::State::new();
+    state.update(b"hbb rekey");
+    state.update(&shared.0);
+    state.update(&initiator_pk.0);
+    state.update(&responder_pk.0);
+    secretbox::Key(state.finalize().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message_proto::message, protobuf::Message as _};
+
+    async fn next_msg(stream: &mut FramedStream) -> Message {
+        let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+        Message::parse_from_bytes(&bytes).unwrap()
+    }
+
+    fn test_delay(time: i64) -> Message {
+        let mut msg = Message::new();
+        msg.set_test_delay(crate::message_proto::TestDelay {
+            time,
+            ..Default::default()
+        });
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_rekey() {
+        let (a, b) = tokio::io::duplex(1 << 16);
+        let addr = crate::config::Config::get_any_listen_addr();
+        let mut host = FramedStream::from(a, addr);
+        let mut client = FramedStream::from(b, addr);
+        let key = secretbox::gen_key();
+        host.set_key(key.clone());
+        client.set_key(key);
+        let mut host_rotation = KeyRotation::new(true);
+        let mut client_rotation = KeyRotation::new(false);
+
+        host_rotation.initiate(&mut host).await.unwrap();
+        // the client keeps sending with the old key until it sees the request
+        client.send(&test_delay(1)).await.unwrap();
+        match next_msg(&mut client).await.union {
+            Some(message::Union::Rekey(rk)) => {
+                client_rotation.handle(rk, &mut client).await.unwrap()
+            }
+            _ => panic!("expecting rekey"),
+        }
+        client.send(&test_delay(2)).await.unwrap();
+        host.send(&test_delay(3)).await.unwrap();
+
+        assert!(matches!(
+            next_msg(&mut host).await.union,
+            Some(message::Union::TestDelay(t)) if t.time == 1
+        ));
+        match next_msg(&mut host).await.union {
+            Some(message::Union::Rekey(rk)) => host_rotation.handle(rk, &mut host).await.unwrap(),
+            _ => panic!("expecting rekey"),
+        }
+        assert!(matches!(
+            next_msg(&mut host).await.union,
+            Some(message::Union::TestDelay(t)) if t.time == 2
+        ));
+        host.send(&test_delay(4)).await.unwrap();
+
+        assert!(matches!(
+            next_msg(&mut client).await.union,
+            Some(message::Union::TestDelay(t)) if t.time == 3
+        ));
+        match next_msg(&mut client).await.union {
+            Some(message::Union::Rekey(rk)) => {
+                client_rotation.handle(rk, &mut client).await.unwrap()
+            }
+            _ => panic!("expecting rekey"),
+        }
+        assert!(matches!(
+            next_msg(&mut client).await.union,
+            Some(message::Union::TestDelay(t)) if t.time == 4
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rekey_misbehaving_peer() {
+        let (a, _b) = tokio::io::duplex(1 << 16);
+        let mut stream = FramedStream::from(a, crate::config::Config::get_any_listen_addr());
+        stream.set_key(secretbox::gen_key());
+        let mut host_rotation = KeyRotation::new(true);
+        assert!(host_rotation
+            .handle(Rekey::new(), &mut stream)
+            .await
+            .is_err());
+        let mut client_rotation = KeyRotation::new(false);
+        assert!(client_rotation
+            .handle(Rekey::new(), &mut stream)
+            .await
+            .is_err());
+        let mut rk = Rekey::new();
+        rk.public_key = vec![0u8; 7].into();
+        assert!(client_rotation.handle(rk, &mut stream).await.is_err());
+    }
+}
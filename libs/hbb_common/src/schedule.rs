@@ -0,0 +1,245 @@
+//! Access windows, the times unattended access is allowed.
+//!
+//! A schedule is a list of windows separated by `;`, each being days and a time range,
+//! e.g. `mon-fri 08:00-18:00; sat 09:00-12:00`. Days are `mon` to `sun`, ranges of them
+//! like `mon-fri`, lists like `mon,wed`, or `*` for every day, which is also meant if
+//! no day is given. A range ending before its start goes past midnight, `24:00` being
+//! the end of the day. An empty schedule allows any time.
+//!
+//! The timezone of a schedule is empty for the local time of the machine, a zone name
+//! such as `Europe/Paris`, which follows daylight saving time, or `UTC` with an optional
+//! fixed offset such as `UTC+02:00`, `+02:00` or `-05:30`.
+use crate::{anyhow::anyhow, bail, ResultType};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    /// Monday first.
+    days: [bool; 7],
+    /// Minutes of the day.
+    start: u32,
+    end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeZone {
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::Local
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    windows: Vec<Window>,
+    timezone: TimeZone,
+}
+
+fn parse_day(s: &str) -> ResultType<usize> {
+    match DAYS.iter().position(|x| *x == s) {
+        Some(i) => Ok(i),
+        None => bail!("Unknown day {}, expecting one of {}", s, DAYS.join(",")),
+    }
+}
+
+fn parse_days(s: &str) -> ResultType<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        if part == "*" {
+            days = [true; 7];
+            continue;
+        }
+        let mut it = part.splitn(2, '-');
+        let from = parse_day(it.next().unwrap_or_default())?;
+        let to = match it.next() {
+            Some(x) => parse_day(x)?,
+            None => from,
+        };
+        let mut i = from;
+        loop {
+            days[i] = true;
+            if i == to {
+                break;
+            }
+            i = (i + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+/// Minutes of `hh:mm`, up to 24:00.
+fn parse_time(s: &str) -> ResultType<u32> {
+    let mut it = s.splitn(2, ':');
+    let (h, m) = match (it.next(), it.next()) {
+        (Some(h), Some(m)) => (h.parse::<u32>()?, m.parse::<u32>()?),
+        _ => bail!("Invalid time {}, expecting hh:mm", s),
+    };
+    if m >= 60 || h * 60 + m > 24 * 60 {
+        bail!("Invalid time {}", s);
+    }
+    Ok(h * 60 + m)
+}
+
+/// The timezone of a schedule, see the module documentation.
+pub fn parse_timezone(tz: &str) -> ResultType<TimeZone> {
+    let tz = tz.trim();
+    if tz.is_empty() {
+        return Ok(TimeZone::Local);
+    }
+    let s = tz.strip_prefix("UTC").unwrap_or(tz);
+    let sign = match s.chars().next() {
+        None => return Ok(TimeZone::Fixed(FixedOffset::east(0))),
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => {
+            return tz.parse::<Tz>().map(TimeZone::Named).map_err(|_| {
+                anyhow!(
+                    "Invalid timezone {}, expecting a zone name like Europe/Paris or an offset like +02:00",
+                    tz
+                )
+            })
+        }
+    };
+    let minutes = parse_time(&s[1..])? as i32;
+    FixedOffset::east_opt(sign * minutes * 60)
+        .map(TimeZone::Fixed)
+        .ok_or_else(|| anyhow!("Invalid timezone {}", tz))
+}
+
+impl Schedule {
+    pub fn parse(spec: &str, tz: &str) -> ResultType<Self> {
+        let mut windows = Vec::new();
+        for x in spec.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let x = x.to_lowercase();
+            let mut it = x.split_whitespace();
+            let (days, range) = match (it.next(), it.next(), it.next()) {
+                (Some(days), Some(range), None) => (days, range),
+                (Some(range), None, None) => ("*", range),
+                _ => bail!("Invalid window {}, expecting e.g. mon-fri 08:00-18:00", x),
+            };
+            let mut it = range.splitn(2, '-');
+            let start = parse_time(it.next().unwrap_or_default())?;
+            let end = match it.next() {
+                Some(x) => parse_time(x)?,
+                None => bail!("Invalid time range {}, expecting e.g. 08:00-18:00", range),
+            };
+            if start == end {
+                bail!("Empty time range {}", range);
+            }
+            windows.push(Window {
+                days: parse_days(days)?,
+                start,
+                end,
+            });
+        }
+        Ok(Self {
+            windows,
+            timezone: parse_timezone(tz)?,
+        })
+    }
+
+    /// Whether any time is allowed.
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    fn local(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        match self.timezone {
+            TimeZone::Local => now.with_timezone(&Local).naive_local(),
+            TimeZone::Fixed(offset) => now.with_timezone(&offset).naive_local(),
+            TimeZone::Named(tz) => now.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    /// The end of the window `t` is in, the latest one if several.
+    fn window_end(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut res = None;
+        for w in self.windows.iter() {
+            // a window of yesterday may go past midnight
+            for days_ago in 0..2 {
+                let date = t.date() - Duration::days(days_ago);
+                if !w.days[date.weekday().num_days_from_monday() as usize] {
+                    continue;
+                }
+                let day = date.and_hms(0, 0, 0);
+                let start = day + Duration::minutes(w.start as _);
+                let mut end = day + Duration::minutes(w.end as _);
+                if w.end < w.start {
+                    end += Duration::days(1);
+                }
+                if start <= t && t < end {
+                    res = res.max(Some(end));
+                }
+            }
+        }
+        res
+    }
+
+    /// Seconds access is still allowed for at `now`, `None` if it is not, windows
+    /// following each other being joined up to a week.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<i64> {
+        if self.is_empty() {
+            return Some(i64::MAX);
+        }
+        let start = self.local(now);
+        let mut end = self.window_end(start)?;
+        while end - start < Duration::days(7) {
+            match self.window_end(end) {
+                Some(x) if x > end => end = x,
+                _ => break,
+            }
+        }
+        Some((end - start).num_seconds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_remaining() {
+        let schedule = Schedule::parse("mon-fri 08:00-18:00; SAT 22:00-02:00", "+02:00").unwrap();
+        // Monday 2022-10-03, 10:00 at +02:00
+        let monday = Utc.ymd(2022, 10, 3).and_hms(8, 0, 0);
+        assert_eq!(schedule.remaining(monday), Some(8 * 3600));
+        assert_eq!(schedule.remaining(monday + Duration::hours(8)), None);
+        // Sunday 01:00, in the window of Saturday
+        let sunday = monday + Duration::days(6) - Duration::hours(9);
+        assert_eq!(schedule.remaining(sunday), Some(3600));
+        assert_eq!(schedule.remaining(sunday + Duration::hours(22)), None);
+
+        let joined = Schedule::parse("mon 00:00-24:00; tue 00:00-12:00", "UTC").unwrap();
+        assert_eq!(
+            joined.remaining(Utc.ymd(2022, 10, 3).and_hms(23, 0, 0)),
+            Some(13 * 3600)
+        );
+        assert_eq!(
+            Schedule::parse("", "").unwrap().remaining(monday),
+            Some(i64::MAX)
+        );
+
+        assert!(Schedule::parse("mon,wed,fri-sun 10:00-11:30", "UTC-05:30").is_ok());
+        assert!(Schedule::parse("08:00-18:00", "").is_ok());
+        assert!(Schedule::parse("monday 08:00-18:00", "").is_err());
+        assert!(Schedule::parse("mon 08:00-25:00", "").is_err());
+        assert!(Schedule::parse("mon 08:00", "").is_err());
+        assert!(Schedule::parse("mon 08:00-18:00", "Europe/Nowhere").is_err());
+
+        // 09:00 in Paris is 07:00 UTC in summer and 08:00 UTC in winter
+        let paris = Schedule::parse("mon-fri 09:00-17:00", "Europe/Paris").unwrap();
+        assert_eq!(paris.remaining(monday - Duration::hours(1)), Some(8 * 3600));
+        let winter = Utc.ymd(2022, 12, 5).and_hms(8, 0, 0);
+        assert_eq!(paris.remaining(winter - Duration::hours(1)), None);
+        assert_eq!(paris.remaining(winter), Some(8 * 3600));
+    }
+}
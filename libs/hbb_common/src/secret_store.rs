@@ -0,0 +1,213 @@
+//! Local secret protecting the remembered peer passwords.
+//!
+//! The secret is a random key kept in the system keyring (Secret Service on Linux,
+//! Credential Manager on Windows, Keychain on macOS). Without a keyring it is derived
+//! from a passphrase with argon2, which has to be given to [`unlock`] in every process
+//! needing it, until then the store is [`Locked`]. Which one is used is decided the
+//! first time, and kept in the local options of the profile.
+//!
+//! A sealed value is [`VERSION`] followed by the base64 of the nonce and the secretbox.
+use crate::{
+    anyhow::anyhow,
+    bail,
+    config::{LocalConfig, APP_NAME},
+    log, ResultType,
+};
+use sodiumoxide::{base64, crypto::secretbox};
+use std::sync::Mutex;
+
+pub const VERSION: &str = "01";
+const SALT: &str = "password-store-salt";
+const CHECK: &str = "password-store-check";
+const CHECK_VALUE: &[u8] = b"password-store";
+
+/// The store has a passphrase which was not given to [`unlock`] yet.
+#[derive(Debug)]
+pub struct Locked;
+
+impl std::fmt::Display for Locked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The password store is locked, unlock it with its passphrase"
+        )
+    }
+}
+
+impl std::error::Error for Locked {}
+
+#[derive(Default)]
+struct Store {
+    key: Option<secretbox::Key>,
+    keyring_tried: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref STORE: Mutex<Store> = Default::default();
+}
+
+fn encode(v: &[u8]) -> String {
+    base64::encode(v, base64::Variant::Original)
+}
+
+fn decode(s: &str) -> ResultType<Vec<u8>> {
+    base64::decode(s, base64::Variant::Original).map_err(|_| anyhow!("Invalid base64"))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn keyring_key() -> ResultType<secretbox::Key> {
+    let user = format!("peer-passwords{}", crate::config::Config::get_profile());
+    let entry = keyring::Entry::new(&APP_NAME.read().unwrap(), &user)?;
+    match entry.get_password() {
+        Ok(s) => match secretbox::Key::from_slice(&decode(&s)?) {
+            Some(key) => Ok(key),
+            None => bail!("Invalid key in the keyring"),
+        },
+        Err(keyring::Error::NoEntry) => {
+            let key = secretbox::gen_key();
+            entry.set_password(&encode(&key.0))?;
+            log::info!("Created the key of the password store in the keyring");
+            Ok(key)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn keyring_key() -> ResultType<secretbox::Key> {
+    bail!("No keyring on {}", std::env::consts::OS);
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> ResultType<secretbox::Key> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key.0)
+        .map_err(|err| anyhow!("Failed to derive key: {}", err))?;
+    Ok(key)
+}
+
+/// Whether `key` opens the check value, which is stored the first time.
+fn check_key(key: &secretbox::Key) -> ResultType<()> {
+    let check = LocalConfig::get_option(CHECK);
+    if check.is_empty() {
+        LocalConfig::set_option(CHECK.to_owned(), seal_with(key, CHECK_VALUE));
+        return Ok(());
+    }
+    if open_with(key, check.as_bytes())? != CHECK_VALUE {
+        bail!("Wrong key");
+    }
+    Ok(())
+}
+
+fn key() -> ResultType<secretbox::Key> {
+    let mut store = STORE.lock().unwrap();
+    if let Some(key) = store.key.as_ref() {
+        return Ok(key.clone());
+    }
+    if LocalConfig::get_option(SALT).is_empty() && !store.keyring_tried {
+        store.keyring_tried = true;
+        match keyring_key().and_then(|key| check_key(&key).map(|_| key)) {
+            Ok(key) => {
+                store.key = Some(key.clone());
+                return Ok(key);
+            }
+            Err(err) => log::warn!("Password store without keyring: {}", err),
+        }
+    }
+    bail!(Locked)
+}
+
+pub fn is_locked() -> bool {
+    key().is_err()
+}
+
+/// Whether the store needs a passphrase, set by the first [`unlock`].
+pub fn has_passphrase() -> bool {
+    !LocalConfig::get_option(SALT).is_empty()
+}
+
+/// Unlock the store with `passphrase`, which is set the first time if there is no
+/// keyring.
+pub fn unlock(passphrase: &str) -> ResultType<()> {
+    if !is_locked() {
+        return Ok(());
+    }
+    if passphrase.is_empty() {
+        bail!("Empty passphrase");
+    }
+    let mut salt = LocalConfig::get_option(SALT);
+    if salt.is_empty() {
+        if !LocalConfig::get_option(CHECK).is_empty() {
+            bail!("The password store uses the keyring, which is not available");
+        }
+        salt = encode(&sodiumoxide::randombytes::randombytes(16));
+        LocalConfig::set_option(SALT.to_owned(), salt.clone());
+    }
+    let key = derive_key(passphrase, &decode(&salt)?)?;
+    if check_key(&key).is_err() {
+        bail!("Wrong passphrase");
+    }
+    STORE.lock().unwrap().key = Some(key);
+    Ok(())
+}
+
+pub fn is_sealed(v: &[u8]) -> bool {
+    v.len() > VERSION.len() && v.starts_with(VERSION.as_bytes())
+}
+
+fn seal_with(key: &secretbox::Key, v: &[u8]) -> String {
+    let nonce = secretbox::gen_nonce();
+    let mut data = nonce.0.to_vec();
+    data.extend(secretbox::seal(v, &nonce, key));
+    VERSION.to_owned() + &encode(&data)
+}
+
+fn open_with(key: &secretbox::Key, v: &[u8]) -> ResultType<Vec<u8>> {
+    if !is_sealed(v) {
+        bail!("Not sealed");
+    }
+    let data = decode(&String::from_utf8_lossy(&v[VERSION.len()..]))?;
+    if data.len() < secretbox::NONCEBYTES {
+        bail!("Sealed value too short");
+    }
+    let (nonce, cipher) = data.split_at(secretbox::NONCEBYTES);
+    let nonce = secretbox::Nonce::from_slice(nonce).ok_or(anyhow!("Invalid nonce"))?;
+    secretbox::open(cipher, &nonce, key).map_err(|_| anyhow!("Failed to open sealed value"))
+}
+
+/// Seal `v` with the local secret, failing with [`Locked`] if it is not available.
+pub fn seal(v: &[u8]) -> ResultType<Vec<u8>> {
+    if v.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(seal_with(&key()?, v).into_bytes())
+}
+
+/// Open a value of [`seal`], failing with [`Locked`] if the secret is not available.
+pub fn open(v: &[u8]) -> ResultType<Vec<u8>> {
+    open_with(&key()?, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal() {
+        let key = derive_key("passphrase", b"0123456789abcdef").unwrap();
+        assert_eq!(
+            key.0,
+            derive_key("passphrase", b"0123456789abcdef").unwrap().0
+        );
+        let password = vec![7u8; 32];
+        let sealed = seal_with(&key, &password);
+        assert!(is_sealed(sealed.as_bytes()));
+        assert_ne!(sealed, seal_with(&key, &password));
+        assert_eq!(open_with(&key, sealed.as_bytes()).unwrap(), password);
+
+        let other = derive_key("other", b"0123456789abcdef").unwrap();
+        assert!(open_with(&other, sealed.as_bytes()).is_err());
+        assert!(open_with(&key, b"00legacy").is_err());
+        assert!(!is_sealed(&password));
+    }
+}
@@ -2,7 +2,7 @@ use crate::{
     config::{Config, NetworkType},
     tcp::FramedStream,
     udp::FramedSocket,
-    ResultType,
+    websocket, ResultType,
 };
 use anyhow::Context;
 use std::net::SocketAddr;
@@ -17,9 +17,13 @@ fn to_socket_addr(host: &str) -> ResultType<SocketAddr> {
         .context("Failed to solve")
 }
 
+/// The address of `host` for udp, resolved unless tunneled through a SOCKS5 proxy, udp
+/// going out directly with an HTTP proxy.
 pub fn get_target_addr(host: &str) -> ResultType<TargetAddr<'static>> {
     let addr = match Config::get_network_type() {
-        NetworkType::Direct => to_socket_addr(&host)?.into_target_addr()?,
+        NetworkType::Direct | NetworkType::ProxyHttp => {
+            to_socket_addr(&host)?.into_target_addr()?
+        }
         NetworkType::ProxySocks => host.into_target_addr()?,
     }
     .to_owned();
@@ -28,6 +32,18 @@ pub fn get_target_addr(host: &str) -> ResultType<TargetAddr<'static>> {
 
 pub fn test_if_valid_server(host: &str) -> String {
     let mut host = host.to_owned();
+    // address with scheme, e.g. http://proxy:8080 or wss://example.com/ws/id
+    if let Some(i) = host.find("://") {
+        let scheme = host[..i].to_lowercase();
+        if !["http", "socks5", "ws", "wss"].contains(&scheme.as_str()) {
+            return format!("Unsupported scheme {}://", scheme);
+        }
+        host = host[i + 3..]
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+    }
     if !host.contains(":") {
         host = format!("{}:{}", host, 0);
     }
@@ -37,7 +53,7 @@ pub fn test_if_valid_server(host: &str) -> String {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
-        NetworkType::ProxySocks => match &host.into_target_addr() {
+        NetworkType::ProxySocks | NetworkType::ProxyHttp => match &host.into_target_addr() {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
@@ -52,8 +68,20 @@ pub async fn connect_tcp<'t, T: IntoTargetAddr<'t>>(
     let target_addr = target.into_target_addr()?;
 
     if let Some(conf) = Config::get_socks() {
+        conf.check()?;
+        if conf.is_http() {
+            return FramedStream::connect_http(
+                conf.proxy_addr(),
+                target_addr,
+                local,
+                conf.username.as_str(),
+                conf.password.as_str(),
+                ms_timeout,
+            )
+            .await;
+        }
         FramedStream::connect(
-            conf.proxy.as_str(),
+            conf.proxy_addr(),
             target_addr,
             local,
             conf.username.as_str(),
@@ -70,12 +98,29 @@ pub async fn connect_tcp<'t, T: IntoTargetAddr<'t>>(
     }
 }
 
+/// Connect to a rendezvous or relay server, which is a websocket url (ws:// or wss://)
+/// for sites where only http(s) gets through, else `host:port` over plain tcp.
+pub async fn connect_server(
+    server: &str,
+    local: SocketAddr,
+    ms_timeout: u64,
+) -> ResultType<FramedStream> {
+    if websocket::is_ws_url(server) {
+        websocket::connect(server, local, ms_timeout).await
+    } else {
+        connect_tcp(server, local, ms_timeout).await
+    }
+}
+
 pub async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<FramedSocket> {
     match Config::get_socks() {
+        // udp can not be tunneled through an HTTP proxy, it goes out directly
+        Some(conf) if conf.is_http() => Ok(FramedSocket::new(local).await?),
         None => Ok(FramedSocket::new(local).await?),
         Some(conf) => {
+            conf.check()?;
             let socket = FramedSocket::new_proxy(
-                conf.proxy.as_str(),
+                conf.proxy_addr(),
                 local,
                 conf.username.as_str(),
                 conf.password.as_str(),
@@ -89,7 +134,7 @@ pub async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<
 
 pub async fn rebind_udp<T: ToSocketAddrs>(local: T) -> ResultType<Option<FramedSocket>> {
     match Config::get_network_type() {
-        NetworkType::Direct => Ok(Some(FramedSocket::new(local).await?)),
-        _ => Ok(None),
+        NetworkType::Direct | NetworkType::ProxyHttp => Ok(Some(FramedSocket::new(local).await?)),
+        NetworkType::ProxySocks => Ok(None),
     }
 }
@@ -1,4 +1,9 @@
-use crate::{bail, bytes_codec::BytesCodec, ResultType};
+use crate::{
+    bail,
+    bytes_codec::BytesCodec,
+    capture::{Capture, Direction},
+    ResultType,
+};
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
 use protobuf::Message;
@@ -11,10 +16,10 @@ use std::{
     task::{Context, Poll},
 };
 use tokio::{
-    io::{AsyncRead, AsyncWrite, ReadBuf},
-    net::{lookup_host, TcpListener, TcpSocket, ToSocketAddrs},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream, ToSocketAddrs},
 };
-use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, ToProxyAddrs};
+use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, TargetAddr, ToProxyAddrs};
 use tokio_util::codec::Framed;
 
 pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin {}
@@ -23,10 +28,24 @@ pub struct DynTcpStream(Box<dyn TcpStreamTrait + Send + Sync>);
 pub struct FramedStream(
     Framed<DynTcpStream, BytesCodec>,
     SocketAddr,
-    Option<(Key, u64, u64)>,
+    Option<Encrypt>,
     u64,
+    Option<Capture>,
 );
 
+/// Keys of both directions are tracked separately, so that they can be replaced
+/// one at a time during a rekey, see [`crate::rekey`].
+struct Encrypt {
+    send_key: Key,
+    send_seqnum: u64,
+    recv_key: Key,
+    recv_seqnum: u64,
+    // bytes sealed since the send key was installed
+    send_usage: u64,
+    // bytes opened since the recv key was installed
+    recv_usage: u64,
+}
+
 impl Deref for FramedStream {
     type Target = Framed<DynTcpStream, BytesCodec>;
 
@@ -92,6 +111,7 @@ impl FramedStream {
                     addr,
                     None,
                     0,
+                    None,
                 ));
             }
         }
@@ -137,12 +157,57 @@ impl FramedStream {
                     addr,
                     None,
                     0,
+                    None,
                 ));
             };
         };
         bail!("could not resolve to any address");
     }
 
+    /// Connect through an HTTP proxy with the CONNECT method, basic auth is used if
+    /// `username` is not empty.
+    pub async fn connect_http<'a, 't, T1, T2>(
+        proxy: &'a str,
+        target: T1,
+        local: T2,
+        username: &'a str,
+        password: &'a str,
+        ms_timeout: u64,
+    ) -> ResultType<Self>
+    where
+        T1: IntoTargetAddr<'t>,
+        T2: ToSocketAddrs,
+    {
+        let target = match target.into_target_addr()? {
+            TargetAddr::Ip(addr) => addr.to_string(),
+            TargetAddr::Domain(domain, port) => format!("{}:{}", domain, port),
+        };
+        if let Some(local) = lookup_host(&local).await?.next() {
+            if let Some(proxy) = lookup_host(proxy)
+                .await?
+                .find(|x| x.is_ipv4() == local.is_ipv4())
+            {
+                let mut stream =
+                    super::timeout(ms_timeout, new_socket(local, true)?.connect(proxy)).await??;
+                stream.set_nodelay(true).ok();
+                super::timeout(
+                    ms_timeout,
+                    http_connect(&mut stream, &target, username, password),
+                )
+                .await??;
+                let addr = stream.local_addr()?;
+                return Ok(Self(
+                    Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
+                    addr,
+                    None,
+                    0,
+                    None,
+                ));
+            }
+        }
+        bail!("could not resolve to any address");
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.1
     }
@@ -157,12 +222,36 @@ impl FramedStream {
             addr,
             None,
             0,
+            None,
         )
     }
 
+    /// Move the framed stream onto another transport, e.g. quic. The key and the
+    /// sequence numbers are kept, so nonces are never reused across transports.
+    pub fn set_transport(
+        &mut self,
+        stream: impl TcpStreamTrait + Send + Sync + 'static,
+        addr: SocketAddr,
+    ) {
+        self.0 = Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new());
+        self.1 = addr;
+    }
+
+    /// Give up the framing, e.g. to run another protocol over the connection.
+    pub fn into_raw(self) -> DynTcpStream {
+        self.0.into_inner()
+    }
+
     pub fn set_raw(&mut self) {
         self.0.codec_mut().set_raw();
         self.2 = None;
+        // no messages any more
+        self.4 = None;
+    }
+
+    /// Record the decrypted messages in both directions from now on, see [`crate::capture`].
+    pub fn set_capture(&mut self, capture: Capture) {
+        self.4 = Some(capture);
     }
 
     pub fn is_secured(&self) -> bool {
@@ -177,10 +266,14 @@ impl FramedStream {
     #[inline]
     pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
         let mut msg = msg;
+        if let Some(capture) = self.4.as_mut() {
+            capture.record(Direction::Out, &msg);
+        }
         if let Some(key) = self.2.as_mut() {
-            key.1 += 1;
-            let nonce = Self::get_nonce(key.1);
-            msg = secretbox::seal(&msg, &nonce, &key.0);
+            key.send_seqnum += 1;
+            key.send_usage += msg.len() as u64;
+            let nonce = Self::get_nonce(key.send_seqnum);
+            msg = secretbox::seal(&msg, &nonce, &key.send_key);
         }
         self.send_bytes(bytes::Bytes::from(msg)).await?;
         Ok(())
@@ -201,9 +294,10 @@ impl FramedStream {
         let mut res = self.0.next().await;
         if let Some(key) = self.2.as_mut() {
             if let Some(Ok(bytes)) = res.as_mut() {
-                key.2 += 1;
-                let nonce = Self::get_nonce(key.2);
-                match secretbox::open(&bytes, &nonce, &key.0) {
+                key.recv_seqnum += 1;
+                key.recv_usage += bytes.len() as u64;
+                let nonce = Self::get_nonce(key.recv_seqnum);
+                match secretbox::open(&bytes, &nonce, &key.recv_key) {
                     Ok(res) => {
                         bytes.clear();
                         bytes.put_slice(&res);
@@ -214,6 +308,11 @@ impl FramedStream {
                 }
             }
         }
+        if let Some(capture) = self.4.as_mut() {
+            if let Some(Ok(bytes)) = res.as_ref() {
+                capture.record(Direction::In, bytes);
+            }
+        }
         res
     }
 
@@ -227,7 +326,41 @@ impl FramedStream {
     }
 
     pub fn set_key(&mut self, key: Key) {
-        self.2 = Some((key, 0, 0));
+        self.2 = Some(Encrypt {
+            send_key: key.clone(),
+            send_seqnum: 0,
+            recv_key: key,
+            recv_seqnum: 0,
+            send_usage: 0,
+            recv_usage: 0,
+        });
+    }
+
+    /// Replace the key of outgoing messages, only valid on a secured stream.
+    pub fn set_send_key(&mut self, key: Key) {
+        if let Some(encrypt) = self.2.as_mut() {
+            encrypt.send_key = key;
+            encrypt.send_seqnum = 0;
+            encrypt.send_usage = 0;
+        }
+    }
+
+    /// Replace the key of incoming messages, only valid on a secured stream.
+    pub fn set_recv_key(&mut self, key: Key) {
+        if let Some(encrypt) = self.2.as_mut() {
+            encrypt.recv_key = key;
+            encrypt.recv_seqnum = 0;
+            encrypt.recv_usage = 0;
+        }
+    }
+
+    /// Bytes under the more used of the current send and recv keys.
+    #[inline]
+    pub fn key_usage(&self) -> u64 {
+        self.2
+            .as_ref()
+            .map(|x| x.send_usage.max(x.recv_usage))
+            .unwrap_or(0)
     }
 
     fn get_nonce(seqnum: u64) -> Nonce {
@@ -237,6 +370,42 @@ impl FramedStream {
     }
 }
 
+const MAX_HTTP_HEADER_SIZE: usize = 8192;
+
+async fn http_connect(
+    stream: &mut TcpStream,
+    target: &str,
+    username: &str,
+    password: &str,
+) -> ResultType<()> {
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if !username.trim().is_empty() {
+        req += &format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(format!("{}:{}", username, password))
+        );
+    }
+    req += "\r\n";
+    stream.write_all(req.as_bytes()).await?;
+    // read byte by byte, anything after the header already belongs to the tunnel
+    let mut res = Vec::new();
+    let mut byte = [0u8; 1];
+    while !res.ends_with(b"\r\n\r\n") {
+        if res.len() >= MAX_HTTP_HEADER_SIZE {
+            bail!("HTTP proxy response too long");
+        }
+        stream.read_exact(&mut byte).await?;
+        res.push(byte[0]);
+    }
+    let res = String::from_utf8_lossy(&res);
+    let status_line = res.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        Some("407") => bail!("HTTP proxy authentication required"),
+        _ => bail!("HTTP proxy CONNECT failed: {}", status_line),
+    }
+}
+
 const DEFAULT_BACKLOG: u32 = 128;
 
 #[allow(clippy::never_loop)]
@@ -283,3 +452,49 @@ impl AsyncWrite for DynTcpStream {
 }
 
 impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_http() {
+        let listener = new_listener("127.0.0.1:0", false).await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut req = Vec::new();
+            let mut byte = [0u8; 1];
+            while !req.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).await.unwrap();
+                req.push(byte[0]);
+            }
+            let req = String::from_utf8(req).unwrap();
+            assert!(req.starts_with("CONNECT example.com:21116 HTTP/1.1\r\n"));
+            // user:pass
+            assert!(req.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            // echo the tunnel
+            let mut framed = Framed::new(stream, BytesCodec::new());
+            if let Some(Ok(bytes)) = framed.next().await {
+                framed.send(bytes.freeze()).await.unwrap();
+            }
+        });
+        let mut stream = FramedStream::connect_http(
+            &proxy,
+            "example.com:21116",
+            "127.0.0.1:0",
+            "user",
+            "pass",
+            3_000,
+        )
+        .await
+        .unwrap();
+        stream.send_raw(b"hello".to_vec()).await.unwrap();
+        let res = stream.next_timeout(3_000).await.unwrap().unwrap();
+        assert_eq!(&res[..], b"hello");
+    }
+}
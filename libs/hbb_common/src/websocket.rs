@@ -0,0 +1,338 @@
+use crate::{bail, socket_client, tcp::FramedStream, ResultType};
+use bytes::Bytes;
+use futures::{ready, Sink, Stream};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message as WsMessage},
+    WebSocketStream,
+};
+
+#[inline]
+pub fn is_ws_url(s: &str) -> bool {
+    let s = s.trim().to_lowercase();
+    s.starts_with("ws://") || s.starts_with("wss://")
+}
+
+/// The relay server used together with a websocket rendezvous server.
+///
+/// A relay server configured as a websocket url is used as is. Otherwise it is derived from the
+/// rendezvous url: the last path segment is replaced by `relay` if there is a path
+/// (`wss://example.com/ws/id` -> `wss://example.com/ws/relay`), else the port is increased
+/// by one (`ws://example.com:21118` -> `ws://example.com:21119`), else `/ws/relay` is appended.
+pub fn relay_url(rendezvous_server: &str, relay_server: &str) -> String {
+    if is_ws_url(relay_server) {
+        return relay_server.trim().to_owned();
+    }
+    let rendezvous_server = rendezvous_server.trim().trim_end_matches('/');
+    let (scheme, rest) = match rendezvous_server.find("://") {
+        Some(i) => rendezvous_server.split_at(i + 3),
+        None => return rendezvous_server.to_owned(),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if let Some(i) = path.rfind('/') {
+        return format!("{}{}{}/relay", scheme, authority, &path[..i]);
+    }
+    if let Some((host, port)) = authority.rsplit_once(':') {
+        if let Ok(port) = port.parse::<u16>() {
+            return format!("{}{}:{}", scheme, host, port.wrapping_add(1));
+        }
+    }
+    format!("{}{}/ws/relay", scheme, authority)
+}
+
+/// `host:port` of a websocket url, the port defaults to 80 for ws and 443 for wss.
+fn authority(url: &str) -> ResultType<String> {
+    let url = url.trim();
+    let (tls, rest) = match url.find("://") {
+        Some(i) if url[..i].eq_ignore_ascii_case("wss") => (true, &url[i + 3..]),
+        Some(i) if url[..i].eq_ignore_ascii_case("ws") => (false, &url[i + 3..]),
+        _ => bail!("Invalid websocket url: {}", url),
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty() {
+        bail!("Invalid websocket url: {}", url);
+    }
+    // ipv6 literals are bracketed, so a colon after the last bracket is the port
+    if host.rsplit(']').next().unwrap_or_default().contains(':') {
+        Ok(host.to_owned())
+    } else {
+        Ok(format!("{}:{}", host, if tls { 443 } else { 80 }))
+    }
+}
+
+/// Connect to a websocket server. The tcp connection goes through the configured proxy
+/// like any other, tls is added for wss.
+pub async fn connect(url: &str, local: SocketAddr, ms_timeout: u64) -> ResultType<FramedStream> {
+    let stream = socket_client::connect_tcp(authority(url)?, local, ms_timeout).await?;
+    let addr = stream.local_addr();
+    let (stream, _) = super::timeout(
+        ms_timeout,
+        tokio_tungstenite::client_async_tls(url.trim(), stream.into_raw()),
+    )
+    .await??;
+    Ok(FramedStream::from(WsStream::new(stream), addr))
+}
+
+/// Server side of the websocket handshake, used by the test servers.
+pub async fn accept(stream: TcpStream, ms_timeout: u64) -> ResultType<FramedStream> {
+    let addr = stream.local_addr()?;
+    let stream = super::timeout(ms_timeout, tokio_tungstenite::accept_async(stream)).await??;
+    Ok(FramedStream::from(WsStream::new(stream), addr))
+}
+
+/// Byte stream over the binary messages of a websocket, so that [`FramedStream`]
+/// can run its own framing and encryption on top of it.
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Bytes,
+}
+
+impl<S> WsStream<S> {
+    fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read_buf: Bytes::new(),
+        }
+    }
+}
+
+fn to_io_error(err: WsError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.read_buf.is_empty() {
+                let n = std::cmp::min(buf.remaining(), self.read_buf.len());
+                let data = self.read_buf.split_to(n);
+                buf.put_slice(&data);
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => self.read_buf = Bytes::from(data),
+                Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(Ok(())),
+                // ping/pong are answered by tungstenite, text is not used
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(to_io_error(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Err(err) = ready!(Pin::new(&mut self.inner).poll_ready(cx)) {
+            return Poll::Ready(Err(to_io_error(err)));
+        }
+        match Pin::new(&mut self.inner).start_send(WsMessage::Binary(buf.to_vec())) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(err) => Poll::Ready(Err(to_io_error(err))),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(to_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config, protobuf::Message as _, rendezvous_proto::*, tcp::new_listener,
+        tokio::sync::Mutex, AddrMangle,
+    };
+    use std::{collections::HashMap, sync::Arc};
+
+    #[test]
+    fn test_authority() {
+        assert_eq!(
+            authority("ws://example.com/ws/id").unwrap(),
+            "example.com:80"
+        );
+        assert_eq!(authority("wss://example.com").unwrap(), "example.com:443");
+        assert_eq!(authority("ws://1.2.3.4:21118").unwrap(), "1.2.3.4:21118");
+        assert_eq!(authority("wss://[::1]/ws/id").unwrap(), "[::1]:443");
+        assert!(authority("http://example.com").is_err());
+    }
+
+    #[test]
+    fn test_relay_url() {
+        assert_eq!(
+            relay_url("wss://example.com/ws/id", ""),
+            "wss://example.com/ws/relay"
+        );
+        assert_eq!(
+            relay_url("ws://example.com:21118", "example.com:21117"),
+            "ws://example.com:21119"
+        );
+        assert_eq!(
+            relay_url("wss://example.com", ""),
+            "wss://example.com/ws/relay"
+        );
+        assert_eq!(
+            relay_url("wss://example.com/ws/id", "ws://relay.example.com:80"),
+            "ws://relay.example.com:80"
+        );
+    }
+
+    async fn next_msg(stream: &mut FramedStream) -> RendezvousMessage {
+        let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+        RendezvousMessage::parse_from_bytes(&bytes).unwrap()
+    }
+
+    /// Stand-in of the rendezvous and relay servers, listening websocket only.
+    async fn run_server(
+        stream: TcpStream,
+        relay_server: String,
+        relays: Arc<Mutex<HashMap<String, FramedStream>>>,
+    ) {
+        let mut stream = accept(stream, 3_000).await.unwrap();
+        while let Some(Ok(bytes)) = stream.next().await {
+            let msg_in = RendezvousMessage::parse_from_bytes(&bytes).unwrap();
+            let mut msg_out = RendezvousMessage::new();
+            match msg_in.union {
+                Some(rendezvous_message::Union::RegisterPeer(rp)) => {
+                    assert_eq!(rp.id, "123456789");
+                    msg_out.set_register_peer_response(RegisterPeerResponse::new());
+                }
+                Some(rendezvous_message::Union::PunchHoleRequest(ph)) => {
+                    assert_eq!(ph.id, "123456789");
+                    msg_out.set_punch_hole_response(PunchHoleResponse {
+                        socket_addr: AddrMangle::encode("1.2.3.4:5".parse().unwrap()).into(),
+                        relay_server: relay_server.clone(),
+                        ..Default::default()
+                    });
+                }
+                Some(rendezvous_message::Union::RequestRelay(rr)) => {
+                    let mut relays = relays.lock().await;
+                    if let Some(mut peer) = relays.remove(&rr.uuid) {
+                        drop(relays);
+                        loop {
+                            crate::tokio::select! {
+                                Some(Ok(bytes)) = stream.next() => {
+                                    peer.send_bytes(bytes.into()).await.unwrap();
+                                }
+                                Some(Ok(bytes)) = peer.next() => {
+                                    stream.send_bytes(bytes.into()).await.unwrap();
+                                }
+                                else => break,
+                            }
+                        }
+                    } else {
+                        relays.insert(rr.uuid, stream);
+                    }
+                    return;
+                }
+                _ => panic!("unexpected message"),
+            }
+            stream.send(&msg_out).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_punch_relay_over_ws() {
+        let listener = new_listener("127.0.0.1:0", false).await.unwrap();
+        let rendezvous_server = format!("ws://{}/ws/id", listener.local_addr().unwrap());
+        let relay_server = relay_url(&rendezvous_server, "");
+        let relays: Arc<Mutex<HashMap<String, FramedStream>>> = Default::default();
+        let relay_server_cloned = relay_server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(run_server(
+                    stream,
+                    relay_server_cloned.clone(),
+                    relays.clone(),
+                ));
+            }
+        });
+        let any_addr = Config::get_any_listen_addr();
+
+        // register
+        let mut host = socket_client::connect_server(&rendezvous_server, any_addr, 3_000)
+            .await
+            .unwrap();
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_register_peer(RegisterPeer {
+            id: "123456789".to_owned(),
+            ..Default::default()
+        });
+        host.send(&msg_out).await.unwrap();
+        assert!(matches!(
+            next_msg(&mut host).await.union,
+            Some(rendezvous_message::Union::RegisterPeerResponse(_))
+        ));
+
+        // punch
+        let mut client = socket_client::connect_server(&rendezvous_server, any_addr, 3_000)
+            .await
+            .unwrap();
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_punch_hole_request(PunchHoleRequest {
+            id: "123456789".to_owned(),
+            ..Default::default()
+        });
+        client.send(&msg_out).await.unwrap();
+        let relay = match next_msg(&mut client).await.union {
+            Some(rendezvous_message::Union::PunchHoleResponse(ph)) => ph.relay_server,
+            _ => panic!("expecting punch hole response"),
+        };
+        assert_eq!(relay, relay_server);
+
+        // relay
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_request_relay(RequestRelay {
+            uuid: "uuid".to_owned(),
+            ..Default::default()
+        });
+        let mut host_relay = socket_client::connect_server(&relay, any_addr, 3_000)
+            .await
+            .unwrap();
+        host_relay.send(&msg_out).await.unwrap();
+        // make sure the host side is waiting before the client arrives
+        crate::sleep(0.1).await;
+        let mut client_relay = socket_client::connect_server(&relay, any_addr, 3_000)
+            .await
+            .unwrap();
+        client_relay.send(&msg_out).await.unwrap();
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_relay_response(RelayResponse {
+            uuid: "hello".to_owned(),
+            ..Default::default()
+        });
+        client_relay.send(&msg_out).await.unwrap();
+        match next_msg(&mut host_relay).await.union {
+            Some(rendezvous_message::Union::RelayResponse(rr)) => assert_eq!(rr.uuid, "hello"),
+            _ => panic!("expecting relay response"),
+        }
+    }
+}
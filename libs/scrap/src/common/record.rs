@@ -32,6 +32,8 @@ pub enum RecordCodecID {
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
     pub id: String,
+    /// Directory of the file, the option "video-save-directory" or `default_dir` if empty.
+    pub dir: String,
     pub default_dir: String,
     pub filename: String,
     pub width: usize,
@@ -41,7 +43,10 @@ pub struct RecorderContext {
 
 impl RecorderContext {
     pub fn set_filename(&mut self) -> ResultType<()> {
-        let mut dir = Config::get_option("video-save-directory");
+        let mut dir = self.dir.clone();
+        if dir.is_empty() {
+            dir = Config::get_option("video-save-directory");
+        }
         if !dir.is_empty() {
             if !PathBuf::from(&dir).exists() {
                 std::fs::create_dir_all(&dir)?;
@@ -126,6 +131,11 @@ impl Recorder {
         Ok(())
     }
 
+    /// The file being written, which changes with the codec.
+    pub fn filename(&self) -> &str {
+        &self.ctx.filename
+    }
+
     pub fn write_message(&mut self, msg: &Message) {
         if let Some(message::Union::VideoFrame(vf)) = &msg.union {
             if let Some(frame) = &vf.union {
@@ -1,4 +1,5 @@
 use crate::client::*;
+use crate::totp::LOGIN_MSG_2FA_REQUIRED;
 use hbb_common::{
     config::PeerConfig,
     log,
@@ -7,7 +8,10 @@ use hbb_common::{
     tokio::{self, sync::mpsc},
     Stream,
 };
-use std::sync::{Arc, RwLock};
+use std::{
+    io::Write,
+    sync::{Arc, RwLock},
+};
 
 #[derive(Clone)]
 pub struct Session {
@@ -15,10 +19,12 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    tfa_code: String,
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
+    /// `tfa_code` is sent if the peer asks for a 2FA code, which is prompted for if empty.
+    pub fn new(id: &str, tfa_code: String, sender: mpsc::UnboundedSender<Data>) -> Self {
         let mut password = "".to_owned();
         if PeerConfig::load(id).password.is_empty() {
             password = rpassword::prompt_password("Enter password: ").unwrap();
@@ -27,6 +33,7 @@ impl Session {
             id: id.to_owned(),
             sender,
             password,
+            tfa_code,
             lc: Default::default(),
         };
         session
@@ -38,6 +45,14 @@ impl Session {
     }
 }
 
+fn prompt(text: &str) -> String {
+    print!("{}", text);
+    std::io::stdout().flush().ok();
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).ok();
+    line.trim().to_owned()
+}
+
 #[async_trait]
 impl Interface for Session {
     fn msgbox(&self, msgtype: &str, title: &str, text: &str) {
@@ -49,6 +64,14 @@ impl Interface for Session {
             log::error!("{}: {}", title, text);
             let pass = rpassword::prompt_password("Enter password: ").unwrap();
             self.sender.send(Data::Login((pass, true))).ok();
+        } else if msgtype == "input-2fa" {
+            let code = if title == LOGIN_MSG_2FA_REQUIRED && !self.tfa_code.is_empty() {
+                self.tfa_code.clone()
+            } else {
+                log::error!("{}", title);
+                prompt("Enter 2FA code: ")
+            };
+            self.sender.send(Data::Login2fa(code)).ok();
         } else if msgtype.contains("error") {
             log::error!("{}: {}: {}", msgtype, title, text);
         } else {
@@ -73,6 +96,10 @@ impl Interface for Session {
         handle_login_from_ui(self.lc.clone(), password, remember, peer).await;
     }
 
+    async fn handle_login_2fa(&mut self, code: String, peer: &mut Stream) {
+        handle_login_2fa(self.lc.clone(), code, peer).await;
+    }
+
     async fn handle_test_delay(&mut self, t: TestDelay, peer: &mut Stream) {
         handle_test_delay(t, peer).await;
     }
@@ -90,11 +117,12 @@ pub async fn start_one_port_forward(
     remote_port: i32,
     key: String,
     token: String,
+    tfa_code: String,
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, tfa_code, sender);
     handler.lc.write().unwrap().port_forward = (remote_host, remote_port);
     if let Err(err) =
         crate::port_forward::listen(handler.id.clone(), port, handler.clone(), receiver, &key, &token).await
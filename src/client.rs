@@ -19,21 +19,23 @@ pub use file_trait::FileManager;
 use hbb_common::{
     allow_err,
     anyhow::{anyhow, Context},
-    bail,
+    bail, capability,
     config::{
-        Config, PeerConfig, PeerInfoSerde, CONNECT_TIMEOUT, READ_TIMEOUT, RELAY_PORT,
-        RENDEZVOUS_TIMEOUT,
+        authorized_keys, Config, PeerConfig, PeerInfoSerde, CONNECT_TIMEOUT, READ_TIMEOUT,
+        RELAY_PORT, RENDEZVOUS_TIMEOUT,
     },
     log,
     message_proto::{option_message::BoolOption, *},
+    mux::Mux,
+    password_security,
     protobuf::Message as _,
     rand,
     rendezvous_proto::*,
-    socket_client,
+    secret_store, socket_client,
     sodiumoxide::crypto::{box_, secretbox, sign},
     timeout,
     tokio::time::Duration,
-    AddrMangle, ResultType, Stream,
+    websocket, AddrMangle, ResultType, Stream,
 };
 pub use helper::LatencyController;
 pub use helper::*;
@@ -50,6 +52,7 @@ pub mod helper;
 pub mod io_loop;
 use crate::{
     server::video_service::{SCRAP_X11_REF_URL, SCRAP_X11_REQUIRED},
+    totp::{LOGIN_MSG_2FA_REQUIRED, LOGIN_MSG_2FA_WRONG},
     ui_session_interface::global_save_keyboard_mode,
 };
 pub static SERVER_KEYBOARD_ENABLED: AtomicBool = AtomicBool::new(true);
@@ -57,6 +60,8 @@ pub static SERVER_FILE_TRANSFER_ENABLED: AtomicBool = AtomicBool::new(true);
 pub static SERVER_CLIPBOARD_ENABLED: AtomicBool = AtomicBool::new(true);
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
+#[cfg(feature = "quic")]
+const QUIC_CONNECT_TIMEOUT: u64 = 3_000;
 
 /// Client of the remote desktop.
 pub struct Client;
@@ -66,6 +71,10 @@ lazy_static::lazy_static! {
     static ref AUDIO_HOST: Host = cpal::default_host();
 }
 
+lazy_static::lazy_static! {
+    static ref CHANNEL_HOSTS: Mutex<HashMap<String, ChannelHost>> = Default::default();
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 lazy_static::lazy_static! {
     static ref ENIGO: Arc<Mutex<enigo::Enigo>> = Arc::new(Mutex::new(enigo::Enigo::new()));
@@ -185,12 +194,12 @@ impl Client {
         }
         let (mut rendezvous_server, servers, contained) = crate::get_rendezvous_server(1_000).await;
         let mut socket =
-            socket_client::connect_tcp(&*rendezvous_server, any_addr, RENDEZVOUS_TIMEOUT).await;
+            socket_client::connect_server(&rendezvous_server, any_addr, RENDEZVOUS_TIMEOUT).await;
         debug_assert!(!servers.contains(&rendezvous_server));
         if socket.is_err() && !servers.is_empty() {
             log::info!("try the other servers: {:?}", servers);
             for server in servers {
-                socket = socket_client::connect_tcp(&*server, any_addr, RENDEZVOUS_TIMEOUT).await;
+                socket = socket_client::connect_server(&server, any_addr, RENDEZVOUS_TIMEOUT).await;
                 if socket.is_ok() {
                     rendezvous_server = server;
                     break;
@@ -203,6 +212,8 @@ impl Client {
         log::info!("rendezvous server: {}", rendezvous_server);
         let mut socket = socket?;
         let my_addr = socket.local_addr();
+        // behind a websocket only gateway the peer can be reached via relay only
+        let ws = websocket::is_ws_url(&rendezvous_server);
         let mut signed_id_pk = Vec::new();
         let mut relay_server = "".to_owned();
 
@@ -215,7 +226,7 @@ impl Client {
             log::info!("#{} punch attempt with {}, id: {}", i, my_addr, peer);
             let mut msg_out = RendezvousMessage::new();
             use hbb_common::protobuf::Enum;
-            let nat_type = if interface.is_force_relay() {
+            let nat_type = if interface.is_force_relay() || ws {
                 NatType::SYMMETRIC
             } else {
                 NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT)
@@ -255,7 +266,11 @@ impl Client {
                                 peer_nat_type = ph.nat_type();
                                 is_local = ph.is_local();
                                 signed_id_pk = ph.pk.into();
-                                relay_server = ph.relay_server;
+                                relay_server = if ws {
+                                    websocket::relay_url(&rendezvous_server, &ph.relay_server)
+                                } else {
+                                    ph.relay_server
+                                };
                                 peer_addr = AddrMangle::decode(&ph.socket_addr);
                                 log::info!("Hole Punched {} = {}", peer, peer_addr);
                                 break;
@@ -268,8 +283,13 @@ impl Client {
                                 rr.relay_server
                             );
                             signed_id_pk = rr.pk().into();
+                            let relay_server = if ws {
+                                websocket::relay_url(&rendezvous_server, &rr.relay_server)
+                            } else {
+                                rr.relay_server
+                            };
                             let mut conn =
-                                Self::create_relay(peer, rr.uuid, rr.relay_server, key, conn_type)
+                                Self::create_relay(peer, rr.uuid, relay_server, key, conn_type)
                                     .await?;
                             Self::secure_connection(
                                 peer,
@@ -377,7 +397,12 @@ impl Client {
         log::info!("peer address: {}, timeout: {}", peer, connect_timeout);
         let start = std::time::Instant::now();
         // NOTICE: Socks5 is be used event in intranet. Which may be not a good way.
-        let mut conn = socket_client::connect_tcp(peer, local_addr, connect_timeout).await;
+        let ws = websocket::is_ws_url(rendezvous_server);
+        let mut conn = if ws {
+            Err(anyhow!("Direct connection is not available over websocket"))
+        } else {
+            socket_client::connect_tcp(peer, local_addr, connect_timeout).await
+        };
         let mut direct = !conn.is_err();
         if interface.is_force_relay() || conn.is_err() {
             if !relay_server.is_empty() {
@@ -402,7 +427,7 @@ impl Client {
                 bail!("Failed to make direct connection to remote desktop");
             }
         }
-        if !relay_server.is_empty() && (direct_failures == 0) != direct {
+        if !ws && !relay_server.is_empty() && (direct_failures == 0) != direct {
             let mut config = PeerConfig::load(peer_id);
             config.direct_failures = if direct { 0 } else { 1 };
             log::info!("direct_failures updated to {}", config.direct_failures);
@@ -410,11 +435,59 @@ impl Client {
         }
         let mut conn = conn?;
         log::info!("{:?} used to establish connection", start.elapsed());
-        Self::secure_connection(peer_id, signed_id_pk, key, &mut conn, direct, interface).await?;
+        #[allow(unused_variables)]
+        let quic_port =
+            Self::secure_connection(peer_id, signed_id_pk, key, &mut conn, direct, interface)
+                .await?;
+        #[cfg(feature = "quic")]
+        if quic_port > 0 {
+            Self::upgrade_to_quic(&mut conn, SocketAddr::new(peer.ip(), quic_port as _)).await?;
+        }
         Ok((conn, direct))
     }
 
+    /// Try to move the session onto the quic endpoint of the peer, and tell the peer
+    /// whether we did. The session stays on tcp if quic is not reachable.
+    #[cfg(feature = "quic")]
+    async fn upgrade_to_quic(conn: &mut Stream, peer: SocketAddr) -> ResultType<()> {
+        let local_addr = if peer.is_ipv4() {
+            Config::get_any_listen_addr()
+        } else {
+            SocketAddr::new(std::net::Ipv6Addr::UNSPECIFIED.into(), 0)
+        };
+        let quic = match timeout(
+            QUIC_CONNECT_TIMEOUT,
+            hbb_common::quic::new_client(local_addr, peer),
+        )
+        .await
+        {
+            Ok(Ok(quic)) => Some(quic),
+            Ok(Err(err)) => {
+                log::info!("Failed to connect quic {}: {}", peer, err);
+                None
+            }
+            Err(_) => {
+                log::info!("Timeout to connect quic {}", peer);
+                None
+            }
+        };
+        let mut msg_out = Message::new();
+        msg_out.set_quic_upgrade(QuicUpgrade {
+            switched: quic.is_some(),
+            ..Default::default()
+        });
+        timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+        if let Some(quic) = quic {
+            log::info!("Connection to {} upgraded to quic", peer);
+            let local_addr = quic.local_addr();
+            conn.set_transport(quic, local_addr);
+        }
+        Ok(())
+    }
+
     /// Establish secure connection with the server.
+    ///
+    /// Returns the quic port of the server if we asked to continue over quic, otherwise 0.
     async fn secure_connection(
         peer_id: &str,
         signed_id_pk: Vec<u8>,
@@ -422,7 +495,7 @@ impl Client {
         conn: &mut Stream,
         direct: bool,
         mut interface: impl Interface,
-    ) -> ResultType<()> {
+    ) -> ResultType<u32> {
         let rs_pk = get_rs_pk(if key.is_empty() {
             hbb_common::config::RS_PUB_KEY
         } else {
@@ -439,14 +512,20 @@ impl Client {
                 log::error!("Handshake failed: invalid public key from rendezvous server");
             }
         }
+        let pk = sign_pk
+            .as_ref()
+            .map(|x| base64::encode(&x.0[..]))
+            .unwrap_or_default();
+        PeerConfig::check_key(peer_id, &pk)?;
         let sign_pk = match sign_pk {
             Some(v) => v,
             None => {
                 // send an empty message out in case server is setting up secure and waiting for first message
                 conn.send(&Message::new()).await?;
-                return Ok(());
+                return Ok(0);
             }
         };
+        let mut quic_port = 0;
         match timeout(READ_TIMEOUT, conn.next()).await? {
             Some(res) => {
                 let bytes = match res {
@@ -465,14 +544,19 @@ impl Client {
                                 let key = secretbox::gen_key();
                                 let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
                                 let sealed_key = box_::seal(&key.0, &nonce, &their_pk_b, &out_sk_b);
+                                let try_quic = cfg!(feature = "quic") && direct && si.quic_port > 0;
                                 let mut msg_out = Message::new();
                                 msg_out.set_public_key(PublicKey {
                                     asymmetric_value: Vec::from(our_pk_b.0).into(),
                                     symmetric_value: sealed_key.into(),
+                                    try_quic,
                                     ..Default::default()
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
                                 conn.set_key(key);
+                                if try_quic {
+                                    quic_port = si.quic_port;
+                                }
                             } else {
                                 log::error!("Handshake failed: sign failure");
                                 conn.send(&Message::new()).await?;
@@ -497,7 +581,7 @@ impl Client {
                 bail!("Reset by the peer");
             }
         }
-        Ok(())
+        Ok(quic_port)
     }
 
     /// Request a relay connection to the server.
@@ -516,7 +600,7 @@ impl Client {
         for i in 1..=3 {
             // use different socket due to current hbbs implement requiring different nat address for each attempt
             let mut socket =
-                socket_client::connect_tcp(rendezvous_server, any_addr, RENDEZVOUS_TIMEOUT)
+                socket_client::connect_server(rendezvous_server, any_addr, RENDEZVOUS_TIMEOUT)
                     .await
                     .with_context(|| "Failed to connect to rendezvous server")?;
 
@@ -565,8 +649,8 @@ impl Client {
         key: &str,
         conn_type: ConnType,
     ) -> ResultType<Stream> {
-        let mut conn = socket_client::connect_tcp(
-            crate::check_port(relay_server, RELAY_PORT),
+        let mut conn = socket_client::connect_server(
+            &crate::check_port(relay_server, RELAY_PORT),
             Config::get_any_listen_addr(),
             CONNECT_TIMEOUT,
         )
@@ -585,6 +669,60 @@ impl Client {
     }
 }
 
+/// A remote control session other sessions to the same peer can open channels in.
+struct ChannelHost {
+    mux: Mux,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    secured: bool,
+    direct: bool,
+}
+
+/// Make the remote control session to `id` available for [`open_channel`].
+pub fn register_channel_host(
+    id: &str,
+    mux: Mux,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    secured: bool,
+    direct: bool,
+) {
+    CHANNEL_HOSTS.lock().unwrap().insert(
+        id.to_owned(),
+        ChannelHost {
+            mux,
+            lc,
+            secured,
+            direct,
+        },
+    );
+}
+
+pub fn unregister_channel_host(id: &str, mux: &Mux) {
+    let mut hosts = CHANNEL_HOSTS.lock().unwrap();
+    // a newer session to the same peer may have replaced it already
+    if hosts.get(id).map(|x| x.mux.ptr_eq(mux)) == Some(true) {
+        hosts.remove(id);
+    }
+}
+
+/// Open a channel in the remote control session to `id`, so that no new connection
+/// and login are needed. Returns the stream with whether the session is secured and direct,
+/// or `None` if there is no such session or the peer does not support channels.
+pub fn open_channel(id: &str, open: ChannelOpen) -> Option<(Stream, bool, bool)> {
+    let hosts = CHANNEL_HOSTS.lock().unwrap();
+    let host = hosts.get(id)?;
+    if !host
+        .lc
+        .read()
+        .unwrap()
+        .peer_capabilities()
+        .has_session(capability::SESSION_CHANNELS)
+    {
+        return None;
+    }
+    log::info!("Open channel in the session to {}", id);
+    Some((host.mux.open(open), host.secured, host.direct))
+}
+
 /// Audio handler for the [`Client`].
 #[derive(Default)]
 pub struct AudioHandler {
@@ -863,6 +1001,7 @@ impl VideoHandler {
         if start {
             self.recorder = Recorder::new(RecorderContext {
                 id,
+                dir: "".to_owned(),
                 default_dir: crate::ui_interface::default_video_save_directory(),
                 filename: "".to_owned(),
                 width: w as _,
@@ -884,12 +1023,16 @@ pub struct LoginConfigHandler {
     pub conn_type: ConnType,
     hash: Hash,
     password: Vec<u8>, // remember password for reconnect
+    /// Of the last login, sent again with the 2FA code.
+    login_hash: Vec<u8>,
+    tfa_code: String,
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
     pub version: i64,
     pub conn_id: i32,
     features: Option<Features>,
+    peer_capabilities: Capabilities,
     session_id: u64,
     pub supported_encoding: Option<(bool, bool)>,
     pub restarting_remote_device: bool,
@@ -925,7 +1068,7 @@ impl LoginConfigHandler {
         self.id = id;
         self.conn_type = conn_type;
         let config = self.load_config();
-        self.remember = !config.password.is_empty();
+        self.remember = config.has_password();
         self.config = config;
         self.session_id = rand::random();
         self.supported_encoding = None;
@@ -1047,13 +1190,11 @@ impl LoginConfigHandler {
         } else if name == "show-quality-monitor" {
             config.show_quality_monitor = !config.show_quality_monitor;
         } else {
-            let v = self.options.get(&name).is_some();
-            if v {
-                self.config.options.remove(&name);
-            } else {
-                self.config.options.insert(name, "Y".to_owned());
+            // not self.config, the pinned key may have changed since it was loaded
+            if config.options.remove(&name).is_none() {
+                config.options.insert(name, "Y".to_owned());
             }
-            self.config.store(&self.id);
+            self.save_config(config);
             return None;
         }
         if !name.contains("block-input") {
@@ -1192,6 +1333,12 @@ impl LoginConfigHandler {
         }
     }
 
+    /// Capabilities of the peer, valid once its peer info is received.
+    #[inline]
+    pub fn peer_capabilities(&self) -> &Capabilities {
+        &self.peer_capabilities
+    }
+
     pub fn is_privacy_mode_supported(&self) -> bool {
         if let Some(features) = &self.features {
             features.privacy_mode
@@ -1268,6 +1415,9 @@ impl LoginConfigHandler {
             self.password = Default::default();
             interface.msgbox("re-input-password", err, "Do you want to enter again?", "");
             true
+        } else if err == LOGIN_MSG_2FA_REQUIRED || err == LOGIN_MSG_2FA_WRONG {
+            interface.msgbox("input-2fa", err, "", "");
+            true
         } else {
             if err.contains(SCRAP_X11_REQUIRED) {
                 interface.msgbox("error", "Login Error", err, SCRAP_X11_REF_URL);
@@ -1302,7 +1452,11 @@ impl LoginConfigHandler {
         if !pi.version.is_empty() {
             self.version = hbb_common::get_version_number(&pi.version);
         }
-        if hbb_common::get_version_number(&pi.version) < hbb_common::get_version_number("1.2.0") {
+        self.peer_capabilities = pi.get_capabilities();
+        if !self
+            .peer_capabilities
+            .has_input(capability::INPUT_KEYBOARD_MAP)
+        {
             global_save_keyboard_mode("legacy".to_owned());
         }
         self.features = pi.features.clone().into_option();
@@ -1322,8 +1476,8 @@ impl LoginConfigHandler {
                 log::debug!("remember password of {}", self.id);
             }
         } else {
-            if !password0.is_empty() {
-                config.password = Default::default();
+            if config.has_password() {
+                config.forget_password();
                 log::debug!("remove password of {}", self.id);
             }
         }
@@ -1355,6 +1509,25 @@ impl LoginConfigHandler {
         serde_json::to_string::<HashMap<String, String>>(&x).unwrap_or_default()
     }
 
+    fn get_file_transfer(&self) -> FileTransfer {
+        FileTransfer {
+            dir: self.get_remote_dir(),
+            show_hidden: !self.get_option("remote_show_hidden").is_empty(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a [`ChannelOpen`] to run this session in a channel of another one,
+    /// see [`open_channel`].
+    pub fn create_channel_open(&self) -> Option<ChannelOpen> {
+        let mut open = ChannelOpen::new();
+        match self.conn_type {
+            ConnType::FILE_TRANSFER => open.set_file_transfer(self.get_file_transfer()),
+            _ => return None,
+        }
+        Some(open)
+    }
+
     /// Create a [`Message`] for login.
     fn create_login_msg(&self, password: Vec<u8>) -> Message {
         #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1373,14 +1546,16 @@ impl LoginConfigHandler {
             option: self.get_option_message(true).into(),
             session_id: self.session_id,
             version: crate::VERSION.to_string(),
+            tfa_code: self.tfa_code.clone(),
             ..Default::default()
         };
+        let (sk, pk) = Config::get_key_pair();
+        lr.client_signature = authorized_keys::sign(&sk, &self.hash.challenge, &self.id).into();
+        if !lr.client_signature.is_empty() {
+            lr.client_key = pk.into();
+        }
         match self.conn_type {
-            ConnType::FILE_TRANSFER => lr.set_file_transfer(FileTransfer {
-                dir: self.get_remote_dir(),
-                show_hidden: !self.get_option("remote_show_hidden").is_empty(),
-                ..Default::default()
-            }),
+            ConnType::FILE_TRANSFER => lr.set_file_transfer(self.get_file_transfer()),
             ConnType::PORT_FORWARD => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
@@ -1388,6 +1563,15 @@ impl LoginConfigHandler {
             }),
             _ => {}
         }
+        let mut capabilities = crate::common::get_capabilities(false, &self.id);
+        if self.conn_type == ConnType::PORT_FORWARD || self.conn_type == ConnType::RDP {
+            // port forwarding leaves the protobuf framing after login,
+            // so never rekeyed or multiplexed
+            capabilities
+                .session
+                .retain(|x| x != capability::SESSION_REKEY && x != capability::SESSION_CHANNELS);
+        }
+        lr.capabilities = Some(capabilities).into();
 
         let mut msg_out = Message::new();
         msg_out.set_login_request(lr);
@@ -1627,14 +1811,12 @@ pub async fn handle_hash(
     interface: &impl Interface,
     peer: &mut Stream,
 ) {
+    // signed in the login
+    lc.write().unwrap().hash = hash.clone();
     let mut password = lc.read().unwrap().password.clone();
     if password.is_empty() {
         if !password_preset.is_empty() {
-            let mut hasher = Sha256::new();
-            hasher.update(password_preset);
-            hasher.update(&hash.salt);
-            let res = hasher.finalize();
-            password = res[..].into();
+            password = password_key(password_preset, &hash);
         }
     }
     if password.is_empty() {
@@ -1643,14 +1825,36 @@ pub async fn handle_hash(
     if password.is_empty() {
         // login without password, the remote side can click accept
         send_login(lc.clone(), Vec::new(), peer).await;
-        interface.msgbox("input-password", "Password Required", "", "");
+        let text = if lc.read().unwrap().config.is_password_locked() {
+            secret_store::Locked.to_string()
+        } else {
+            "".to_owned()
+        };
+        interface.msgbox("input-password", "Password Required", &text, "");
     } else {
         let mut hasher = Sha256::new();
         hasher.update(&password);
         hasher.update(&hash.challenge);
         send_login(lc.clone(), hasher.finalize()[..].into(), peer).await;
     }
-    lc.write().unwrap().hash = hash;
+}
+
+/// What `password` is hashed into before the challenge of `hash`, and remembered as:
+/// its argon2 key if the peer gives the parameters, else sha256(password + salt).
+fn password_key(password: &str, hash: &Hash) -> Vec<u8> {
+    if hash.kdf.is_empty() {
+        let mut hasher = Sha256::new();
+        hasher.update(password);
+        hasher.update(&hash.salt);
+        return hasher.finalize()[..].into();
+    }
+    match password_security::derive_password_key(password, &hash.kdf) {
+        Ok(key) => key,
+        Err(err) => {
+            log::error!("Failed to derive the password key: {}", err);
+            Vec::new()
+        }
+    }
 }
 
 /// Send login message to peer.
@@ -1661,7 +1865,14 @@ pub async fn handle_hash(
 /// * `password` - Password.
 /// * `peer` - [`Stream`] for communicating with peer.
 async fn send_login(lc: Arc<RwLock<LoginConfigHandler>>, password: Vec<u8>, peer: &mut Stream) {
-    let msg_out = lc.read().unwrap().create_login_msg(password);
+    let msg_out = {
+        let mut lc = lc.write().unwrap();
+        lc.login_hash = password.clone();
+        let msg_out = lc.create_login_msg(password);
+        // a code is valid once only
+        lc.tfa_code.clear();
+        msg_out
+    };
     allow_err!(peer.send(&msg_out).await);
 }
 
@@ -1679,18 +1890,36 @@ pub async fn handle_login_from_ui(
     remember: bool,
     peer: &mut Stream,
 ) {
-    let mut hasher = Sha256::new();
-    hasher.update(password);
-    hasher.update(&lc.read().unwrap().hash.salt);
-    let res = hasher.finalize();
+    let res = password_key(&password, &lc.read().unwrap().hash);
     lc.write().unwrap().remember = remember;
-    lc.write().unwrap().password = res[..].into();
+    lc.write().unwrap().password = res.clone();
     let mut hasher2 = Sha256::new();
     hasher2.update(&res[..]);
     hasher2.update(&lc.read().unwrap().hash.challenge);
     send_login(lc.clone(), hasher2.finalize()[..].into(), peer).await;
 }
 
+/// Handle the code of the two-factor authentication entered in the ui, sending it along
+/// with the password of the last login.
+///
+/// # Arguments
+///
+/// * `lc` - Login config.
+/// * `code` - The code shown by the authenticator app.
+/// * `peer` - [`Stream`] for communicating with peer.
+pub async fn handle_login_2fa(
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    code: String,
+    peer: &mut Stream,
+) {
+    let password = {
+        let mut lc = lc.write().unwrap();
+        lc.tfa_code = code;
+        lc.login_hash.clone()
+    };
+    send_login(lc, password, peer).await;
+}
+
 /// Interface for client to send data and commands.
 #[async_trait]
 pub trait Interface: Send + Clone + 'static + Sized {
@@ -1709,6 +1938,7 @@ pub trait Interface: Send + Clone + 'static + Sized {
     fn is_force_relay(&self) -> bool;
     async fn handle_hash(&mut self, pass: &str, hash: Hash, peer: &mut Stream);
     async fn handle_login_from_ui(&mut self, password: String, remember: bool, peer: &mut Stream);
+    async fn handle_login_2fa(&mut self, code: String, peer: &mut Stream);
     async fn handle_test_delay(&mut self, t: TestDelay, peer: &mut Stream);
 }
 
@@ -1717,6 +1947,7 @@ pub trait Interface: Send + Clone + 'static + Sized {
 pub enum Data {
     Close,
     Login((String, bool)),
+    Login2fa(String),
     Message(Message),
     SendFiles((i32, String, String, i32, bool, bool)),
     RemoveDirAll((i32, String, bool, bool)),
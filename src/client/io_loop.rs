@@ -11,8 +11,7 @@ use crate::{client::Data, client::Interface};
 
 use hbb_common::config::{PeerConfig, TransferSerde};
 use hbb_common::fs::{
-    can_enable_overwrite_detection, get_job, get_string, new_send_confirm, DigestCheckResult,
-    RemoveJobMeta, TransferJobMeta,
+    get_job, get_string, new_send_confirm, DigestCheckResult, RemoveJobMeta, TransferJobMeta,
 };
 use hbb_common::message_proto::permission_info::Permission;
 use hbb_common::protobuf::Message as _;
@@ -23,7 +22,15 @@ use hbb_common::tokio::{
     time::{self, Duration, Instant, Interval},
 };
 use hbb_common::{allow_err, message_proto::*, sleep};
-use hbb_common::{fs, log, Stream};
+use hbb_common::{
+    capability,
+    capture::{self, Capture},
+    compress::{self, Kind},
+    fs, log,
+    mux::Mux,
+    rekey::KeyRotation,
+    ResultType, Stream,
+};
 use std::collections::HashMap;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -47,6 +54,9 @@ pub struct Remote<T: InvokeUiSession> {
     data_count: Arc<AtomicUsize>,
     frame_count: Arc<AtomicUsize>,
     video_format: CodecFormat,
+    key_rotation: KeyRotation,
+    mux: Mux,
+    mux_receiver: mpsc::UnboundedReceiver<Message>,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -58,6 +68,7 @@ impl<T: InvokeUiSession> Remote<T> {
         sender: mpsc::UnboundedSender<Data>,
         frame_count: Arc<AtomicUsize>,
     ) -> Self {
+        let (mux, mux_receiver) = Mux::new(true);
         Self {
             handler,
             video_sender,
@@ -76,6 +87,9 @@ impl<T: InvokeUiSession> Remote<T> {
             data_count: Arc::new(AtomicUsize::new(0)),
             frame_count,
             video_format: CodecFormat::Unknown,
+            key_rotation: KeyRotation::new(false),
+            mux,
+            mux_receiver,
         }
     }
 
@@ -88,20 +102,50 @@ impl<T: InvokeUiSession> Remote<T> {
         } else {
             ConnType::default()
         };
-        match Client::start(
-            &self.handler.id,
-            key,
-            token,
-            conn_type,
-            self.handler.clone(),
-        )
-        .await
-        {
-            Ok((mut peer, direct)) => {
+        let replay = self.handler.get_replay();
+        let channel = self
+            .handler
+            .lc
+            .read()
+            .unwrap()
+            .create_channel_open()
+            .and_then(|open| crate::client::open_channel(&self.handler.id, open));
+        let started = match (replay, channel) {
+            (Some(path), _) => Self::replay(&path),
+            (None, Some(started)) => Ok(started),
+            (None, None) => Client::start(
+                &self.handler.id,
+                key,
+                token,
+                conn_type,
+                self.handler.clone(),
+            )
+            .await
+            .map(|(peer, direct)| {
+                let secured = peer.is_secured();
+                (peer, secured, direct)
+            }),
+        };
+        match started {
+            Ok((mut peer, secured, direct)) => {
                 SERVER_KEYBOARD_ENABLED.store(true, Ordering::SeqCst);
                 SERVER_CLIPBOARD_ENABLED.store(true, Ordering::SeqCst);
                 SERVER_FILE_TRANSFER_ENABLED.store(true, Ordering::SeqCst);
-                self.handler.set_connection_type(peer.is_secured(), direct); // flutter -> connection_ready
+                self.handler.set_connection_type(secured, direct); // flutter -> connection_ready
+                if self.handler.get_replay().is_none() {
+                    if let Some(capture) = Capture::start(&self.handler.id, false) {
+                        peer.set_capture(capture);
+                    }
+                }
+                if conn_type == ConnType::DEFAULT_CONN {
+                    crate::client::register_channel_host(
+                        &self.handler.id,
+                        self.mux.clone(),
+                        self.handler.lc.clone(),
+                        secured,
+                        direct,
+                    );
+                }
 
                 // just build for now
                 #[cfg(not(windows))]
@@ -149,6 +193,9 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
+                        Some(msg) = self.mux_receiver.recv() => {
+                            allow_err!(peer.send(&msg).await);
+                        }
                         _msg = rx_clip_client.recv() => {
                             #[cfg(windows)]
                             match _msg {
@@ -187,6 +234,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                 }
+                crate::client::unregister_channel_host(&self.handler.id, &self.mux);
                 log::debug!("Exit io_loop of id={}", self.handler.id);
             }
             Err(err) => {
@@ -202,6 +250,17 @@ impl<T: InvokeUiSession> Remote<T> {
         SERVER_FILE_TRANSFER_ENABLED.store(false, Ordering::SeqCst);
     }
 
+    /// A stream of the messages received in a client capture, see [`capture`].
+    fn replay(path: &str) -> ResultType<(Stream, bool, bool)> {
+        let (header, records) = capture::load(path)?;
+        if header.is_host {
+            hbb_common::bail!("Only client captures can be replayed");
+        }
+        log::info!("Replay {} of {}", path, header.id);
+        let stream = capture::replay(records, capture::Direction::In)?;
+        Ok((stream, false, false))
+    }
+
     fn handle_job_status(&mut self, id: i32, file_num: i32, err: Option<String>) {
         if let Some(job) = self.remove_jobs.get_mut(&id) {
             if job.no_confirm {
@@ -241,7 +300,7 @@ impl<T: InvokeUiSession> Remote<T> {
         match ClipboardContext::new() {
             Ok(mut ctx) => {
                 // ignore clipboard update before service start
-                check_clipboard(&mut ctx, Some(&old_clipboard));
+                check_clipboard(&mut ctx, Some(&old_clipboard), None);
                 std::thread::spawn(move || loop {
                     std::thread::sleep(Duration::from_millis(CLIPBOARD_INTERVAL));
                     match rx.try_recv() {
@@ -257,7 +316,11 @@ impl<T: InvokeUiSession> Remote<T> {
                     {
                         continue;
                     }
-                    if let Some(msg) = check_clipboard(&mut ctx, Some(&old_clipboard)) {
+                    let dictionary = compress::negotiate(
+                        Kind::Clipboard,
+                        lc.read().unwrap().peer_capabilities(),
+                    );
+                    if let Some(msg) = check_clipboard(&mut ctx, Some(&old_clipboard), dictionary) {
                         tx_protobuf.send(Data::Message(msg)).ok();
                     }
                 });
@@ -284,6 +347,9 @@ impl<T: InvokeUiSession> Remote<T> {
                     .handle_login_from_ui(password, remember, peer)
                     .await;
             }
+            Data::Login2fa(code) => {
+                self.handler.handle_login_2fa(code, peer).await;
+            }
             Data::ToggleClipboardFile => {
                 self.check_clipboard_file_context();
             }
@@ -292,7 +358,13 @@ impl<T: InvokeUiSession> Remote<T> {
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
-                let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+                let od = self
+                    .handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .peer_capabilities()
+                    .has_file_transfer(capability::FILE_TRANSFER_OVERWRITE_DETECTION);
                 if is_remote {
                     log::debug!("New job {}, write to {} from remote {}", id, to, path);
                     self.write_jobs.push(fs::TransferJob::new_write(
@@ -322,7 +394,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         Err(err) => {
                             self.handle_job_status(id, -1, Some(err.to_string()));
                         }
-                        Ok(job) => {
+                        Ok(mut job) => {
                             log::debug!(
                                 "New job {}, read {} to remote {}, {} files",
                                 id,
@@ -346,6 +418,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // peer is not windows, need transform \ to /
                                 fs::transform_windows_path(&mut files);
                             }
+                            job.set_dictionary(self.file_dictionary());
                             self.read_jobs.push(job);
                             self.timer = time::interval(MILLI1);
                             allow_err!(peer.send(&fs::new_receive(id, to, file_num, files)).await);
@@ -354,7 +427,13 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
             Data::AddJob((id, path, to, file_num, include_hidden, is_remote)) => {
-                let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+                let od = self
+                    .handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .peer_capabilities()
+                    .has_file_transfer(capability::FILE_TRANSFER_OVERWRITE_DETECTION);
                 if is_remote {
                     log::debug!(
                         "new write waiting job {}, write to {} from remote {}",
@@ -403,6 +482,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 true,
                             );
                             job.is_last_job = true;
+                            job.set_dictionary(self.file_dictionary());
                             self.read_jobs.push(job);
                             self.timer = time::interval(MILLI1);
                         }
@@ -734,7 +814,11 @@ impl<T: InvokeUiSession> Remote<T> {
                         {
                             let txt = self.old_clipboard.lock().unwrap().clone();
                             if !txt.is_empty() {
-                                let msg_out = crate::create_clipboard_msg(txt);
+                                let dictionary = compress::negotiate(
+                                    Kind::Clipboard,
+                                    self.handler.lc.read().unwrap().peer_capabilities(),
+                                );
+                                let msg_out = crate::create_clipboard_msg(txt, dictionary);
                                 let sender = self.sender.clone();
                                 tokio::spawn(async move {
                                     // due to clipboard service interval time
@@ -786,7 +870,13 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                 }
-                Some(message::Union::FileResponse(fr)) => {
+                Some(message::Union::FileResponse(mut fr)) => {
+                    if let Some(file_response::Union::CompressedDir(data)) = &fr.union {
+                        match FileDirectory::parse_from_bytes(&compress::decompress(data)) {
+                            Ok(fd) => fr.set_dir(fd),
+                            Err(err) => log::error!("Invalid compressed directory: {}", err),
+                        }
+                    }
                     match fr.union {
                         Some(file_response::Union::Dir(fd)) => {
                             #[cfg(windows)]
@@ -881,15 +971,23 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     }
                                                 }
                                                 DigestCheckResult::NoSuchFile => {
-                                                    let msg = new_send_confirm(
-                                                    FileTransferSendConfirmRequest {
-                                                        id: digest.id,
-                                                        file_num: digest.file_num,
-                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
-                                                        ..Default::default()
-                                                    },
-                                                );
-                                                    allow_err!(peer.send(&msg).await);
+                                                    // not to hold the session while hashing a partial download
+                                                    let sender = self.sender.clone();
+                                                    let (id, file_num) =
+                                                        (digest.id, digest.file_num);
+                                                    tokio::spawn(async move {
+                                                        let req = fs::new_write_confirm(
+                                                            id,
+                                                            file_num,
+                                                            &write_path,
+                                                        )
+                                                        .await;
+                                                        sender
+                                                            .send(Data::Message(new_send_confirm(
+                                                                req,
+                                                            )))
+                                                            .ok();
+                                                    });
                                                 }
                                             },
                                             Err(err) => {
@@ -1017,6 +1115,23 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                     _ => {}
                 },
+                Some(message::Union::Rekey(rk)) => {
+                    if let Err(err) = self.key_rotation.handle(rk, peer).await {
+                        self.handler
+                            .msgbox("error", "Connection Error", &err.to_string(), "");
+                        return false;
+                    }
+                }
+                Some(message::Union::Channel(ch)) => match self.mux.handle(ch) {
+                    // the host opens no channels for now
+                    Ok(Some((id, _, _))) => self.mux.close(id, "Unsupported channel"),
+                    Ok(None) => {}
+                    Err(err) => {
+                        self.handler
+                            .msgbox("error", "Connection Error", &err.to_string(), "");
+                        return false;
+                    }
+                },
                 Some(message::Union::MessageBox(msgbox)) => {
                     let mut link = msgbox.link;
                     if !link.starts_with("rustdesk://") {
@@ -1158,6 +1273,13 @@ impl<T: InvokeUiSession> Remote<T> {
         true
     }
 
+    fn file_dictionary(&self) -> Option<u32> {
+        compress::negotiate(
+            Kind::FileBlock,
+            self.handler.lc.read().unwrap().peer_capabilities(),
+        )
+    }
+
     fn check_clipboard_file_context(&mut self) {
         #[cfg(windows)]
         {
@@ -10,14 +10,15 @@ pub use arboard::Clipboard as ClipboardContext;
 use hbb_common::{
     allow_err,
     anyhow::bail,
-    compress::{compress as compress_func, decompress},
+    capability,
+    compress::{compress_with, decompress},
     config::{self, Config, COMPRESS_LEVEL, RENDEZVOUS_TIMEOUT},
     get_version_number, log,
     message_proto::*,
     protobuf::Enum,
     protobuf::Message as _,
     rendezvous_proto::*,
-    sleep, socket_client, tokio, ResultType,
+    sleep, socket_client, tokio, websocket, ResultType,
 };
 // #[cfg(any(target_os = "android", target_os = "ios", feature = "cli"))]
 use hbb_common::{config::RENDEZVOUS_PORT, futures::future::join_all};
@@ -60,9 +61,10 @@ pub fn valid_for_numlock(evt: &KeyEvent) -> bool {
     }
 }
 
-pub fn create_clipboard_msg(content: String) -> Message {
+/// `dictionary` is the one negotiated with the peer, see [`hbb_common::compress::negotiate`].
+pub fn create_clipboard_msg(content: String, dictionary: Option<u32>) -> Message {
     let bytes = content.into_bytes();
-    let compressed = compress_func(&bytes, COMPRESS_LEVEL);
+    let compressed = compress_with(&bytes, COMPRESS_LEVEL, dictionary);
     let compress = compressed.len() < bytes.len();
     let content = if compress { compressed } else { bytes };
     let mut msg = Message::new();
@@ -78,6 +80,7 @@ pub fn create_clipboard_msg(content: String) -> Message {
 pub fn check_clipboard(
     ctx: &mut ClipboardContext,
     old: Option<&Arc<Mutex<String>>>,
+    dictionary: Option<u32>,
 ) -> Option<Message> {
     let side = if old.is_none() { "host" } else { "client" };
     let old = if let Some(old) = old { old } else { &CONTENT };
@@ -87,7 +90,7 @@ pub fn check_clipboard(
             if changed {
                 log::info!("{} update found on {}", CLIPBOARD_NAME, side);
                 *old.lock().unwrap() = content.clone();
-                return Some(create_clipboard_msg(content));
+                return Some(create_clipboard_msg(content, dictionary));
             }
         }
     }
@@ -280,6 +283,11 @@ async fn test_nat_type_() -> ResultType<bool> {
     }
     let start = std::time::Instant::now();
     let (rendezvous_server, _, _) = get_rendezvous_server(1_000).await;
+    if websocket::is_ws_url(&rendezvous_server) {
+        // only the websocket endpoint is reachable, hole punching is out of question
+        Config::set_nat_type(NatType::SYMMETRIC as _);
+        return Ok(true);
+    }
     let server1 = rendezvous_server;
     let tmp: Vec<&str> = server1.split(":").collect();
     if tmp.len() != 2 {
@@ -326,7 +334,8 @@ async fn test_nat_type_() -> ResultType<bool> {
                         Config::set_option(
                             "rendezvous-servers".to_owned(),
                             cu.rendezvous_servers.join(","),
-                        );
+                        )
+                        .ok();
                         Config::set_serial(cu.serial);
                     }
                 }
@@ -335,7 +344,7 @@ async fn test_nat_type_() -> ResultType<bool> {
             break;
         }
     }
-    Config::set_option("local-ip-addr".to_owned(), addr.ip().to_string());
+    Config::set_option("local-ip-addr".to_owned(), addr.ip().to_string()).ok();
     let ok = port1 > 0 && port2 > 0;
     if ok {
         let t = if port1 == port2 {
@@ -410,8 +419,8 @@ async fn test_rendezvous_server_() {
     for host in servers {
         futs.push(tokio::spawn(async move {
             let tm = std::time::Instant::now();
-            if socket_client::connect_tcp(
-                crate::check_port(&host, RENDEZVOUS_PORT),
+            if socket_client::connect_server(
+                &crate::check_port(&host, RENDEZVOUS_PORT),
                 Config::get_any_listen_addr(),
                 RENDEZVOUS_TIMEOUT,
             )
@@ -444,19 +453,32 @@ pub fn refresh_rendezvous_server() {
     });
 }
 
+/// Run this program with `args`, in the profile of this process unless `args` has one.
 pub fn run_me<T: AsRef<std::ffi::OsStr>>(args: Vec<T>) -> std::io::Result<std::process::Child> {
+    me_command(args)?.spawn()
+}
+
+/// The command running this program with `args`, in the current profile unless given.
+pub fn me_command<T: AsRef<std::ffi::OsStr>>(
+    args: Vec<T>,
+) -> std::io::Result<std::process::Command> {
+    let profile = Config::get_profile();
+    let profile_args = if profile.is_empty() || args.iter().any(|x| x.as_ref() == "--profile") {
+        vec![]
+    } else {
+        vec!["--profile".to_owned(), profile]
+    };
     #[cfg(not(feature = "appimage"))]
-    {
-        let cmd = std::env::current_exe()?;
-        return std::process::Command::new(cmd).args(&args).spawn();
-    }
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
     #[cfg(feature = "appimage")]
-    {
+    let mut cmd = {
         let appdir = std::env::var("APPDIR").unwrap();
         let appimage_cmd = std::path::Path::new(&appdir).join("AppRun");
         log::info!("path: {:?}", appimage_cmd);
-        return std::process::Command::new(appimage_cmd).args(&args).spawn();
-    }
+        std::process::Command::new(appimage_cmd)
+    };
+    cmd.args(&args).args(&profile_args);
+    Ok(cmd)
 }
 
 pub fn username() -> String {
@@ -565,6 +587,18 @@ pub fn is_setup(name: &str) -> bool {
     name.to_lowercase().ends_with("install.exe") || name.to_lowercase().ends_with("安装.exe")
 }
 
+/// Whether the license, if any, grants `feature` and has not expired.
+pub fn is_licensed(_feature: &str) -> bool {
+    #[cfg(windows)]
+    if let Some(lic) = crate::platform::windows::get_license() {
+        return lic.has_feature(_feature)
+            && lic
+                .check(hbb_common::chrono::Utc::now().naive_utc().date())
+                .is_ok();
+    }
+    true
+}
+
 pub fn get_custom_rendezvous_server(custom: String) -> String {
     if !custom.is_empty() {
         return custom;
@@ -592,6 +626,18 @@ pub fn get_api_server(api: String, custom: String) -> String {
         }
     }
     let s = get_custom_rendezvous_server(custom);
+    if websocket::is_ws_url(&s) {
+        // the api is served on the same https endpoint as the websocket
+        let s = s.trim();
+        let (scheme, rest) = s.split_at(s.find("://").unwrap_or_default());
+        let host = rest[3..].split('/').next().unwrap_or_default();
+        let scheme = if scheme.eq_ignore_ascii_case("wss") {
+            "https"
+        } else {
+            "http"
+        };
+        return format!("{}://{}", scheme, host);
+    }
     if !s.is_empty() {
         if s.contains(':') {
             let tmp: Vec<&str> = s.split(":").collect();
@@ -677,6 +723,44 @@ pub fn make_privacy_mode_msg(state: back_notification::PrivacyModeState) -> Mess
     msg_out
 }
 
+/// Capabilities of this side, announced in [`LoginRequest`] and [`PeerInfo`].
+///
+/// The codecs are the ones this side can encode as the host, or decode when connecting to `id`.
+pub fn get_capabilities(is_host: bool, id: &str) -> Capabilities {
+    let (h264, h265) = if is_host {
+        scrap::codec::Encoder::supported_encoding()
+    } else {
+        let state = scrap::codec::Decoder::video_codec_state(id);
+        (state.score_h264 > 0, state.score_h265 > 0)
+    };
+    let mut codecs = vec![capability::CODEC_VP9];
+    if h264 {
+        codecs.push(capability::CODEC_H264);
+    }
+    if h265 {
+        codecs.push(capability::CODEC_H265);
+    }
+    Capabilities {
+        codecs: capability::names(&codecs),
+        file_transfer: capability::names(&[capability::FILE_TRANSFER_OVERWRITE_DETECTION]),
+        clipboard: capability::names(&[capability::CLIPBOARD_TEXT]),
+        input: capability::names(&[
+            capability::INPUT_MOUSE,
+            capability::INPUT_KEYBOARD_LEGACY,
+            capability::INPUT_KEYBOARD_MAP,
+            capability::INPUT_KEYBOARD_TRANSLATE,
+        ]),
+        session: capability::names(&[
+            capability::SESSION_RESTART,
+            capability::SESSION_REKEY,
+            capability::SESSION_CHANNELS,
+            capability::SESSION_PASSWORD_KDF,
+        ]),
+        zstd_dictionaries: hbb_common::compress::dictionary_ids(),
+        ..Default::default()
+    }
+}
+
 #[cfg(not(target_os = "linux"))]
 lazy_static::lazy_static! {
     pub static ref IS_X11: Mutex<bool> = Mutex::new(false);
@@ -1,6 +1,6 @@
 use std::env::Args;
 
-use hbb_common::log;
+use hbb_common::{config::peer_acl::Rule, log};
 
 // shared by flutter and sciter main function
 pub fn core_main() -> Option<Vec<String>> {
@@ -33,6 +33,15 @@ pub fn core_main() -> Option<Vec<String>> {
         }
         i += 1;
     }
+    // --profile <name> may come anywhere, e.g. appended by run_me
+    if let Some(i) = args.iter().position(|x| x == "--profile") {
+        let name = args.get(i + 1).cloned().unwrap_or_default();
+        args.drain(i..(i + 2).min(args.len()));
+        if let Err(err) = hbb_common::config::Config::set_profile(&name) {
+            println!("{}", err);
+            return None;
+        }
+    }
     if args.contains(&"--install".to_string()) {
         is_setup = true;
     }
@@ -85,6 +94,21 @@ pub fn core_main() -> Option<Vec<String>> {
                 .ok();
         }
     }
+    // uninstalling must still work once the license has expired
+    #[cfg(windows)]
+    if args.first().map(|x| x.as_str()) != Some("--uninstall")
+        && args.first().map(|x| x.as_str()) != Some("--before-uninstall")
+    {
+        if let Err(err) = crate::platform::check_license() {
+            log::error!("{}", err);
+            println!("{}", err);
+            // started from the desktop, with no console to print to
+            if args.is_empty() {
+                crate::platform::error_box(&err.to_string());
+            }
+            return None;
+        }
+    }
     #[cfg(windows)]
     #[cfg(not(debug_assertions))]
     if !crate::platform::is_installed() && args.is_empty() {
@@ -161,7 +185,8 @@ pub fn core_main() -> Option<Vec<String>> {
                 // to-do: for flutter, starting tray not ready yet, or we can reuse sciter's tray implementation.
             }
         } else if args[0] == "--import-config" {
-            if args.len() == 2 {
+            // --import-config <bundle> | --import-config <config.toml> --unsigned
+            if args.len() >= 2 {
                 let filepath;
                 let path = std::path::Path::new(&args[1]);
                 if !path.is_absolute() {
@@ -171,12 +196,132 @@ pub fn core_main() -> Option<Vec<String>> {
                 } else {
                     filepath = path.to_str().unwrap().to_string();
                 }
-                import_config(&filepath);
+                if filepath.ends_with(".toml") {
+                    if args.get(2).map(|x| x.as_str()) == Some("--unsigned") {
+                        log::warn!("Importing unsigned config files");
+                        import_config(&filepath);
+                    } else {
+                        log::error!("Refused to import unsigned {}", filepath);
+                        println!(
+                            "Refused to import unsigned {}, add --unsigned to import it anyway",
+                            filepath
+                        );
+                    }
+                } else if let Err(err) = import_config_bundle(&filepath) {
+                    log::error!("Failed to import {}: {}", filepath, err);
+                    println!("Failed to import {}: {}", filepath, err);
+                }
             }
             return None;
+        } else if args[0] == "--export-config" {
+            // --export-config <bundle> <secret key file> [--with-password]
+            if args.len() >= 3 {
+                let with_password = args.contains(&"--with-password".to_owned());
+                if let Err(err) = export_config_bundle(&args[1], &args[2], with_password) {
+                    println!("Failed to export {}: {}", args[1], err);
+                }
+            }
+            return None;
+        } else if args[0] == "--gen-admin-key" {
+            let (pk, sk) = hbb_common::config::bundle::gen_keypair();
+            println!("public key (option admin-keys): {}", pk);
+            println!("secret key: {}", sk);
+            return None;
         } else if args[0] == "--password" {
             if args.len() == 2 {
-                crate::ipc::set_permanent_password(args[1].to_owned()).unwrap();
+                if let Err(err) = crate::ipc::set_permanent_password(args[1].to_owned()) {
+                    println!("{}", err);
+                }
+            }
+            return None;
+        } else if args[0] == "--enable-2fa" {
+            let uri = crate::ui_interface::enroll_2fa();
+            println!("Add this to your authenticator app: {}", uri);
+            print!("Code: ");
+            std::io::Write::flush(&mut std::io::stdout()).ok();
+            let mut code = String::new();
+            std::io::stdin().read_line(&mut code).ok();
+            let err = crate::ui_interface::confirm_2fa(code.trim().to_owned());
+            if err.is_empty() {
+                println!("Two-factor authentication enabled");
+            } else {
+                println!("Two-factor authentication not enabled: {}", err);
+            }
+            return None;
+        } else if args[0] == "--disable-2fa" {
+            crate::ui_interface::disable_2fa();
+            return None;
+        } else if args[0] == "--client-key" {
+            // to be authorized by the hosts, see --authorized-keys
+            println!("{}", crate::ui_interface::get_client_key());
+            return None;
+        } else if args[0] == "--authorized-keys" {
+            if let Err(err) = authorized_keys(&args[1..]) {
+                println!("{}", err);
+            }
+            return None;
+        } else if args[0] == "--peer-acl" {
+            if let Err(err) = peer_acl(&args[1..]) {
+                println!("{}", err);
+            }
+            return None;
+        } else if args[0] == "--peer-key" {
+            if let Err(err) = peer_key(&args[1..]) {
+                println!("{}", err);
+            }
+            return None;
+        } else if args[0] == "--option" {
+            // --option [name [value]], an empty value resets to the default
+            if args.len() == 3 {
+                if let Err(err) = crate::ipc::try_set_option(&args[1], &args[2]) {
+                    println!("{}", err);
+                } else if args[1] == "allow-legacy-password" && args[2] == "Y" {
+                    if let Ok(hash) = crate::ipc::get_permanent_password_hash() {
+                        if !hash.key.is_empty() && hash.hash.is_empty() {
+                            println!(
+                                "Set the permanent password again for older clients to use it"
+                            );
+                        }
+                    }
+                }
+            } else {
+                match crate::ipc::get_option_list() {
+                    Ok(list) => {
+                        for x in list.iter().filter(|x| args.len() == 1 || x.name == args[1]) {
+                            if x.kind.is_empty() {
+                                println!("{} = {:?} (unknown option)", x.name, x.value);
+                            } else {
+                                println!(
+                                    "{} = {:?} ({}, default {:?}) {}",
+                                    x.name, x.value, x.kind, x.default, x.description
+                                );
+                            }
+                        }
+                    }
+                    Err(err) => println!("Failed to get options: {}", err),
+                }
+            }
+            return None;
+        } else if args[0] == "--profiles" {
+            // --profiles [name,...], set the profiles whose servers run along
+            if args.len() == 2 {
+                let profiles: Vec<String> = args[1]
+                    .split(',')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_owned())
+                    .collect();
+                if let Err(err) = crate::ipc::set_active_profiles(profiles) {
+                    println!("{}", err);
+                }
+            } else {
+                let active = crate::ipc::get_active_profiles().unwrap_or_else(|err| {
+                    println!("Failed to get active profiles: {}", err);
+                    vec![]
+                });
+                for name in hbb_common::config::Config::get_profiles() {
+                    let mark = if active.contains(&name) { "*" } else { " " };
+                    println!("{} {}", mark, name);
+                }
             }
             return None;
         } else if args[0] == "--check-hwcodec-config" {
@@ -203,7 +348,10 @@ fn import_config(path: &str) {
     let path2 = std::path::Path::new(&path2);
     let path = std::path::Path::new(path);
     log::info!("import config from {:?} and {:?}", path, path2);
-    let config: Config = load_path(path.into());
+    let config: Config = migration::load(path, migration::Kind::Main).unwrap_or_else(|err| {
+        log::error!("Failed to load {:?}: {}", path, err);
+        Default::default()
+    });
     if config.is_empty() {
         log::info!("Empty source config, skipped");
         return;
@@ -215,7 +363,10 @@ fn import_config(path: &str) {
             log::info!("config written");
         }
     }
-    let config2: Config2 = load_path(path2.into());
+    let config2: Config2 = migration::load(path2, migration::Kind::Options).unwrap_or_else(|err| {
+        log::error!("Failed to load {:?}: {}", path2, err);
+        Default::default()
+    });
     if get_modified_time(&path2) > get_modified_time(&Config2::file()) {
         if store_path(Config2::file(), config2).is_err() {
             log::info!("config2 written");
@@ -223,6 +374,186 @@ fn import_config(path: &str) {
     }
 }
 
+/// Apply a signed bundle of [`export_config_bundle`], by the service if it runs.
+fn import_config_bundle(path: &str) -> hbb_common::ResultType<()> {
+    use hbb_common::config::bundle::Bundle;
+    let data = std::fs::read_to_string(path)?;
+    if !crate::ipc::import_config_bundle(data.clone())? {
+        log::info!("Service not running, import {} here", path);
+        Bundle::import(&data)?;
+    }
+    log::info!("Config bundle {} imported", path);
+    Ok(())
+}
+
+fn export_config_bundle(
+    path: &str,
+    key_path: &str,
+    with_password: bool,
+) -> hbb_common::ResultType<()> {
+    use hbb_common::config::bundle::Bundle;
+    let password = if with_password {
+        let password = crate::ipc::get_permanent_password_hash()?;
+        if password.key.is_empty() && password.hash.is_empty() {
+            hbb_common::bail!("No permanent password");
+        }
+        Some(password)
+    } else {
+        None
+    };
+    let bundle = Bundle::new(crate::ipc::get_options(), password);
+    let sk = std::fs::read_to_string(key_path)?;
+    std::fs::write(path, bundle.sign(&sk)?)?;
+    println!("Config bundle written to {}", path);
+    Ok(())
+}
+
+/// --authorized-keys [add <key> [name [permission,...]] | revoke <key> | remove <key>],
+/// listing them if no command.
+fn authorized_keys(args: &[String]) -> hbb_common::ResultType<()> {
+    use hbb_common::config::authorized_keys::{AuthorizedKey, PERMISSIONS};
+    let mut keys = crate::ipc::get_authorized_keys()?;
+    if args.is_empty() {
+        for x in keys.iter() {
+            let state = if x.revoked { " (revoked)" } else { "" };
+            println!("{} {}{}", x.key, x.name, state);
+            println!("    added {}, {}", x.added, x.permissions.join(","));
+        }
+        return Ok(());
+    }
+    if args.len() < 2 {
+        hbb_common::bail!("Missing the key");
+    }
+    let key = args[1].trim();
+    match args[0].as_str() {
+        "add" => {
+            let mut x = AuthorizedKey::new(key, args.get(2).map(|x| x.as_str()).unwrap_or(""));
+            if let Some(permissions) = args.get(3).filter(|x| *x != "default") {
+                x.permissions = permissions
+                    .split(',')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_owned())
+                    .collect();
+            }
+            keys.retain(|x| x.key != key);
+            keys.push(x);
+        }
+        "revoke" | "remove" => {
+            if !keys.iter().any(|x| x.key == key) {
+                hbb_common::bail!("No key {}", key);
+            }
+            if args[0] == "remove" {
+                keys.retain(|x| x.key != key);
+            } else {
+                keys.iter_mut()
+                    .filter(|x| x.key == key)
+                    .for_each(|x| x.revoked = true);
+            }
+        }
+        _ => hbb_common::bail!(
+            "Unknown command {}, permissions being among {}",
+            args[0],
+            PERMISSIONS.join(",")
+        ),
+    }
+    crate::ipc::set_authorized_keys(keys)
+}
+
+/// --peer-key <id> [accept|forget], showing the pinned key of the peer and the new one
+/// to review if no command.
+fn peer_key(args: &[String]) -> hbb_common::ResultType<()> {
+    use hbb_common::config::{key_fingerprint, PeerConfig};
+    let id = match args.first() {
+        Some(x) => x.trim(),
+        None => hbb_common::bail!("Missing the peer id"),
+    };
+    match args.get(1).map(|x| x.as_str()) {
+        None => {
+            let c = PeerConfig::load(id);
+            println!("pinned key {}", key_fingerprint(&c.pinned_key));
+            if !c.pending_key.is_empty() {
+                println!("new key {}", key_fingerprint(&c.pending_key));
+            }
+        }
+        Some("accept") => PeerConfig::accept_key(id)?,
+        Some("forget") => PeerConfig::forget_key(id),
+        Some(x) => hbb_common::bail!("Unknown command {}, expecting accept or forget", x),
+    }
+    Ok(())
+}
+
+/// --peer-acl [set <peer> allow|deny|view-only [permission,...|default [windows
+/// [timezone]]] | remove <peer>], listing the rules if no command, a peer being an id,
+/// key:<client key> or *.
+fn peer_acl(args: &[String]) -> hbb_common::ResultType<()> {
+    let mut rules = crate::ipc::get_peer_acl()?;
+    if args.is_empty() {
+        for x in rules.iter() {
+            let access = if x.deny {
+                "deny"
+            } else if x.view_only {
+                "view-only"
+            } else {
+                "allow"
+            };
+            let permissions = match x.permissions.as_ref() {
+                Some(x) => x.join(","),
+                None => "default permissions".to_owned(),
+            };
+            println!("{} {}, {}", x.peer, access, permissions);
+            if !x.windows.is_empty() {
+                println!("    access windows {} {}", x.windows, x.timezone);
+            }
+        }
+        return Ok(());
+    }
+    if args.len() < 2 {
+        hbb_common::bail!("Missing the peer");
+    }
+    let peer = args[1].trim();
+    match args[0].as_str() {
+        "set" => {
+            let rule = parse_peer_acl_rule(peer, &args[2..])?;
+            rules.retain(|x| x.peer != peer);
+            rules.push(rule);
+        }
+        "remove" => {
+            if !rules.iter().any(|x| x.peer == peer) {
+                hbb_common::bail!("No rule for {}", peer);
+            }
+            rules.retain(|x| x.peer != peer);
+        }
+        _ => hbb_common::bail!("Unknown command {}", args[0]),
+    }
+    crate::ipc::set_peer_acl(rules)
+}
+
+/// The rule of `--peer-acl set <peer>` from the arguments following the peer.
+fn parse_peer_acl_rule(peer: &str, args: &[String]) -> hbb_common::ResultType<Rule> {
+    let mut rule = Rule {
+        peer: peer.to_owned(),
+        ..Default::default()
+    };
+    match args.get(0).map(|x| x.as_str()).unwrap_or("allow") {
+        "allow" => {}
+        "deny" => rule.deny = true,
+        "view-only" => rule.view_only = true,
+        x => hbb_common::bail!("Unknown access {}, expecting allow, deny or view-only", x),
+    }
+    if let Some(permissions) = args.get(1).filter(|x| *x != "default") {
+        rule.permissions = Some(
+            permissions
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+                .collect(),
+        );
+    }
+    rule.windows = args.get(2).cloned().unwrap_or_default();
+    rule.timezone = args.get(3).cloned().unwrap_or_default();
+    Ok(rule)
+}
+
 /// invoke a new connection
 ///
 /// [Note]
@@ -255,3 +586,29 @@ fn core_main_invoke_new_connection(mut args: Args) -> Option<Vec<String>> {
     }
     return None;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer_acl_rule() {
+        let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let rule = parse_peer_acl_rule("*", &args(&["view-only"])).unwrap();
+        assert!(rule.view_only && rule.permissions.is_none());
+        let rule = parse_peer_acl_rule(
+            "123",
+            &args(&["allow", "default", "mon-fri 08:00-18:00", "Europe/Paris"]),
+        )
+        .unwrap();
+        assert_eq!(rule.permissions, None);
+        assert_eq!(rule.windows, "mon-fri 08:00-18:00");
+        assert_eq!(rule.timezone, "Europe/Paris");
+        let rule = parse_peer_acl_rule("123", &args(&["allow", "keyboard,,file"])).unwrap();
+        assert_eq!(
+            rule.permissions,
+            Some(vec!["keyboard".to_owned(), "file".to_owned()])
+        );
+        assert!(parse_peer_acl_rule("123", &args(&["maybe"])).is_err());
+    }
+}
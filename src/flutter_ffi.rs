@@ -127,6 +127,12 @@ pub fn session_login(id: String, password: String, remember: bool) {
     }
 }
 
+pub fn session_login_2fa(id: String, code: String) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        session.login_2fa(code);
+    }
+}
+
 pub fn session_close(id: String) {
     if let Some(session) = SESSIONS.read().unwrap().get(&id) {
         session.close();
@@ -421,15 +427,17 @@ pub fn main_get_option(key: String) -> String {
     get_option(key)
 }
 
-pub fn main_set_option(key: String, value: String) {
+/// Returns the error if the value is invalid.
+pub fn main_set_option(key: String, value: String) -> String {
     if key.eq("custom-rendezvous-server") {
-        set_option(key, value);
+        let err = set_option(key, value);
         #[cfg(target_os = "android")]
         crate::rendezvous_mediator::RendezvousMediator::restart();
         #[cfg(any(target_os = "android", target_os = "ios", feature = "cli"))]
         crate::common::test_rendezvous_server();
+        err
     } else {
-        set_option(key, value);
+        set_option(key, value)
     }
 }
 
@@ -437,11 +445,13 @@ pub fn main_get_options() -> String {
     get_options()
 }
 
-pub fn main_set_options(json: String) {
+/// Returns the errors of the invalid values, which are not set.
+pub fn main_set_options(json: String) -> String {
     let map: HashMap<String, String> = serde_json::from_str(&json).unwrap_or(HashMap::new());
-    if !map.is_empty() {
-        set_options(map)
+    if map.is_empty() {
+        return "".to_owned();
     }
+    set_options(map)
 }
 
 pub fn main_test_if_valid_server(server: String) -> String {
@@ -565,6 +575,82 @@ pub fn main_peer_has_password(id: String) -> bool {
     peer_has_password(id)
 }
 
+pub fn main_is_password_store_locked() -> SyncReturn<bool> {
+    SyncReturn(is_password_store_locked())
+}
+
+pub fn main_unlock_password_store(passphrase: String) -> String {
+    unlock_password_store(passphrase)
+}
+
+pub fn main_get_peer_key(id: String) -> SyncReturn<String> {
+    SyncReturn(get_peer_key(id))
+}
+
+pub fn main_accept_peer_key(id: String) -> String {
+    accept_peer_key(id)
+}
+
+pub fn main_forget_peer_key(id: String) {
+    forget_peer_key(id)
+}
+
+pub fn main_is_2fa_enabled() -> SyncReturn<bool> {
+    SyncReturn(is_2fa_enabled())
+}
+
+pub fn main_enroll_2fa() -> String {
+    enroll_2fa()
+}
+
+pub fn main_confirm_2fa(code: String) -> String {
+    confirm_2fa(code)
+}
+
+pub fn main_disable_2fa() {
+    disable_2fa()
+}
+
+pub fn main_get_client_key() -> SyncReturn<String> {
+    SyncReturn(get_client_key())
+}
+
+pub fn main_get_authorized_keys() -> String {
+    get_authorized_keys()
+}
+
+pub fn main_set_authorized_keys(keys: String) -> String {
+    set_authorized_keys(keys)
+}
+
+pub fn main_get_address_book(filter: String) -> String {
+    get_address_book(filter)
+}
+
+pub fn main_get_address_book_tags() -> SyncReturn<String> {
+    SyncReturn(serde_json::to_string(&get_address_book_tags()).unwrap_or_default())
+}
+
+pub fn main_get_address_book_groups() -> SyncReturn<String> {
+    SyncReturn(serde_json::to_string(&get_address_book_groups()).unwrap_or_default())
+}
+
+pub fn main_set_address_book_entry(entry: String) -> String {
+    set_address_book_entry(entry)
+}
+
+pub fn main_remove_address_book_entry(id: String) {
+    remove_address_book_entry(id)
+}
+
+pub fn main_import_address_book(path: String) -> String {
+    import_address_book(path)
+}
+
+pub fn main_export_address_book(path: String) -> String {
+    export_address_book(path)
+}
+
 pub fn main_get_recent_peers() -> String {
     if !config::APP_DIR.read().unwrap().is_empty() {
         let peers: Vec<HashMap<&str, String>> = PeerConfig::peers()
@@ -749,8 +835,8 @@ pub fn main_get_temporary_password() -> String {
     ui_interface::temporary_password()
 }
 
-pub fn main_get_permanent_password() -> String {
-    ui_interface::permanent_password()
+pub fn main_has_permanent_password() -> SyncReturn<bool> {
+    SyncReturn(ui_interface::has_permanent_password())
 }
 
 pub fn main_get_online_statue() -> i64 {
@@ -893,7 +979,7 @@ pub fn main_set_home_dir(home: String) {
 pub fn main_stop_service() {
     #[cfg(target_os = "android")]
     {
-        config::Config::set_option("stop-service".into(), "Y".into());
+        config::Config::set_option("stop-service".into(), "Y".into()).ok();
         crate::rendezvous_mediator::RendezvousMediator::restart();
     }
 }
@@ -901,7 +987,7 @@ pub fn main_stop_service() {
 pub fn main_start_service() {
     #[cfg(target_os = "android")]
     {
-        config::Config::set_option("stop-service".into(), "".into());
+        config::Config::set_option("stop-service".into(), "".into()).ok();
         crate::rendezvous_mediator::RendezvousMediator::restart();
     }
 }
@@ -910,8 +996,8 @@ pub fn main_update_temporary_password() {
     update_temporary_password();
 }
 
-pub fn main_set_permanent_password(password: String) {
-    set_permanent_password(password);
+pub fn main_set_permanent_password(password: String) -> String {
+    set_permanent_password(password)
 }
 
 pub fn main_check_super_user_permission() -> bool {
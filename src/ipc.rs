@@ -13,10 +13,18 @@ pub use clipboard::ClipbaordFile;
 use hbb_common::{
     allow_err, bail, bytes,
     bytes_codec::BytesCodec,
-    config::{self, Config, Config2},
+    config::{
+        self,
+        authorized_keys::{AuthorizedKey, AuthorizedKeys},
+        bundle::PasswordHash,
+        peer_acl::{PeerAcl, Rule},
+        Config, Config2,
+    },
     futures::StreamExt as _,
     futures_util::sink::SinkExt,
-    log, password_security as password, timeout, tokio,
+    log,
+    options::{self, OptionValue},
+    password_security as password, timeout, tokio,
     tokio::io::{AsyncRead, AsyncWrite},
     tokio_util::codec::Framed,
     ResultType,
@@ -40,6 +48,9 @@ pub enum FS {
     ReadDir {
         dir: String,
         include_hidden: bool,
+        /// Compress the listing with this zstd dictionary of the peer.
+        #[serde(default)]
+        dictionary: Option<u32>,
     },
     RemoveDir {
         path: String,
@@ -70,6 +81,8 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        #[serde(default)]
+        blk_id: u32,
     },
     WriteDone {
         id: i32,
@@ -147,6 +160,7 @@ pub enum Data {
         file_transfer_enabled: bool,
         restart: bool,
         recording: bool,
+        view_only: bool,
     },
     ChatMessage {
         text: String,
@@ -163,7 +177,27 @@ pub enum Data {
     SAS,
     OnlineStatus(Option<(i64, bool)>),
     Config((String, Option<String>)),
+    /// All options, requested with `None`, set with `Some`, which is answered by
+    /// [`Data::SetOptionResult`] with the invalid values.
     Options(Option<HashMap<String, String>>),
+    /// All options of the schema with their values, requested with `None`.
+    OptionList(Option<Vec<OptionValue>>),
+    /// Set one option, answered by [`Data::SetOptionResult`] with the error if rejected.
+    SetOption((String, String)),
+    SetOptionResult(Option<String>),
+    /// Profiles whose servers the default profile runs, set with `Some`, which is
+    /// answered by [`Data::SetOptionResult`].
+    Profiles(Option<Vec<String>>),
+    /// Set the permanent password, answered by [`Data::SetOptionResult`].
+    PermanentPassword(String),
+    /// A signed config bundle to verify and apply, answered by [`Data::SetOptionResult`].
+    ImportConfig(String),
+    /// Client keys accepted instead of a password, set with `Some`, which is answered
+    /// by [`Data::SetOptionResult`].
+    AuthorizedKeys(Option<Vec<AuthorizedKey>>),
+    /// Access rules of the peers, set with `Some`, which is answered by
+    /// [`Data::SetOptionResult`].
+    PeerAcl(Option<Vec<Rule>>),
     NatType(Option<i32>),
     ConfirmedKey(Option<(Vec<u8>, Vec<u8>)>),
     RawMessage(Vec<u8>),
@@ -343,10 +377,14 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(Config::get_id());
                 } else if name == "temporary-password" {
                     value = Some(password::temporary_password());
-                } else if name == "permanent-password" {
-                    value = Some(Config::get_permanent_password());
+                } else if name == "permanent-password-hash" {
+                    // the verifiers only, the password is not stored
+                    value = serde_json::to_string(&Config::get_permanent_password_hash()).ok();
                 } else if name == "salt" {
                     value = Some(Config::get_salt());
+                } else if name == "totp" {
+                    // whether enrolled, the secret never leaves the service
+                    value = Some(if crate::totp::is_enabled() { "Y" } else { "" }.to_owned());
                 } else if name == "rendezvous_server" {
                     value = Some(format!(
                         "{},{}",
@@ -366,10 +404,10 @@ async fn handle(data: Data, stream: &mut Connection) {
                     Config::set_id(&value);
                 } else if name == "temporary-password" {
                     password::update_temporary_password();
-                } else if name == "permanent-password" {
-                    Config::set_permanent_password(&value);
                 } else if name == "salt" {
                     Config::set_salt(&value);
+                } else if name == "totp-secret" {
+                    Config::set_totp_secret(&value);
                 } else {
                     return;
                 }
@@ -383,10 +421,85 @@ async fn handle(data: Data, stream: &mut Connection) {
             }
             Some(value) => {
                 let _chk = CheckIfRestart::new();
-                Config::set_options(value);
-                allow_err!(stream.send(&Data::Options(None)).await);
+                let res = Config::set_options(value).err();
+                allow_err!(
+                    stream
+                        .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                        .await
+                );
             }
         },
+        Data::OptionList(None) => {
+            allow_err!(stream.send(&Data::OptionList(Some(options::list()))).await);
+        }
+        Data::SetOption((name, value)) => {
+            let _chk = CheckIfRestart::new();
+            let res = Config::try_set_option(name, value).err();
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
+        Data::Profiles(None) => {
+            allow_err!(
+                stream
+                    .send(&Data::Profiles(Some(Config::get_active_profiles())))
+                    .await
+            );
+        }
+        Data::Profiles(Some(profiles)) => {
+            let res = Config::set_active_profiles(&profiles).err();
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
+        Data::AuthorizedKeys(None) => {
+            let keys = AuthorizedKeys::load().keys;
+            allow_err!(stream.send(&Data::AuthorizedKeys(Some(keys))).await);
+        }
+        Data::AuthorizedKeys(Some(keys)) => {
+            let res = AuthorizedKeys::store(keys).err();
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
+        Data::PeerAcl(None) => {
+            let rules = PeerAcl::load().rules;
+            allow_err!(stream.send(&Data::PeerAcl(Some(rules))).await);
+        }
+        Data::PeerAcl(Some(rules)) => {
+            let res = PeerAcl::store(rules).err();
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
+        Data::PermanentPassword(password) => {
+            let res = Config::set_permanent_password(&password).err();
+            if res.is_none() {
+                log::info!("permanent-password updated");
+            }
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
+        Data::ImportConfig(data) => {
+            let _chk = CheckIfRestart::new();
+            let res = config::bundle::Bundle::import(&data).err();
+            allow_err!(
+                stream
+                    .send(&Data::SetOptionResult(res.map(|x| x.to_string())))
+                    .await
+            );
+        }
         Data::NatType(_) => {
             let t = Config::get_nat_type();
             allow_err!(stream.send(&Data::NatType(Some(t))).await);
@@ -641,18 +754,52 @@ pub fn update_temporary_password() -> ResultType<()> {
     set_config("temporary-password", "".to_owned())
 }
 
-pub fn get_permanent_password() -> String {
-    if let Ok(Some(v)) = get_config("permanent-password") {
-        Config::set_permanent_password(&v);
+pub fn get_permanent_password_hash() -> ResultType<PasswordHash> {
+    match get_config("permanent-password-hash")? {
+        Some(v) => Ok(serde_json::from_str(&v)?),
+        None => bail!("No permanent password hash"),
+    }
+}
+
+pub fn has_permanent_password() -> bool {
+    match get_permanent_password_hash() {
+        Ok(hash) => !hash.key.is_empty() || !hash.hash.is_empty(),
+        Err(_) => Config::has_permanent_password(),
+    }
+}
+
+pub fn get_salt() -> String {
+    if let Ok(Some(v)) = get_config("salt") {
         v
     } else {
-        Config::get_permanent_password()
+        Config::get_salt()
     }
 }
 
-pub fn set_permanent_password(v: String) -> ResultType<()> {
-    Config::set_permanent_password(&v);
-    set_config("permanent-password", v)
+/// Set the permanent password in the service, failing if it could not be.
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_permanent_password(v: String) -> ResultType<()> {
+    Config::set_permanent_password(&v)?;
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::PermanentPassword(v)).await?;
+    match c.next_timeout(3000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(()),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
+pub fn is_2fa_enabled() -> bool {
+    if let Ok(Some(v)) = get_config("totp") {
+        !v.is_empty()
+    } else {
+        crate::totp::is_enabled()
+    }
+}
+
+/// Enroll the secret of the two-factor authentication, an empty one disabling it.
+pub fn set_totp_secret(v: String) -> ResultType<()> {
+    set_config("totp-secret", v)
 }
 
 pub fn get_id() -> String {
@@ -689,7 +836,8 @@ async fn get_options_(ms_timeout: u64) -> ResultType<HashMap<String, String>> {
     let mut c = connect(ms_timeout, "").await?;
     c.send(&Data::Options(None)).await?;
     if let Some(Data::Options(Some(value))) = c.next_timeout(ms_timeout).await? {
-        Config::set_options(value.clone());
+        // as the service has them, whatever this version checks
+        Config::set_options(value.clone()).ok();
         Ok(value)
     } else {
         Ok(Config::get_options())
@@ -723,17 +871,138 @@ pub fn set_option(key: &str, value: &str) {
     set_options(options).ok();
 }
 
+/// Set the options here and in the service, failing with the invalid values, which
+/// are not set.
 #[tokio::main(flavor = "current_thread")]
 pub async fn set_options(value: HashMap<String, String>) -> ResultType<()> {
+    let mut rejected = None;
     if let Ok(mut c) = connect(1000, "").await {
         c.send(&Data::Options(Some(value.clone()))).await?;
         // do not put below before connect, because we need to check should_exit
-        c.next_timeout(1000).await.ok();
+        if let Ok(Some(Data::SetOptionResult(Some(err)))) = c.next_timeout(1000).await {
+            rejected = Some(err);
+        }
+    }
+    Config::set_options(value)?;
+    if let Some(err) = rejected {
+        bail!(err);
     }
-    Config::set_options(value);
     Ok(())
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_option_list() -> ResultType<Vec<OptionValue>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::OptionList(None)).await?;
+    if let Some(Data::OptionList(Some(list))) = c.next_timeout(1000).await? {
+        return Ok(list);
+    }
+    bail!("No option list");
+}
+
+/// Set an option in the service, failing if it is unknown or the value is invalid.
+#[tokio::main(flavor = "current_thread")]
+pub async fn try_set_option(name: &str, value: &str) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::SetOption((name.to_owned(), value.to_owned())))
+        .await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(()),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
+/// Verify and apply a signed config bundle in the service, which has the trusted keys,
+/// returning false if the service is not running.
+#[tokio::main(flavor = "current_thread")]
+pub async fn import_config_bundle(data: String) -> ResultType<bool> {
+    let mut c = match connect(1000, "").await {
+        Ok(c) => c,
+        Err(_) => return Ok(false),
+    };
+    c.send(&Data::ImportConfig(data)).await?;
+    match c.next_timeout(3000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(true),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
+fn check_default_profile() -> ResultType<()> {
+    if !Config::get_profile().is_empty() {
+        bail!("Profiles are managed in the default profile");
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_active_profiles() -> ResultType<Vec<String>> {
+    check_default_profile()?;
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::Profiles(None)).await?;
+    if let Some(Data::Profiles(Some(profiles))) = c.next_timeout(1000).await? {
+        return Ok(profiles);
+    }
+    bail!("No profiles");
+}
+
+/// Run the servers of `profiles` along with the one of the default profile, and
+/// stop the others.
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_active_profiles(profiles: Vec<String>) -> ResultType<()> {
+    check_default_profile()?;
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::Profiles(Some(profiles))).await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(()),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_authorized_keys() -> ResultType<Vec<AuthorizedKey>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::AuthorizedKeys(None)).await?;
+    if let Some(Data::AuthorizedKeys(Some(keys))) = c.next_timeout(1000).await? {
+        return Ok(keys);
+    }
+    bail!("No authorized keys");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_authorized_keys(keys: Vec<AuthorizedKey>) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::AuthorizedKeys(Some(keys))).await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(()),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_peer_acl() -> ResultType<Vec<Rule>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::PeerAcl(None)).await?;
+    if let Some(Data::PeerAcl(Some(rules))) = c.next_timeout(1000).await? {
+        return Ok(rules);
+    }
+    bail!("No peer access rules");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_peer_acl(rules: Vec<Rule>) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::PeerAcl(Some(rules))).await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::SetOptionResult(None)) => Ok(()),
+        Some(Data::SetOptionResult(Some(err))) => bail!(err),
+        _ => bail!("No response"),
+    }
+}
+
 #[inline]
 async fn get_nat_type_(ms_timeout: u64) -> ResultType<i32> {
     let mut c = connect(ms_timeout, "").await?;
@@ -34,5 +34,6 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("uac_warning", "Temporarily denied access due to elevation request, please wait for the remote user to accept the UAC dialog. To avoid this problem, it is recommended to install the software on the remote device or run it with administrator privileges."),
         ("elevated_foreground_window_warning", "Temporarily unable to use the mouse and keyboard, because the current window of the remote desktop requires higher privilege to operate, you can request the remote user to minimize the current window. To avoid this problem, it is recommended to install the software on the remote device or run it with administrator privileges."),
         ("JumpLink", "View"),
+        ("password_store_passphrase_tip", "Passphrase of the password store, required to remember the password"),
         ].iter().cloned().collect();
 }
@@ -44,6 +44,7 @@ mod port_forward;
 #[cfg(windows)]
 mod tray;
 
+pub mod totp;
 mod ui_cm_interface;
 mod ui_interface;
 mod ui_session_interface;
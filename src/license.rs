@@ -1,6 +1,27 @@
-use hbb_common::{bail, sodiumoxide::crypto::sign, ResultType};
+//! Licenses, in the name of the executable or the registry on Windows.
+//!
+//! A license is the JSON of [`License`], signed or not, in url safe base64 reversed. The
+//! `naming` tool generates signing key pairs, issues licenses and verifies them. Only
+//! signed licenses have an organization, an expiry date and features; a build with a
+//! `LICENSE_PUBLIC_KEY`, the base64 of the public key of the issuer, accepts licenses
+//! signed by it only. Other builds accept unsigned licenses until a signed one has been
+//! used, so that its expiry and features are not lifted by replacing it.
+use hbb_common::{bail, chrono::NaiveDate, config::Config, sodiumoxide::crypto::sign, ResultType};
 use serde_derive::{Deserialize, Serialize};
 
+/// What a license may be restricted to, an empty list granting all.
+pub const FEATURES: &[&str] = &[
+    "rendezvous-service",
+    "file-transfer",
+    "port-forward",
+    "recording",
+];
+
+const PK: &[u8; 32] = &[
+    88, 168, 68, 104, 60, 5, 163, 198, 165, 38, 12, 85, 114, 203, 96, 163, 70, 48, 0, 131, 57, 12,
+    46, 129, 83, 17, 84, 193, 119, 197, 130, 103,
+];
+
 #[derive(Debug, PartialEq, Default, Serialize, Deserialize, Clone)]
 pub struct License {
     #[serde(default)]
@@ -9,24 +30,109 @@ pub struct License {
     pub host: String,
     #[serde(default)]
     pub api: String,
+    /// The organization it is issued to.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub org: String,
+    /// The last valid day, `YYYY-MM-DD` in UTC, never expiring if empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub expiry: String,
+    /// The granted ones of [`FEATURES`], all if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    #[serde(skip)]
+    pub signed: bool,
+}
+
+impl License {
+    pub fn expiry_date(&self) -> ResultType<Option<NaiveDate>> {
+        if self.expiry.is_empty() {
+            return Ok(None);
+        }
+        match NaiveDate::parse_from_str(&self.expiry, "%Y-%m-%d") {
+            Ok(x) => Ok(Some(x)),
+            Err(_) => bail!("Invalid expiry date {}, expecting YYYY-MM-DD", self.expiry),
+        }
+    }
+
+    pub fn validate(&self) -> ResultType<()> {
+        self.expiry_date()?;
+        if let Some(x) = self
+            .features
+            .iter()
+            .find(|x| !FEATURES.contains(&x.as_str()))
+        {
+            bail!("Unknown feature {}, expecting one of {:?}", x, FEATURES);
+        }
+        Ok(())
+    }
+
+    /// Check the license has not expired at `today`.
+    pub fn check(&self, today: NaiveDate) -> ResultType<()> {
+        if let Some(expiry) = self.expiry_date()? {
+            if today > expiry {
+                bail!(
+                    "The license of {} expired on {}, please renew it",
+                    if self.org.is_empty() {
+                        "this copy"
+                    } else {
+                        &self.org
+                    },
+                    expiry
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.is_empty() || self.features.iter().any(|x| x == name)
+    }
+}
+
+/// Whether unsigned licenses are refused, the build having its own issuer key or a
+/// signed license having been used.
+pub fn signature_required() -> bool {
+    option_env!("LICENSE_PUBLIC_KEY").is_some() || Config::get_license_signed()
+}
+
+fn public_keys() -> Vec<sign::PublicKey> {
+    let mut res = Vec::new();
+    match option_env!("LICENSE_PUBLIC_KEY") {
+        Some(pk) => {
+            if let Some(pk) = base64::decode(pk)
+                .ok()
+                .and_then(|x| sign::PublicKey::from_slice(&x))
+            {
+                res.push(pk);
+            }
+        }
+        None => res.push(sign::PublicKey(*PK)),
+    }
+    res
 }
 
 fn get_license_from_string_(s: &str) -> ResultType<License> {
     let tmp: String = s.chars().rev().collect();
-    const PK: &[u8; 32] = &[
-        88, 168, 68, 104, 60, 5, 163, 198, 165, 38, 12, 85, 114, 203, 96, 163, 70, 48, 0, 131, 57,
-        12, 46, 129, 83, 17, 84, 193, 119, 197, 130, 103,
-    ];
-    let pk = sign::PublicKey(*PK);
     let data = base64::decode_config(tmp, base64::URL_SAFE_NO_PAD)?;
-    if let Ok(lic) = serde_json::from_slice::<License>(&data) {
+    if let Ok(mut lic) = serde_json::from_slice::<License>(&data) {
+        if signature_required() {
+            bail!("Unsigned license");
+        }
+        // only signed licenses are trusted with these
+        lic.org.clear();
+        lic.expiry.clear();
+        lic.features.clear();
         return Ok(lic);
     }
-    if let Ok(data) = sign::verify(&data, &pk) {
-        Ok(serde_json::from_slice::<License>(&data)?)
-    } else {
-        bail!("sign:verify failed");
+    for pk in public_keys() {
+        if let Ok(data) = sign::verify(&data, &pk) {
+            let mut lic = serde_json::from_slice::<License>(&data)?;
+            lic.validate()?;
+            lic.signed = true;
+            return Ok(lic);
+        }
     }
+    bail!("sign:verify failed");
 }
 
 pub fn get_license_from_string(s: &str) -> ResultType<License> {
@@ -36,6 +142,9 @@ pub fn get_license_from_string(s: &str) -> ResultType<License> {
         s
     };
     if s.contains("host=") {
+        if signature_required() {
+            bail!("Unsigned license");
+        }
         let strs: Vec<&str> = s.split("host=").collect();
         if strs.len() == 2 {
             let strs2: Vec<&str> = strs[1].split(",key=").collect();
@@ -50,7 +159,7 @@ pub fn get_license_from_string(s: &str) -> ResultType<License> {
             return Ok(License {
                 host: host.to_owned(),
                 key: key.to_owned(),
-                api: "".to_owned(),
+                ..Default::default()
             });
         }
     } else {
@@ -59,11 +168,42 @@ pub fn get_license_from_string(s: &str) -> ResultType<License> {
         } else {
             s.split("--")
         };
+        let mut err = None;
         for s in strs {
-            if let Ok(lic) = get_license_from_string_(s) {
-                return Ok(lic);
+            match get_license_from_string_(s) {
+                Ok(lic) => return Ok(lic),
+                Err(e) => err = Some(e),
             }
         }
+        if let Some(err) = err {
+            bail!("Failed to parse: {}", err);
+        }
     }
     bail!("Failed to parse");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let day = |x| NaiveDate::parse_from_str(x, "%Y-%m-%d").unwrap();
+        let mut lic = License {
+            expiry: "2030-01-31".to_owned(),
+            features: vec!["recording".to_owned()],
+            ..Default::default()
+        };
+        assert!(lic.validate().is_ok());
+        assert!(lic.check(day("2030-01-31")).is_ok());
+        assert!(lic.check(day("2030-02-01")).is_err());
+        assert!(lic.has_feature("recording"));
+        assert!(!lic.has_feature("file-transfer"));
+        lic.features.push("admin".to_owned());
+        assert!(lic.validate().is_err());
+        lic.features.clear();
+        lic.expiry = "31/01/2030".to_owned();
+        assert!(lic.validate().is_err());
+        assert!(lic.has_feature("file-transfer"));
+    }
+}
@@ -38,6 +38,7 @@ fn main() {
     let args = format!(
         "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
         -k, --key=[KEY] ''
+        -t, --tfa-code=[CODE] 'Code of the two-factor authentication if the peer asks for it, prompted for if missing'
        -s, --server... 'Start server'",
     );
     let matches = App::new("deskviewer")
@@ -74,7 +75,8 @@ fn main() {
         }
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
-        cli::start_one_port_forward(options[0].clone(), port, remote_host, remote_port, key, token);
+        let tfa_code = matches.value_of("tfa-code").unwrap_or("").to_owned();
+        cli::start_one_port_forward(options[0].clone(), port, remote_host, remote_port, key, token, tfa_code);
     }
     common::global_clean();
 }
\ No newline at end of file
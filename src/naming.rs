@@ -1,12 +1,95 @@
 mod license;
-use hbb_common::ResultType;
+use hbb_common::{bail, chrono, sodiumoxide::crypto::sign, ResultType};
 use license::*;
 
+const USAGE: &str = "Usage:
+    naming <key> <host> [api]
+        the name of an executable with an unsigned license
+    naming keygen
+        a key pair to sign licenses with
+    naming issue <secret key> <key> <host> [api] [--org <name>] [--expiry YYYY-MM-DD] [--features a,b]
+        the name of an executable with a signed license
+    naming verify <name>
+        the license of an executable name, as this build checks it";
+
+fn encode(data: &[u8]) -> String {
+    let tmp = base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+    tmp.chars().rev().collect()
+}
+
 fn gen_name(lic: &License) -> ResultType<String> {
     let tmp = serde_json::to_vec::<License>(lic)?;
-    let tmp = base64::encode_config(tmp, base64::URL_SAFE_NO_PAD);
-    let tmp: String = tmp.chars().rev().collect();
-    Ok(tmp)
+    Ok(encode(&tmp))
+}
+
+fn keygen() {
+    let (pk, sk) = sign::gen_keypair();
+    println!("secret key: {}", base64::encode(&sk[..]));
+    println!("public key: {}", base64::encode(&pk[..]));
+    println!("Build with LICENSE_PUBLIC_KEY=<public key> to accept the licenses it signs only");
+}
+
+fn issue(args: &[String]) -> ResultType<String> {
+    if args.len() < 3 {
+        bail!("Missing arguments\n{}", USAGE);
+    }
+    let sk = match base64::decode(&args[0])
+        .ok()
+        .and_then(|x| sign::SecretKey::from_slice(&x))
+    {
+        Some(x) => x,
+        None => bail!("Invalid secret key"),
+    };
+    let mut lic = License {
+        key: args[1].clone(),
+        host: args[2].clone(),
+        ..Default::default()
+    };
+    let mut it = args[3..].iter();
+    while let Some(arg) = it.next() {
+        let mut value = || match it.next() {
+            Some(x) => Ok(x.clone()),
+            None => bail!("Missing the value of {}", arg),
+        };
+        match arg.as_str() {
+            "--org" => lic.org = value()?,
+            "--expiry" => lic.expiry = value()?,
+            "--features" => {
+                lic.features = value()?
+                    .split(',')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_owned())
+                    .collect()
+            }
+            x if !x.starts_with("--") && lic.api.is_empty() => lic.api = x.to_owned(),
+            x => bail!("Unknown argument {}\n{}", x, USAGE),
+        }
+    }
+    lic.validate()?;
+    let data = sign::sign(&serde_json::to_vec(&lic)?, &sk);
+    Ok(format!("rustdesk-licensed-{}.exe", encode(&data)))
+}
+
+fn verify(name: &str) -> ResultType<()> {
+    let lic = get_license_from_string(name)?;
+    println!("key: {}", lic.key);
+    println!("host: {}", lic.host);
+    println!("api: {}", lic.api);
+    println!("signed: {}", lic.signed);
+    if lic.signed {
+        println!("org: {}", lic.org);
+        println!("expiry: {}", lic.expiry);
+        let features: Vec<&str> = FEATURES
+            .iter()
+            .filter(|x| lic.has_feature(x))
+            .cloned()
+            .collect();
+        println!("features: {}", features.join(","));
+    }
+    if signature_required() {
+        println!("only signed licenses are accepted here");
+    }
+    lic.check(chrono::Utc::now().naive_utc().date())
 }
 
 fn main() {
@@ -18,23 +101,31 @@ fn main() {
         }
         i += 1;
     }
-    let api = if args.len() < 3 {
-        "".to_owned()
-    } else {
-        args[2].clone()
+    let res = match args.first().map(|x| x.as_str()) {
+        None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some("keygen") => {
+            keygen();
+            Ok(())
+        }
+        Some("issue") => issue(&args[1..]).map(|x| println!("{}", x)),
+        Some("verify") if args.len() == 2 => verify(&args[1]),
+        Some(_) if args.len() == 1 => {
+            println!("{:?}", get_license_from_string(&args[0]));
+            Ok(())
+        }
+        Some(_) => gen_name(&License {
+            key: args[0].clone(),
+            host: args[1].clone(),
+            api: args.get(2).cloned().unwrap_or_default(),
+            ..Default::default()
+        })
+        .map(|x| println!("rustdesk-licensed-{}.exe", x)),
     };
-    if args.len() >= 2 {
-        println!(
-            "rustdesk-licensed-{}.exe",
-            gen_name(&License {
-                key: args[0].clone(),
-                host: args[1].clone(),
-                api,
-            })
-            .unwrap()
-        );
-    }
-    if args.len() == 1 {
-        println!("{:?}", get_license_from_string(&args[0]));
+    if let Err(err) = res {
+        println!("{}", err);
+        std::process::exit(1);
     }
 }
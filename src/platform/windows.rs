@@ -2,7 +2,7 @@ use super::{CursorData, ResultType};
 use crate::ipc;
 use crate::license::*;
 use hbb_common::{
-    allow_err, bail,
+    allow_err, bail, chrono,
     config::{self, Config},
     log, sleep, timeout, tokio,
 };
@@ -1102,7 +1102,7 @@ copy /Y \"{tmp_path}\\{app_name} Tray.lnk\" \"%PROGRAMDATA%\\Microsoft\\Windows\
 {shortcuts}
 copy /Y \"{tmp_path}\\Uninstall {app_name}.lnk\" \"{path}\\\"
 {dels}
-sc create {app_name} binpath= \"\\\"{exe}\\\" --import-config \\\"{config_path}\\\"\" start= auto DisplayName= \"{app_name} Service\"
+sc create {app_name} binpath= \"\\\"{exe}\\\" --import-config \\\"{config_path}\\\" --unsigned\" start= auto DisplayName= \"{app_name} Service\"
 sc start {app_name}
 sc stop {app_name}
 sc delete {app_name}
@@ -1329,7 +1329,9 @@ pub fn get_license() -> Option<License> {
     let mut lic: License = Default::default();
     if let Ok(tmp) = get_license_from_exe_name() {
         lic = tmp;
-    } else {
+    } else if let Ok(tmp) = get_license_from_string(&get_reg("License")) {
+        lic = tmp;
+    } else if !signature_required() {
         lic.key = get_reg("Key");
         lic.host = get_reg("Host");
         lic.api = get_reg("Api");
@@ -1337,15 +1339,53 @@ pub fn get_license() -> Option<License> {
     if lic.key.is_empty() || lic.host.is_empty() {
         return None;
     }
+    if lic.signed {
+        Config::set_license_signed();
+    }
     Some(lic)
 }
 
+/// Refuse to run with a license which is invalid, e.g. badly signed, or expired.
+pub fn check_license() -> ResultType<()> {
+    let exe = std::env::current_exe()?.to_str().unwrap_or("").to_owned();
+    if exe.contains("-licensed-") || exe.contains("host=") {
+        if let Err(err) = get_license_from_exe_name() {
+            bail!("Invalid license in the name of the executable: {}", err);
+        }
+    }
+    if let Some(lic) = get_license() {
+        lic.check(chrono::Utc::now().naive_utc().date())?;
+    }
+    Ok(())
+}
+
+/// Show `text` as an error in a modal message box, for when there is no window yet.
+pub fn error_box(text: &str) {
+    use std::os::windows::ffi::OsStrExt;
+    let wide = |s: &str| -> Vec<u16> {
+        std::ffi::OsStr::new(s)
+            .encode_wide()
+            .chain(Some(0).into_iter())
+            .collect()
+    };
+    let text = wide(text);
+    let title = wide(&crate::get_app_name());
+    unsafe {
+        MessageBoxW(
+            NULL as _,
+            text.as_ptr(),
+            title.as_ptr(),
+            MB_OK | MB_ICONERROR,
+        );
+    }
+}
+
 pub fn bootstrap() {
     if let Some(lic) = get_license() {
         *config::PROD_RENDEZVOUS_SERVER.write().unwrap() = lic.host.clone();
         #[cfg(feature = "hbbs")]
         {
-            if !is_win_server() {
+            if !is_win_server() || !crate::common::is_licensed("rendezvous-service") {
                 return;
             }
             crate::hbbs::bootstrap(&lic.key, &lic.host);
@@ -1370,11 +1410,16 @@ fn register_licence() -> String {
         reg add {subkey} /f /v Key /t REG_SZ /d \"{key}\"
         reg add {subkey} /f /v Host /t REG_SZ /d \"{host}\"
         reg add {subkey} /f /v Api /t REG_SZ /d \"{api}\"
+        reg add {subkey} /f /v License /t REG_SZ /d \"{license}\"
     ",
             subkey = subkey,
             key = &lic.key,
             host = &lic.host,
             api = &lic.api,
+            license = std::env::current_exe()
+                .ok()
+                .and_then(|x| x.file_name().map(|x| x.to_string_lossy().to_string()))
+                .unwrap_or_default(),
         )
     } else {
         "".to_owned()
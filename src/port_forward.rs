@@ -202,6 +202,9 @@ async fn connect_and_login_2(
                     Some(Data::Login((password, remember))) => {
                         interface.handle_login_from_ui(password, remember, &mut stream).await;
                     }
+                    Some(Data::Login2fa(code)) => {
+                        interface.handle_login_2fa(code, &mut stream).await;
+                    }
                     _ => {}
                 }
             },
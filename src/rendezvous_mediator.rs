@@ -13,6 +13,7 @@ use hbb_common::tcp::FramedStream;
 use hbb_common::{
     allow_err,
     anyhow::bail,
+    bytes::BytesMut,
     config::{Config, REG_INTERVAL, RENDEZVOUS_PORT, RENDEZVOUS_TIMEOUT},
     futures::future::join_all,
     log,
@@ -24,7 +25,7 @@ use hbb_common::{
         time::{interval, Duration},
     },
     udp::FramedSocket,
-    AddrMangle, IntoTargetAddr, ResultType, TargetAddr,
+    websocket, AddrMangle, IntoTargetAddr, ResultType, TargetAddr,
 };
 
 use crate::server::{check_zombie, new as new_server, ServerPtr};
@@ -36,6 +37,29 @@ lazy_static::lazy_static! {
 }
 static SHOULD_EXIT: AtomicBool = AtomicBool::new(false);
 
+/// Registration channel to the rendezvous server, udp normally, a websocket
+/// if the server is given as a ws:// or wss:// url.
+enum Socket {
+    Udp(FramedSocket),
+    Ws(FramedStream),
+}
+
+impl Socket {
+    async fn send(&mut self, msg: &Message, addr: TargetAddr<'static>) -> ResultType<()> {
+        match self {
+            Socket::Udp(socket) => socket.send(msg, addr).await,
+            Socket::Ws(stream) => stream.send(msg).await,
+        }
+    }
+
+    async fn next(&mut self) -> Option<ResultType<BytesMut>> {
+        match self {
+            Socket::Udp(socket) => socket.next().await.map(|res| res.map(|(bytes, _)| bytes)),
+            Socket::Ws(stream) => stream.next().await.map(|res| Ok(res?)),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct RendezvousMediator {
     addr: TargetAddr<'static>,
@@ -115,9 +139,13 @@ impl RendezvousMediator {
             last_id_pk_registry: "".to_owned(),
         };
 
-        rz.addr = socket_client::get_target_addr(&crate::check_port(&host, RENDEZVOUS_PORT))?;
         let any_addr = Config::get_any_listen_addr();
-        let mut socket = socket_client::new_udp(any_addr, RENDEZVOUS_TIMEOUT).await?;
+        let mut socket = if rz.is_ws() {
+            Socket::Ws(rz.connect_rendezvous().await?)
+        } else {
+            rz.addr = socket_client::get_target_addr(&crate::check_port(&host, RENDEZVOUS_PORT))?;
+            Socket::Udp(socket_client::new_udp(any_addr, RENDEZVOUS_TIMEOUT).await?)
+        };
 
         const TIMER_OUT: Duration = Duration::from_secs(1);
         let mut timer = interval(TIMER_OUT);
@@ -161,7 +189,7 @@ impl RendezvousMediator {
             select! {
                 n = socket.next() => {
                     match n {
-                        Some(Ok((bytes, _))) => {
+                        Some(Ok(bytes)) => {
                             if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                                 match msg_in.union {
                                     Some(rendezvous_message::Union::RegisterPeerResponse(rpr)) => {
@@ -209,7 +237,7 @@ impl RendezvousMediator {
                                     }
                                     Some(rendezvous_message::Union::ConfigureUpdate(cu)) => {
                                         let v0 = Config::get_rendezvous_servers();
-                                        Config::set_option("rendezvous-servers".to_owned(), cu.rendezvous_servers.join(","));
+                                        Config::set_option("rendezvous-servers".to_owned(), cu.rendezvous_servers.join(",")).ok();
                                         Config::set_serial(cu.serial);
                                         if v0 != Config::get_rendezvous_servers() {
                                             Self::restart();
@@ -248,11 +276,16 @@ impl RendezvousMediator {
                                 Config::update_latency(&host, -1);
                                 old_latency = 0;
                                 if last_dns_check.elapsed().as_millis() as i64 > DNS_INTERVAL {
-                                    rz.addr = socket_client::get_target_addr(&crate::check_port(&host, RENDEZVOUS_PORT))?;
-                                    // in some case of network reconnect (dial IP network),
-                                    // old UDP socket not work any more after network recover
-                                    if let Some(s) = socket_client::rebind_udp(any_addr).await? {
-                                        socket = s;
+                                    if rz.is_ws() {
+                                        // the websocket may be silently dropped by a middlebox
+                                        socket = Socket::Ws(rz.connect_rendezvous().await?);
+                                    } else {
+                                        rz.addr = socket_client::get_target_addr(&crate::check_port(&host, RENDEZVOUS_PORT))?;
+                                        // in some case of network reconnect (dial IP network),
+                                        // old UDP socket not work any more after network recover
+                                        if let Some(s) = socket_client::rebind_udp(any_addr).await? {
+                                            socket = Socket::Udp(s);
+                                        }
                                     }
                                     last_dns_check = Instant::now();
                                 }
@@ -268,10 +301,38 @@ impl RendezvousMediator {
         Ok(())
     }
 
+    #[inline]
+    fn is_ws(&self) -> bool {
+        websocket::is_ws_url(&self.host)
+    }
+
+    async fn connect_rendezvous(&self) -> ResultType<FramedStream> {
+        if self.is_ws() {
+            socket_client::connect_server(
+                &self.host,
+                Config::get_any_listen_addr(),
+                RENDEZVOUS_TIMEOUT,
+            )
+            .await
+        } else {
+            socket_client::connect_tcp(
+                self.addr.to_owned(),
+                Config::get_any_listen_addr(),
+                RENDEZVOUS_TIMEOUT,
+            )
+            .await
+        }
+    }
+
     async fn handle_request_relay(&self, rr: RequestRelay, server: ServerPtr) -> ResultType<()> {
+        let relay_server = if self.is_ws() {
+            websocket::relay_url(&self.host, &rr.relay_server)
+        } else {
+            rr.relay_server
+        };
         self.create_relay(
             rr.socket_addr.into(),
-            rr.relay_server,
+            relay_server,
             rr.uuid,
             server,
             rr.secure,
@@ -298,12 +359,7 @@ impl RendezvousMediator {
             secure,
         );
 
-        let mut socket = socket_client::connect_tcp(
-            self.addr.to_owned(),
-            Config::get_any_listen_addr(),
-            RENDEZVOUS_TIMEOUT,
-        )
-        .await?;
+        let mut socket = self.connect_rendezvous().await?;
 
         let mut msg_out = Message::new();
         let mut rr = RelayResponse {
@@ -323,14 +379,23 @@ impl RendezvousMediator {
     }
 
     async fn handle_intranet(&self, fla: FetchLocalAddr, server: ServerPtr) -> ResultType<()> {
+        if self.is_ws() {
+            let relay_server = self.get_relay_server(fla.relay_server);
+            let uuid = Uuid::new_v4().to_string();
+            return self
+                .create_relay(
+                    fla.socket_addr.into(),
+                    relay_server,
+                    uuid,
+                    server,
+                    true,
+                    true,
+                )
+                .await;
+        }
         let peer_addr = AddrMangle::decode(&fla.socket_addr);
         log::debug!("Handle intranet from {:?}", peer_addr);
-        let mut socket = socket_client::connect_tcp(
-            self.addr.to_owned(),
-            Config::get_any_listen_addr(),
-            RENDEZVOUS_TIMEOUT,
-        )
-        .await?;
+        let mut socket = self.connect_rendezvous().await?;
         let local_addr = socket.local_addr();
         let local_addr: SocketAddr =
             format!("{}:{}", local_addr.ip(), local_addr.port()).parse()?;
@@ -354,6 +419,7 @@ impl RendezvousMediator {
         let relay_server = self.get_relay_server(ph.relay_server);
         if ph.nat_type.enum_value_or_default() == NatType::SYMMETRIC
             || Config::get_nat_type() == NatType::SYMMETRIC as i32
+            || self.is_ws()
         {
             let uuid = Uuid::new_v4().to_string();
             return self
@@ -370,12 +436,7 @@ impl RendezvousMediator {
         let peer_addr = AddrMangle::decode(&ph.socket_addr);
         log::debug!("Punch hole to {:?}", peer_addr);
         let mut socket = {
-            let socket = socket_client::connect_tcp(
-                self.addr.to_owned(),
-                Config::get_any_listen_addr(),
-                RENDEZVOUS_TIMEOUT,
-            )
-            .await?;
+            let socket = self.connect_rendezvous().await?;
             let local_addr = socket.local_addr();
             allow_err!(socket_client::connect_tcp(peer_addr, local_addr, 300).await);
             socket
@@ -397,7 +458,7 @@ impl RendezvousMediator {
         Ok(())
     }
 
-    async fn register_pk(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_pk(&mut self, socket: &mut Socket) -> ResultType<()> {
         let mut msg_out = Message::new();
         let pk = Config::get_key_pair().1;
         let uuid = hbb_common::get_uuid();
@@ -413,7 +474,7 @@ impl RendezvousMediator {
         Ok(())
     }
 
-    async fn handle_uuid_mismatch(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn handle_uuid_mismatch(&mut self, socket: &mut Socket) -> ResultType<()> {
         if self.last_id_pk_registry != Config::get_id() {
             return Ok(());
         }
@@ -431,7 +492,7 @@ impl RendezvousMediator {
         self.register_pk(socket).await
     }
 
-    async fn register_peer(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_peer(&mut self, socket: &mut Socket) -> ResultType<()> {
         if !SOLVING_PK_MISMATCH.lock().unwrap().is_empty() {
             return Ok(());
         }
@@ -464,6 +525,9 @@ impl RendezvousMediator {
         if relay_server.is_empty() {
             relay_server = provided_by_rendzvous_server;
         }
+        if self.is_ws() {
+            return websocket::relay_url(&self.host, &relay_server);
+        }
         if relay_server.is_empty() {
             if self.host.contains(":") {
                 let tmp: Vec<&str> = self.host.split(":").collect();
@@ -484,7 +548,7 @@ fn get_direct_port() -> i32 {
         .parse::<i32>()
         .unwrap_or(0);
     if port <= 0 {
-        port = RENDEZVOUS_PORT + 2;
+        port = RENDEZVOUS_PORT + 2 + Config::get_profile_port_offset();
     }
     port
 }
@@ -539,6 +603,7 @@ async fn direct_server(server: ServerPtr) {
                             hbb_common::Stream::from(stream, local_addr),
                             addr,
                             false,
+                            true,
                         )
                         .await
                     );
@@ -570,6 +635,10 @@ pub fn get_mac() -> String {
 
 #[allow(dead_code)]
 fn lan_discovery() -> ResultType<()> {
+    // peers ping the well-known port, which only the default profile answers on
+    if !Config::get_profile().is_empty() {
+        return Ok(());
+    }
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
@@ -639,6 +708,9 @@ pub async fn query_online_states<F: FnOnce(Vec<String>, Vec<String>)>(ids: Vec<S
 
 async fn create_online_stream() -> ResultType<FramedStream> {
     let (rendezvous_server, _servers, _contained) = crate::get_rendezvous_server(1_000).await;
+    if websocket::is_ws_url(&rendezvous_server) {
+        bail!("Online states are not served over websocket");
+    }
     let tmp: Vec<&str> = rendezvous_server.split(":").collect();
     if tmp.len() != 2 {
         bail!("Invalid server address: {}", rendezvous_server);
@@ -44,7 +44,11 @@ pub const NAME_POS: &'static str = "";
 }
 }
 
+mod audit_log;
 mod connection;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod host_record;
+mod login_guard;
 mod service;
 mod video_qos;
 pub mod video_service;
@@ -95,7 +99,14 @@ async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) ->
     if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
         stream.set_nodelay(true).ok();
         let stream_addr = stream.local_addr()?;
-        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure).await?;
+        create_tcp_connection(
+            server,
+            Stream::from(stream, stream_addr),
+            addr,
+            secure,
+            true,
+        )
+        .await?;
     }
     Ok(())
 }
@@ -114,20 +125,23 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+    #[allow(unused_variables)] direct: bool,
 ) -> ResultType<()> {
     let mut stream = stream;
     check_privacy_mode_on(&mut stream).await?;
 
-    let id = {
-        let mut w = server.write().unwrap();
-        w.id_count += 1;
-        w.id_count
-    };
+    let id = server.write().unwrap().get_new_id();
     let (sk, pk) = Config::get_key_pair();
     if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
         let mut sk_ = [0u8; sign::SECRETKEYBYTES];
         sk_[..].copy_from_slice(&sk);
         let sk = sign::SecretKey(sk_);
+        #[cfg(feature = "quic")]
+        let mut quic_server = if direct { new_quic_server() } else { None };
+        #[cfg(feature = "quic")]
+        let quic_port = quic_server.as_ref().map(|x| x.1).unwrap_or(0);
+        #[cfg(not(feature = "quic"))]
+        let quic_port = 0;
         let mut msg_out = Message::new();
         let (our_pk_b, our_sk_b) = box_::gen_keypair();
         msg_out.set_signed_id(SignedId {
@@ -141,6 +155,7 @@ pub async fn create_tcp_connection(
                 .unwrap_or_default(),
                 &sk,
             ).into(),
+            quic_port,
             ..Default::default()
         });
         timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
@@ -165,6 +180,12 @@ pub async fn create_tcp_connection(
                             let mut key = [0u8; secretbox::KEYBYTES];
                             key[..].copy_from_slice(&symmetric_key);
                             stream.set_key(secretbox::Key(key));
+                            #[cfg(feature = "quic")]
+                            if pk.try_quic {
+                                if let Some((quic_server, _)) = quic_server.as_mut() {
+                                    upgrade_to_quic(&mut stream, quic_server, addr).await?;
+                                }
+                            }
                         } else if pk.asymmetric_value.is_empty() {
                             Config::set_key_confirmed(false);
                             log::info!("Force to update pk");
@@ -188,6 +209,48 @@ pub async fn create_tcp_connection(
     Ok(())
 }
 
+#[cfg(feature = "quic")]
+fn new_quic_server() -> Option<(hbb_common::quic::Server, u32)> {
+    if !hbb_common::options::get_bool("enable-quic") {
+        return None;
+    }
+    match hbb_common::quic::new_server(Config::get_any_listen_addr()) {
+        Ok(server) => {
+            let port = server.local_addr().map(|x| x.port() as u32).unwrap_or(0);
+            Some((server, port))
+        }
+        Err(err) => {
+            log::error!("Failed to create quic endpoint: {}", err);
+            None
+        }
+    }
+}
+
+/// Wait for the client's decision and move the session onto quic if it managed to connect.
+#[cfg(feature = "quic")]
+async fn upgrade_to_quic(
+    stream: &mut Stream,
+    quic_server: &mut hbb_common::quic::Server,
+    addr: SocketAddr,
+) -> ResultType<()> {
+    let bytes = match timeout(CONNECT_TIMEOUT, stream.next()).await? {
+        Some(res) => res?,
+        None => bail!("Reset by the peer"),
+    };
+    match Message::parse_from_bytes(&bytes)?.union {
+        Some(message::Union::QuicUpgrade(qu)) => {
+            if qu.switched {
+                let conn = timeout(CONNECT_TIMEOUT, quic_server.accept(addr.ip())).await??;
+                log::info!("Connection from {} upgraded to quic", addr);
+                let local_addr = conn.local_addr();
+                stream.set_transport(conn, local_addr);
+            }
+        }
+        _ => bail!("Handshake failed: expecting quic upgrade"),
+    }
+    Ok(())
+}
+
 pub async fn accept_connection(
     server: ServerPtr,
     socket: Stream,
@@ -225,8 +288,8 @@ async fn create_relay_connection_(
     peer_addr: SocketAddr,
     secure: bool,
 ) -> ResultType<()> {
-    let mut stream = socket_client::connect_tcp(
-        crate::check_port(relay_server, RELAY_PORT),
+    let mut stream = socket_client::connect_server(
+        &crate::check_port(relay_server, RELAY_PORT),
         Config::get_any_listen_addr(),
         CONNECT_TIMEOUT,
     )
@@ -242,11 +305,16 @@ async fn create_relay_connection_(
         ..Default::default()
     });
     stream.send(&msg_out).await?;
-    create_tcp_connection(server, stream, peer_addr, secure).await?;
+    create_tcp_connection(server, stream, peer_addr, secure, false).await?;
     Ok(())
 }
 
 impl Server {
+    pub fn get_new_id(&mut self) -> i32 {
+        self.id_count += 1;
+        self.id_count
+    }
+
     pub fn add_connection(&mut self, conn: ConnInner, noperms: &Vec<&'static str>) {
         for s in self.services.values() {
             if !noperms.contains(&s.name()) {
@@ -358,6 +426,11 @@ pub async fn start_server(is_server: bool) {
         input_service::fix_key_down_timeout_loop();
         #[cfg(target_os = "macos")]
         tokio::spawn(async { sync_and_watch_config_dir().await });
+        if Config::get_profile().is_empty() {
+            tokio::spawn(async { run_profile_servers().await });
+        } else if std::env::args().any(|x| x == WATCH_PARENT) {
+            watch_parent();
+        }
         crate::RendezvousMediator::start_all().await;
     } else {
         match crate::ipc::connect(1000, "").await {
@@ -386,6 +459,73 @@ pub async fn start_server(is_server: bool) {
     }
 }
 
+/// Argument of the profile servers, exiting when their stdin, a pipe from the server of the
+/// default profile, is closed.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const WATCH_PARENT: &str = "--watch-parent";
+
+/// Exit once the parent closes stdin, e.g. because it has died.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn watch_parent() {
+    std::thread::spawn(|| {
+        let mut buf = [0u8; 64];
+        loop {
+            match std::io::Read::read(&mut std::io::stdin(), &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        log::info!("The server of the default profile is gone, exit");
+        std::process::exit(0);
+    });
+}
+
+/// Run the servers of the active profiles, each registering its own id, and keep them
+/// in line with [`Config::get_active_profiles`].
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+async fn run_profile_servers() {
+    let mut servers: HashMap<String, std::process::Child> = HashMap::new();
+    loop {
+        let active = Config::get_active_profiles();
+        servers.retain(|name, child| {
+            match child.try_wait() {
+                Ok(None) => {
+                    if active.contains(name) {
+                        return true;
+                    }
+                    log::info!("Stop the server of profile {}", name);
+                    allow_err!(child.kill());
+                    child.wait().ok();
+                }
+                Ok(Some(status)) => {
+                    log::error!("Server of profile {} exited: {}", name, status);
+                }
+                Err(err) => {
+                    log::error!("Failed to check the server of profile {}: {}", name, err);
+                }
+            }
+            false
+        });
+        for name in active {
+            if servers.contains_key(&name) {
+                continue;
+            }
+            log::info!("Start the server of profile {}", name);
+            // the child exits once the pipe closes, i.e. once this process is gone
+            let child =
+                crate::common::me_command(vec!["--server", "--profile", &name, WATCH_PARENT])
+                    .and_then(|mut x| x.stdin(std::process::Stdio::piped()).spawn());
+            match child {
+                Ok(child) => {
+                    servers.insert(name, child);
+                }
+                Err(err) => log::error!("Failed to start the server of profile {}: {}", name, err),
+            }
+        }
+        hbb_common::sleep(3.).await;
+    }
+}
+
 #[cfg(target_os = "macos")]
 async fn sync_and_watch_config_dir() {
     if crate::platform::is_root() {
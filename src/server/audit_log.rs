@@ -0,0 +1,155 @@
+//! Local audit log of the security events of the connections.
+//!
+//! Whatever the api server, with "enable-audit-log" every event, a JSON object with an
+//! "action", is written as a line of `audit.jsonl` in the "audit" directory next to the
+//! logs. Past "audit-log-max-size" megabytes, the file becomes `audit.1.jsonl`, the older
+//! ones shifting up to "audit-log-max-files". With "audit-syslog" the events also go to
+//! syslog, and so to journald, on Linux and macOS.
+use hbb_common::{chrono, config::Config, log, options, ResultType};
+use serde_json::{json, Value};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Actions logged with the warning severity, the others being informational.
+const WARNINGS: &[&str] = &[
+    "login-failure",
+    "login-banned",
+    "acl-denied",
+    "outside-access-windows",
+];
+
+struct AuditLog {
+    file: File,
+    size: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref LOG: Mutex<Option<AuditLog>> = Default::default();
+}
+
+pub fn dir() -> PathBuf {
+    Config::log_path().join("audit")
+}
+
+/// The current file for 0, the rotated ones after.
+fn path(dir: &Path, i: usize) -> PathBuf {
+    if i == 0 {
+        dir.join("audit.jsonl")
+    } else {
+        dir.join(format!("audit.{}.jsonl", i))
+    }
+}
+
+fn open(dir: &Path) -> ResultType<AuditLog> {
+    std::fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path(dir, 0))?;
+    let size = file.metadata()?.len();
+    Ok(AuditLog { file, size })
+}
+
+/// Shift the files by one, dropping the one past `max_files` rotated ones.
+fn rotate(dir: &Path, max_files: usize) -> ResultType<()> {
+    let last = path(dir, max_files);
+    if last.exists() {
+        std::fs::remove_file(last)?;
+    }
+    for i in (0..max_files).rev() {
+        let from = path(dir, i);
+        if from.exists() {
+            std::fs::rename(from, path(dir, i + 1))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_line(line: &str) -> ResultType<()> {
+    let dir = dir();
+    let max_size = options::get_number("audit-log-max-size") as u64 * 1024 * 1024;
+    let mut lock = LOG.lock().unwrap();
+    if lock.is_none() {
+        *lock = Some(open(&dir)?);
+    }
+    let len = line.len() as u64 + 1;
+    if let Some(log) = lock.as_ref() {
+        if log.size > 0 && log.size + len > max_size {
+            *lock = None;
+            rotate(&dir, options::get_number("audit-log-max-files") as _)?;
+            *lock = Some(open(&dir)?);
+        }
+    }
+    if let Some(log) = lock.as_mut() {
+        writeln!(log.file, "{}", line)?;
+        log.size += len;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn syslog(warning: bool, line: &str) {
+    use std::os::unix::net::UnixDatagram;
+    const AUTHPRIV: u8 = 10;
+    let severity = if warning { 4 } else { 6 };
+    let socket = if cfg!(target_os = "macos") {
+        "/var/run/syslog"
+    } else {
+        "/dev/log"
+    };
+    let msg = format!(
+        "<{}>{} {}[{}]: {}",
+        AUTHPRIV * 8 + severity,
+        chrono::Local::now().format("%b %e %H:%M:%S"),
+        crate::get_app_name(),
+        std::process::id(),
+        line
+    );
+    if let Err(err) = UnixDatagram::unbound().and_then(|x| x.send_to(msg.as_bytes(), socket)) {
+        log::error!("Failed to send the audit event to syslog: {}", err);
+    }
+}
+
+/// Write the event `v` with its time.
+pub fn write(mut v: Value) {
+    v["time"] = json!(chrono::Local::now().to_rfc3339());
+    let line = v.to_string();
+    if options::get_bool("enable-audit-log") {
+        if let Err(err) = write_line(&line) {
+            log::error!("Failed to write the audit log: {}", err);
+        }
+    }
+    #[cfg(unix)]
+    if options::get_bool("audit-syslog") {
+        let warning = v["action"]
+            .as_str()
+            .map(|x| WARNINGS.contains(&x))
+            .unwrap_or(false);
+        syslog(warning, &line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("audit_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let read = |i| std::fs::read_to_string(path(&dir, i)).ok();
+        for x in ["a", "b", "c", "d"].iter() {
+            std::fs::write(path(&dir, 0), x).unwrap();
+            rotate(&dir, 2).unwrap();
+        }
+        assert_eq!(read(0), None);
+        assert_eq!(read(1), Some("d".to_owned()));
+        assert_eq!(read(2), Some("c".to_owned()));
+        assert_eq!(read(3), None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
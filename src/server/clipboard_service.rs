@@ -35,13 +35,14 @@ pub fn new() -> GenericService {
 
 fn run(sp: GenericService, state: &mut State) -> ResultType<()> {
     if let Some(ctx) = state.ctx.as_mut() {
-        if let Some(msg) = check_clipboard(ctx, None) {
+        // shared by all sessions, so without a dictionary of any one peer
+        if let Some(msg) = check_clipboard(ctx, None, None) {
             sp.send(msg);
         }
         sp.snapshot(|sps| {
             let txt = crate::CONTENT.lock().unwrap().clone();
             if !txt.is_empty() {
-                let msg_out = crate::create_clipboard_msg(txt);
+                let msg_out = crate::create_clipboard_msg(txt, None);
                 sps.send_shared(Arc::new(msg_out));
             }
             Ok(())
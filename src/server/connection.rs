@@ -1,4 +1,4 @@
-use super::{input_service::*, *};
+use super::{audit_log, input_service::*, login_guard, *};
 #[cfg(windows)]
 use crate::clipboard_file::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -6,15 +6,26 @@ use crate::common::update_clipboard;
 use crate::video_service;
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
-use crate::{ipc, VERSION};
+use crate::{ipc, totp, VERSION};
 use hbb_common::{
-    config::Config,
+    capability,
+    capture::Capture,
+    chrono::Utc,
+    compress::{self, Kind},
+    config::{
+        authorized_keys::{self, AuthorizedKey, AuthorizedKeys},
+        peer_acl::{PeerAcl, Rule},
+        Config,
+    },
     fs,
-    fs::can_enable_overwrite_detection,
-    futures::{SinkExt, StreamExt},
-    get_time, get_version_number,
+    futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt},
+    get_time,
     message_proto::{option_message::BoolOption, permission_info::Permission},
-    password_security as password, sleep, timeout,
+    mux::Mux,
+    password_security as password,
+    rekey::KeyRotation,
+    schedule::Schedule,
+    sleep, timeout,
     tokio::{
         net::TcpStream,
         sync::mpsc,
@@ -36,8 +47,10 @@ use system_shutdown;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
-    static ref LOGIN_FAILURES: Arc::<Mutex<HashMap<String, (i32, i32, i32)>>> = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<String, Session>>> = Default::default();
+    /// The password, kdf and key of the last derivation, the temporary password being
+    /// checked with each login while the derivation is slow on purpose.
+    static ref PASSWORD_KEY: Mutex<Option<(String, String, Vec<u8>)>> = Default::default();
 }
 pub static CLICK_TIME: AtomicI64 = AtomicI64::new(0);
 pub static MOUSE_MOVE_TIME: AtomicI64 = AtomicI64::new(0);
@@ -82,6 +95,8 @@ pub struct Connection {
     file: bool,
     restart: bool,
     recording: bool,
+    /// Set by the access rule of the peer, keeping keyboard and clipboard off.
+    view_only: bool,
     last_test_delay: i64,
     lock_after_session_end: bool,
     show_remote_cursor: bool, // by peer
@@ -94,9 +109,16 @@ pub struct Connection {
     peer_info: (String, String),
     api_server: String,
     lr: LoginRequest,
+    peer_capabilities: Capabilities,
     last_recv_time: Arc<Mutex<Instant>>,
     chat_unanswered: bool,
     close_manually: bool,
+    key_rotation: Option<KeyRotation>,
+    mux: Mux,
+    schedule: Schedule,
+    /// When the access window of an unattended session ends.
+    access_end: Option<Instant>,
+    access_warned: bool,
 }
 
 impl Subscriber for ConnInner {
@@ -123,12 +145,27 @@ impl Subscriber for ConnInner {
 }
 
 const TEST_DELAY_TIMEOUT: Duration = Duration::from_secs(1);
+const ACCESS_WARNING: Duration = Duration::from_secs(300);
 const SEC30: Duration = Duration::from_secs(30);
 const H1: Duration = Duration::from_secs(3600);
 const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
 const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+/// For clients hashing the password the old way, which only the temporary password and
+/// a permanent one set with "allow-legacy-password" accept.
+const LOGIN_MSG_PASSWORD_FORMAT: &str = "Password format changed, please update the client";
+
+/// The access windows of `rule`, or the ones of the options.
+fn access_schedule(rule: Option<&Rule>) -> ResultType<Schedule> {
+    if let Some(schedule) = rule.map(|x| x.schedule()).transpose()?.flatten() {
+        return Ok(schedule);
+    }
+    Schedule::parse(
+        &Config::get_option("access-windows"),
+        &Config::get_option("access-windows-timezone"),
+    )
+}
 
 impl Connection {
     pub async fn start(
@@ -137,10 +174,41 @@ impl Connection {
         id: i32,
         server: super::ServerPtrWeak,
     ) {
-        let hash = Hash {
-            salt: Config::get_salt(),
-            challenge: Config::get_auto_password(6),
-            ..Default::default()
+        Self::run(addr, stream, id, server, None).await
+    }
+
+    /// Serve a channel opened in the session of `lr`, which is already authorized,
+    /// `hash` being the one sent in that session. Boxed, as it is spawned from the
+    /// connection of that session.
+    fn start_channel(
+        addr: SocketAddr,
+        stream: super::Stream,
+        id: i32,
+        server: super::ServerPtrWeak,
+        lr: LoginRequest,
+        hash: Hash,
+    ) -> BoxFuture<'static, ()> {
+        Self::run(addr, stream, id, server, Some((lr, hash))).boxed()
+    }
+
+    async fn run(
+        addr: SocketAddr,
+        stream: super::Stream,
+        id: i32,
+        server: super::ServerPtrWeak,
+        channel_of: Option<(LoginRequest, Hash)>,
+    ) {
+        let (channel_of, hash) = match channel_of {
+            Some((lr, hash)) => (Some(lr), hash),
+            None => (
+                None,
+                Hash {
+                    salt: Config::get_salt(),
+                    challenge: Config::get_auto_password(6),
+                    kdf: Config::get_permanent_password_hash().kdf,
+                    ..Default::default()
+                },
+            ),
         };
         let (tx_from_cm_holder, mut rx_from_cm) = mpsc::unbounded_channel::<ipc::Data>();
         // holding tx_from_cm_holde to avoid cpu burning of rx_from_cm.recv when all sender closed
@@ -149,6 +217,7 @@ impl Connection {
         let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_video, mut rx_video) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_input, rx_input) = std_mpsc::channel();
+        let (mux, mut rx_mux) = Mux::new(false);
 
         let tx_cloned = tx.clone();
         let mut conn = Self {
@@ -173,6 +242,7 @@ impl Connection {
             file: Connection::permission("enable-file-transfer"),
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
+            view_only: false,
             last_test_delay: 0,
             lock_after_session_end: false,
             show_remote_cursor: false,
@@ -185,9 +255,15 @@ impl Connection {
             peer_info: Default::default(),
             api_server: "".to_owned(),
             lr: Default::default(),
+            peer_capabilities: Default::default(),
             last_recv_time: Arc::new(Mutex::new(Instant::now())),
             chat_unanswered: false,
             close_manually: false,
+            key_rotation: None,
+            mux,
+            schedule: Default::default(),
+            access_end: None,
+            access_warned: false,
         };
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         tokio::spawn(async move {
@@ -198,7 +274,14 @@ impl Connection {
         #[cfg(target_os = "android")]
         start_channel(rx_to_cm, tx_from_cm);
 
-        if !conn.on_open(addr).await {
+        if let Some(capture) = Capture::start(&id.to_string(), true) {
+            conn.stream.set_capture(capture);
+        }
+        let opened = match channel_of {
+            Some(lr) => conn.on_channel_open(addr, lr).await,
+            None => conn.on_open(addr).await,
+        };
+        if !opened {
             return;
         }
         if !conn.keyboard {
@@ -272,7 +355,16 @@ impl Connection {
                             conn.chat_unanswered = false;
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
+                            if conn.view_only && enabled && (&name == "keyboard" || &name == "clipboard") {
+                                log::info!("Permission {} kept off for a view-only peer", name);
+                                continue;
+                            }
                             log::info!("Change permission {} -> {}", name, enabled);
+                            conn.audit(json!({
+                                "action": "permission",
+                                "name": &name,
+                                "enabled": enabled,
+                            }));
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -391,6 +483,12 @@ impl Connection {
                         break;
                     }
                 },
+                Some(msg) = rx_mux.recv() => {
+                    if let Err(err) = conn.stream.send(&msg).await {
+                        conn.on_close(&err.to_string(), false).await;
+                        break;
+                    }
+                },
                 Some((instant, value)) = rx.recv() => {
                     let latency = instant.elapsed().as_millis() as i64;
                     let msg: &Message = &value;
@@ -428,6 +526,17 @@ impl Connection {
                         msg.set_misc(misc);
                         conn.inner.send(msg.into());
                     }
+                    if conn.authorized {
+                        if let Some(key_rotation) = conn.key_rotation.as_mut() {
+                            if let Err(err) = key_rotation.check(&mut conn.stream).await {
+                                conn.on_close(&err.to_string(), false).await;
+                                break;
+                            }
+                        }
+                        if !conn.check_access_end().await {
+                            break;
+                        }
+                    }
                 }
                 _ = test_delay_timer.tick() => {
                     if last_recv_time.elapsed() >= SEC30 {
@@ -461,6 +570,8 @@ impl Connection {
         video_service::notify_video_frame_feched(id, None);
         scrap::codec::Encoder::update_video_encoder(id, scrap::codec::EncoderUpdate::Remove);
         video_service::VIDEO_QOS.lock().unwrap().reset();
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        super::host_record::stop(id);
         if conn.authorized {
             password::update_temporary_password();
         }
@@ -624,6 +735,75 @@ impl Connection {
         true
     }
 
+    async fn on_channel_open(&mut self, addr: SocketAddr, lr: LoginRequest) -> bool {
+        log::debug!("#{} Channel opened from {}.", self.inner.id, addr);
+        self.ip = addr.ip().to_string();
+        self.get_api_server();
+        self.post_audit(json!({
+            "ip": addr.ip(),
+            "action": "new",
+        }));
+        self.peer_capabilities = lr.get_capabilities();
+        self.lr = lr.clone();
+        // the rules may have changed since the session was authorized
+        if !self.apply_acl().await || !self.check_licensed(&lr).await {
+            return false;
+        }
+        if let Some(login_request::Union::FileTransfer(ft)) = lr.union.as_ref() {
+            if !self.file {
+                self.send_login_error("No permission of file transfer")
+                    .await;
+                sleep(1.).await;
+                return false;
+            }
+            self.file_transfer = Some((ft.dir.clone(), ft.show_hidden));
+        }
+        self.try_start_cm(lr.my_id, lr.my_name, true);
+        self.send_logon_response().await;
+        true
+    }
+
+    /// Serve a channel the client opened in this session, with the permissions
+    /// a separate connection of its kind would need.
+    fn on_channel(&mut self, id: u32, open: ChannelOpen, stream: super::Stream) {
+        if self.file_transfer.is_some() || self.port_forward_socket.is_some() {
+            self.mux
+                .close(id, "Channels are only supported in remote control sessions");
+            return;
+        }
+        let reason = match open.union {
+            Some(channel_open::Union::FileTransfer(ft)) => {
+                if Connection::permission("enable-file-transfer") && self.file {
+                    let mut lr = self.lr.clone();
+                    lr.set_file_transfer(ft);
+                    let ip = self
+                        .ip
+                        .parse()
+                        .unwrap_or(Config::get_any_listen_addr().ip());
+                    let addr = SocketAddr::new(ip, 0);
+                    if let Some(server) = self.server.upgrade() {
+                        let conn_id = server.write().unwrap().get_new_id();
+                        log::info!("#{} Channel {} opened as #{}", self.inner.id, id, conn_id);
+                        tokio::spawn(Connection::start_channel(
+                            addr,
+                            stream,
+                            conn_id,
+                            self.server.clone(),
+                            lr,
+                            self.hash.clone(),
+                        ));
+                        return;
+                    }
+                    "Server is closed"
+                } else {
+                    "No permission of file transfer"
+                }
+            }
+            None => "Unsupported channel",
+        };
+        self.mux.close(id, reason);
+    }
+
     fn get_api_server(&mut self) {
         self.api_server = crate::get_audit_server(
             Config::get_option("api-server"),
@@ -631,7 +811,32 @@ impl Connection {
         );
     }
 
+    /// Write the event `v` of the connection to the local audit log.
+    fn audit(&self, mut v: Value) {
+        if let Some(map) = v.as_object_mut() {
+            map.entry("conn_id").or_insert(json!(self.inner.id));
+            map.entry("ip").or_insert(json!(self.ip));
+            if !self.lr.my_id.is_empty() {
+                map.entry("peer_id").or_insert(json!(self.lr.my_id));
+                map.entry("peer_name").or_insert(json!(self.lr.my_name));
+            }
+        }
+        audit_log::write(v);
+    }
+
+    fn audit_file(&self, op: &str, path: &str) {
+        self.audit(json!({
+            "action": "file-transfer",
+            "op": op,
+            "path": path,
+        }));
+    }
+
     fn post_audit(&self, v: Value) {
+        // heartbeats have no action
+        if v.get("action").is_some() {
+            self.audit(v.clone());
+        }
         if self.api_server.is_empty() {
             return;
         }
@@ -663,6 +868,11 @@ impl Connection {
             0
         };
         self.post_audit(json!({"peer": self.peer_info, "Type": conn_type}));
+        self.audit(json!({
+            "action": "login",
+            "type": ["remote", "file-transfer", "port-forward"][conn_type],
+            "port_forward": self.port_forward_address,
+        }));
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -733,6 +943,7 @@ impl Connection {
         pi.sas_enabled = sas_enabled;
         pi.features = Some(Features {
             privacy_mode: video_service::is_privacy_mode_supported(),
+            capabilities: Some(crate::common::get_capabilities(true, "")).into(),
             ..Default::default()
         })
         .into();
@@ -783,6 +994,17 @@ impl Connection {
                 if !self.audio_enabled() {
                     noperms.push(super::audio_service::NAME);
                 }
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                super::host_record::start(super::host_record::Metadata {
+                    conn_id: self.inner.id(),
+                    host_id: Config::get_id(),
+                    hostname: whoami::hostname(),
+                    username: crate::platform::get_active_username(),
+                    peer_id: self.lr.my_id.clone(),
+                    peer_name: self.lr.my_name.clone(),
+                    ip: self.ip.clone(),
+                    ..Default::default()
+                });
                 s.write()
                     .unwrap()
                     .add_connection(self.inner.clone(), &noperms);
@@ -818,6 +1040,7 @@ impl Connection {
             file_transfer_enabled: self.file_transfer_enabled(),
             restart: self.restart,
             recording: self.recording,
+            view_only: self.view_only,
         });
     }
 
@@ -860,17 +1083,47 @@ impl Connection {
         self.tx_input.send(MessageInput::Key((msg, press))).ok();
     }
 
+    /// Whether the login is sha256(`key` + challenge).
+    fn validate_password_key(&self, key: &[u8]) -> bool {
+        if key.is_empty() {
+            return false;
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        hasher.update(&self.hash.challenge);
+        hasher.finalize()[..] == self.lr.password[..]
+    }
+
+    /// A client knowing the password key of the permanent password derives the key of
+    /// any password, while older clients hash it with the salt.
     fn validate_one_password(&self, password: String) -> bool {
         if password.len() == 0 {
             return false;
         }
         let mut hasher = Sha256::new();
-        hasher.update(password);
+        hasher.update(&password);
         hasher.update(&self.hash.salt);
-        let mut hasher2 = Sha256::new();
-        hasher2.update(&hasher.finalize()[..]);
-        hasher2.update(&self.hash.challenge);
-        hasher2.finalize()[..] == self.lr.password[..]
+        if self.validate_password_key(&hasher.finalize()[..]) {
+            return true;
+        }
+        if self.hash.kdf.is_empty() {
+            return false;
+        }
+        match derive_password_key(&password, &self.hash.kdf) {
+            Ok(key) => self.validate_password_key(&key),
+            Err(err) => {
+                log::error!("Failed to derive password key: {}", err);
+                false
+            }
+        }
+    }
+
+    /// [`Self::validate_password_key`] with the base64 of the key.
+    fn validate_password_hash(&self, hash: String) -> bool {
+        match base64::decode(hash) {
+            Ok(key) => self.validate_password_key(&key),
+            Err(_) => false,
+        }
     }
 
     fn validate_password(&mut self) -> bool {
@@ -890,13 +1143,186 @@ impl Connection {
             }
         }
         if password::permanent_enabled() {
-            if self.validate_one_password(Config::get_permanent_password()) {
+            let hash = Config::get_permanent_password_hash();
+            if self.validate_password_hash(hash.key) || self.validate_password_hash(hash.hash) {
                 return true;
             }
         }
         false
     }
 
+    /// Whether the client hashes the password the old way while only the password key
+    /// of the permanent password is stored, so that it can never log in with it.
+    fn is_legacy_password_client(&self) -> bool {
+        if self
+            .peer_capabilities
+            .has_session(capability::SESSION_PASSWORD_KDF)
+            || password::temporary_enabled()
+            || !password::permanent_enabled()
+        {
+            return false;
+        }
+        let hash = Config::get_permanent_password_hash();
+        !hash.key.is_empty() && hash.hash.is_empty()
+    }
+
+    /// Apply the rule of the access list for the peer, false if it is refused.
+    async fn apply_acl(&mut self) -> bool {
+        let acl = PeerAcl::load();
+        let key = if authorized_keys::verify(
+            &self.lr.client_key,
+            &self.lr.client_signature,
+            &self.hash.challenge,
+            &self.lr.username,
+        ) {
+            Some(base64::encode(&self.lr.client_key))
+        } else {
+            None
+        };
+        let rule = acl.find(&self.lr.my_id, key.as_deref());
+        match access_schedule(rule) {
+            Ok(schedule) => self.schedule = schedule,
+            Err(err) => {
+                log::error!("Invalid access windows: {}", err);
+                self.send_login_error("Connection not allowed").await;
+                sleep(1.).await;
+                return false;
+            }
+        }
+        let rule = match rule {
+            Some(rule) => rule,
+            None => return true,
+        };
+        let tunnel = matches!(self.lr.union, Some(login_request::Union::PortForward(_)));
+        if rule.deny || tunnel && !rule.permission("tunnel", true) {
+            log::info!(
+                "Access of {} refused by the rule {}",
+                self.lr.my_id,
+                rule.peer
+            );
+            self.post_audit(json!({
+                "ip": self.ip,
+                "action": "acl-denied",
+                "peer_id": self.lr.my_id,
+                "rule": rule.peer,
+            }));
+            self.send_login_error("Connection not allowed").await;
+            sleep(1.).await;
+            return false;
+        }
+        self.view_only = rule.view_only;
+        self.keyboard = rule.permission("keyboard", self.keyboard);
+        self.clipboard = rule.permission("clipboard", self.clipboard);
+        self.audio = rule.permission("audio", self.audio);
+        self.file = rule.permission("file", self.file);
+        self.restart = rule.permission("restart", self.restart);
+        self.recording = rule.permission("recording", self.recording);
+        true
+    }
+
+    /// Whether the license allows the kind of session of `lr`, refused if not.
+    async fn check_licensed(&mut self, lr: &LoginRequest) -> bool {
+        let feature = match lr.union {
+            Some(login_request::Union::FileTransfer(_)) => "file-transfer",
+            Some(login_request::Union::PortForward(_)) => "port-forward",
+            _ => return true,
+        };
+        if crate::common::is_licensed(feature) {
+            return true;
+        }
+        self.send_login_error("Not allowed by the license").await;
+        sleep(1.).await;
+        false
+    }
+
+    /// Whether access is allowed now by the access windows, noting when it ends. They
+    /// do not apply to the connections accepted in the connection manager.
+    fn check_access_window(&mut self) -> bool {
+        if self.schedule.is_empty()
+            || self.lr.password.is_empty() && self.find_authorized_key().is_none()
+        {
+            return true;
+        }
+        match self.schedule.remaining(Utc::now()) {
+            Some(secs) if secs > 0 => {
+                self.access_end = Some(Instant::now() + Duration::from_secs(secs as _));
+                self.access_warned = false;
+                true
+            }
+            _ => {
+                log::info!("Access of {} outside of the access windows", self.lr.my_id);
+                self.post_audit(json!({
+                    "ip": self.ip,
+                    "action": "outside-access-windows",
+                    "peer_id": self.lr.my_id,
+                }));
+                false
+            }
+        }
+    }
+
+    /// Warn before the end of the access window, and close the session at its end
+    /// unless another window follows.
+    async fn check_access_end(&mut self) -> bool {
+        let end = match self.access_end {
+            Some(end) => end,
+            None => return true,
+        };
+        let now = Instant::now();
+        if now < end {
+            if !self.access_warned && end - now <= ACCESS_WARNING {
+                self.access_warned = true;
+                let mut msg_out = Message::new();
+                msg_out.set_message_box(MessageBox {
+                    msgtype: "info".to_owned(),
+                    title: "Access window".to_owned(),
+                    text: format!(
+                        "The access window ends in {} minutes, the session will then be closed.",
+                        ((end - now).as_secs() + 59) / 60
+                    ),
+                    ..Default::default()
+                });
+                self.send(msg_out).await;
+            }
+            return true;
+        }
+        if self.check_access_window() {
+            return true;
+        }
+        let mut misc = Misc::new();
+        misc.set_close_reason("The access window has ended".to_owned());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+        self.on_close("Access window ended", false).await;
+        false
+    }
+
+    /// The key the client signed the login with, if authorized.
+    fn find_authorized_key(&self) -> Option<AuthorizedKey> {
+        if self.lr.client_key.is_empty() {
+            return None;
+        }
+        AuthorizedKeys::load()
+            .find(
+                &self.lr.client_key,
+                &self.lr.client_signature,
+                &self.hash.challenge,
+                &self.lr.username,
+            )
+            .cloned()
+    }
+
+    fn add_login_failure(&self) {
+        let ban = login_guard::add_failure(&self.ip, &self.lr.my_id);
+        self.post_audit(json!({
+            "ip": self.ip,
+            "action": "login-failure",
+            "peer_id": self.lr.my_id,
+            "ban": ban,
+        }));
+    }
+
     fn is_of_recent_session(&mut self) -> bool {
         let session = SESSIONS
             .lock()
@@ -939,9 +1365,16 @@ impl Connection {
         return Config::get_option(enable_prefix_option).is_empty();
     }
 
+    /// Capabilities of the client, valid once its login request is received.
+    #[inline]
+    pub fn peer_capabilities(&self) -> &Capabilities {
+        &self.peer_capabilities
+    }
+
     async fn on_message(&mut self, msg: Message) -> bool {
         if let Some(message::Union::LoginRequest(lr)) = msg.union {
             self.lr = lr.clone();
+            self.peer_capabilities = lr.get_capabilities();
             if let Some(o) = lr.option.as_ref() {
                 self.update_option(o).await;
                 if let Some(q) = o.video_codec_state.clone().take() {
@@ -962,12 +1395,23 @@ impl Connection {
                 );
             }
             self.video_ack_required = lr.video_ack_required;
+            if self
+                .peer_capabilities()
+                .has_session(capability::SESSION_REKEY)
+                && self.key_rotation.is_none()
+                && self.stream.is_secured()
+            {
+                self.key_rotation = Some(KeyRotation::new(true));
+            }
             if self.authorized {
                 return true;
             }
+            if !self.apply_acl().await || !self.check_licensed(&lr).await {
+                return false;
+            }
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
-                    if !Connection::permission("enable-file-transfer") {
+                    if !self.file {
                         self.send_login_error("No permission of file transfer")
                             .await;
                         sleep(1.).await;
@@ -1019,12 +1463,45 @@ impl Connection {
             }
             if !crate::is_ip(&lr.username) && lr.username != Config::get_id() {
                 self.send_login_error("Offline").await;
+            } else if !self.check_access_window() {
+                self.send_login_error("Connection not allowed at this time")
+                    .await;
+                sleep(1.).await;
+                return false;
             } else if self.is_of_recent_session() {
                 self.try_start_cm(lr.my_id, lr.my_name, true);
                 self.send_logon_response().await;
                 if self.port_forward_socket.is_some() {
                     return false;
                 }
+            } else if let Some(key) = self.find_authorized_key() {
+                if self.file_transfer.is_some() && !key.has_permission("file") {
+                    self.send_login_error("No permission of file transfer")
+                        .await;
+                    sleep(1.).await;
+                    return false;
+                }
+                if self.port_forward_socket.is_some() && !key.has_permission("tunnel") {
+                    self.send_login_error("No permission of IP tunneling").await;
+                    sleep(1.).await;
+                    return false;
+                }
+                log::info!(
+                    "Logged in with the authorized key {} ({})",
+                    key.key,
+                    key.name
+                );
+                self.keyboard &= key.has_permission("keyboard");
+                self.clipboard &= key.has_permission("clipboard");
+                self.audio &= key.has_permission("audio");
+                self.file &= key.has_permission("file");
+                self.restart &= key.has_permission("restart");
+                self.recording &= key.has_permission("recording");
+                self.try_start_cm(lr.my_id, lr.my_name, true);
+                self.send_logon_response().await;
+                if self.port_forward_socket.is_some() {
+                    return false;
+                }
             } else if lr.password.is_empty() {
                 self.try_start_cm(lr.my_id, lr.my_name, false);
             } else {
@@ -1032,37 +1509,36 @@ impl Connection {
                     self.send_login_error("Connection not allowed").await;
                     return false;
                 }
-                let mut failure = LOGIN_FAILURES
-                    .lock()
-                    .unwrap()
-                    .get(&self.ip)
-                    .map(|x| x.clone())
-                    .unwrap_or((0, 0, 0));
-                let time = (get_time() / 60_000) as i32;
-                if failure.2 > 30 {
+                let ban = login_guard::banned_for(&self.ip, &lr.my_id);
+                if ban > 0 {
+                    self.post_audit(json!({
+                        "ip": self.ip,
+                        "action": "login-banned",
+                        "peer_id": lr.my_id,
+                        "ban": ban,
+                    }));
                     self.send_login_error("Too many wrong password attempts")
                         .await;
-                } else if time == failure.0 && failure.1 > 6 {
-                    self.send_login_error("Please try 1 minute later").await;
                 } else if !self.validate_password() {
-                    if failure.0 == time {
-                        failure.1 += 1;
-                        failure.2 += 1;
+                    if self.is_legacy_password_client() {
+                        // not counted, as it would ban a client reconnecting with a
+                        // remembered password, nor is any password guessed this way
+                        self.send_login_error(LOGIN_MSG_PASSWORD_FORMAT).await;
                     } else {
-                        failure.0 = time;
-                        failure.1 = 1;
-                        failure.2 += 1;
+                        self.add_login_failure();
+                        self.send_login_error("Wrong Password").await;
                     }
-                    LOGIN_FAILURES
-                        .lock()
-                        .unwrap()
-                        .insert(self.ip.clone(), failure);
-                    self.send_login_error("Wrong Password").await;
                     self.try_start_cm(lr.my_id, lr.my_name, false);
+                } else if totp::is_enabled() && lr.tfa_code.is_empty() {
+                    // not a session to resume until the second factor is given too
+                    SESSIONS.lock().unwrap().remove(&lr.my_id);
+                    self.send_login_error(totp::LOGIN_MSG_2FA_REQUIRED).await;
+                } else if totp::is_enabled() && !totp::verify(&lr.tfa_code) {
+                    SESSIONS.lock().unwrap().remove(&lr.my_id);
+                    self.add_login_failure();
+                    self.send_login_error(totp::LOGIN_MSG_2FA_WRONG).await;
                 } else {
-                    if failure.0 != 0 {
-                        LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
-                    }
+                    login_guard::clear(&self.ip, &lr.my_id);
                     self.try_start_cm(lr.my_id, lr.my_name, true);
                     self.send_logon_response().await;
                     if self.port_forward_socket.is_some() {
@@ -1161,10 +1637,11 @@ impl Connection {
                                 }
                             }
                             Some(file_action::Union::Send(s)) => {
+                                self.audit_file("send", &s.path);
                                 let id = s.id;
-                                let od = can_enable_overwrite_detection(get_version_number(
-                                    &self.lr.version,
-                                ));
+                                let od = self.peer_capabilities().has_file_transfer(
+                                    capability::FILE_TRANSFER_OVERWRITE_DETECTION,
+                                );
                                 let path = s.path.clone();
                                 match fs::TransferJob::new_read(
                                     id,
@@ -1178,20 +1655,25 @@ impl Connection {
                                     Err(err) => {
                                         self.send(fs::new_error(id, err, 0)).await;
                                     }
-                                    Ok(job) => {
+                                    Ok(mut job) => {
                                         self.send(fs::new_dir(id, path, job.files().to_vec()))
                                             .await;
+                                        job.set_dictionary(compress::negotiate(
+                                            Kind::FileBlock,
+                                            self.peer_capabilities(),
+                                        ));
                                         self.read_jobs.push(job);
                                         self.timer = time::interval(MILLI1);
                                     }
                                 }
                             }
                             Some(file_action::Union::Receive(r)) => {
+                                self.audit_file("receive", &r.path);
                                 // note: 1.1.10 introduced identical file detection, which breaks original logic of send/recv files
                                 // whenever got send/recv request, check peer version to ensure old version of rustdesk
-                                let od = can_enable_overwrite_detection(get_version_number(
-                                    &self.lr.version,
-                                ));
+                                let od = self.peer_capabilities().has_file_transfer(
+                                    capability::FILE_TRANSFER_OVERWRITE_DETECTION,
+                                );
                                 self.send_fs(ipc::FS::NewWrite {
                                     path: r.path,
                                     id: r.id,
@@ -1206,6 +1688,7 @@ impl Connection {
                                 });
                             }
                             Some(file_action::Union::RemoveDir(d)) => {
+                                self.audit_file("remove-dir", &d.path);
                                 self.send_fs(ipc::FS::RemoveDir {
                                     path: d.path,
                                     id: d.id,
@@ -1213,6 +1696,7 @@ impl Connection {
                                 });
                             }
                             Some(file_action::Union::RemoveFile(f)) => {
+                                self.audit_file("remove-file", &f.path);
                                 self.send_fs(ipc::FS::RemoveFile {
                                     path: f.path,
                                     id: f.id,
@@ -1220,6 +1704,7 @@ impl Connection {
                                 });
                             }
                             Some(file_action::Union::Create(c)) => {
+                                self.audit_file("create-dir", &c.path);
                                 self.send_fs(ipc::FS::CreateDir {
                                     path: c.path,
                                     id: c.id,
@@ -1245,6 +1730,7 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            blk_id: block.blk_id,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -1262,6 +1748,24 @@ impl Connection {
                     }),
                     _ => {}
                 },
+                Some(message::Union::Rekey(rk)) => {
+                    let res = match self.key_rotation.as_mut() {
+                        Some(key_rotation) => key_rotation.handle(rk, &mut self.stream).await,
+                        None => Err(hbb_common::anyhow::anyhow!("Rekey failed: not negotiated")),
+                    };
+                    if let Err(err) = res {
+                        self.on_close(&err.to_string(), false).await;
+                        return false;
+                    }
+                }
+                Some(message::Union::Channel(ch)) => match self.mux.handle(ch) {
+                    Ok(Some((id, open, stream))) => self.on_channel(id, open, stream),
+                    Ok(None) => {}
+                    Err(err) => {
+                        self.on_close(&err.to_string(), false).await;
+                        return false;
+                    }
+                },
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::SwitchDisplay(s)) => {
                         video_service::switch_display(s.display).await;
@@ -1483,9 +1987,11 @@ impl Connection {
 
     fn read_dir(&mut self, dir: &str, include_hidden: bool) {
         let dir = dir.to_string();
+        let dictionary = compress::negotiate(Kind::FileDirectory, self.peer_capabilities());
         self.send_fs(ipc::FS::ReadDir {
             dir,
             include_hidden,
+            dictionary,
         });
     }
 
@@ -1495,6 +2001,19 @@ impl Connection {
     }
 }
 
+/// [`password::derive_password_key`], reusing the key of the last derivation.
+fn derive_password_key(password: &str, kdf: &str) -> ResultType<Vec<u8>> {
+    let mut cache = PASSWORD_KEY.lock().unwrap();
+    if let Some((p, k, key)) = cache.as_ref() {
+        if p == password && k == kdf {
+            return Ok(key.clone());
+        }
+    }
+    let key = password::derive_password_key(password, kdf)?;
+    *cache = Some((password.to_owned(), kdf.to_owned(), key.clone()));
+    Ok(key)
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 async fn start_ipc(
     mut rx_to_cm: mpsc::UnboundedReceiver<ipc::Data>,
@@ -1580,8 +2099,9 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            blk_id}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, blk_id})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -0,0 +1,368 @@
+//! Recording of the incoming sessions by the host itself, whatever the client does.
+//!
+//! With "allow-auto-record-incoming", every session showing the screen is recorded from
+//! the frames the video service encodes. A session has its own files, another one being
+//! started when the display or the codec changes, and a JSON sidecar with the
+//! [`Metadata`] of the connection, `<peer id>_<conn id>_<time>.json`.
+//!
+//! Finished sessions older than "incoming-record-max-days" are deleted with their files,
+//! then the oldest ones past "incoming-record-max-size" megabytes.
+use hbb_common::{
+    chrono,
+    config::Config,
+    log,
+    message_proto::{message, video_frame, Message},
+    options, ResultType,
+};
+use scrap::record::{RecordCodecID, Recorder, RecorderContext};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    pub conn_id: i32,
+    /// Id of this machine.
+    pub host_id: String,
+    pub hostname: String,
+    /// The user logged in on this machine.
+    pub username: String,
+    pub peer_id: String,
+    pub peer_name: String,
+    pub ip: String,
+    /// RFC 3339.
+    pub start: String,
+    /// Empty while recording.
+    #[serde(default)]
+    pub end: String,
+    /// Names of the video files, next to the sidecar.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+struct Recording {
+    meta: Metadata,
+    dir: PathBuf,
+    sidecar: PathBuf,
+    recorder: Option<Recorder>,
+    /// Of the display, `None` before the first frame.
+    size: Option<(usize, usize)>,
+    start: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDINGS: Mutex<HashMap<i32, Recording>> = Default::default();
+}
+
+/// The directory of the recordings of incoming sessions.
+pub fn directory() -> PathBuf {
+    let dir = Config::get_option("incoming-record-directory");
+    if !dir.is_empty() {
+        return PathBuf::from(dir);
+    }
+    let mut dir = Config::get_option("video-save-directory");
+    if dir.is_empty() {
+        dir = crate::ui_interface::default_video_save_directory();
+    }
+    PathBuf::from(dir).join("incoming")
+}
+
+/// The peer id and name come from the client, keep them out of the paths.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn codec_of(frame: &video_frame::Union) -> Option<RecordCodecID> {
+    match frame {
+        video_frame::Union::Vp9s(_) => Some(RecordCodecID::VP9),
+        video_frame::Union::H264s(_) => Some(RecordCodecID::H264),
+        video_frame::Union::H265s(_) => Some(RecordCodecID::H265),
+        _ => None,
+    }
+}
+
+impl Recording {
+    fn try_store(&self) -> ResultType<()> {
+        std::fs::write(&self.sidecar, serde_json::to_string_pretty(&self.meta)?)?;
+        Ok(())
+    }
+
+    fn store(&self) {
+        if let Err(err) = self.try_store() {
+            log::error!("Failed to store {}: {}", self.sidecar.display(), err);
+        }
+    }
+
+    fn write(&mut self, frame: &video_frame::Union, size: (usize, usize)) {
+        let codec_id = match codec_of(frame) {
+            Some(x) => x,
+            None => return,
+        };
+        if self.size != Some(size) {
+            self.size = Some(size);
+            // finish the file of the previous display first
+            self.recorder = None;
+            let ctx = RecorderContext {
+                id: format!("{}_{}", sanitize(&self.meta.peer_id), self.meta.conn_id),
+                dir: self.dir.to_string_lossy().to_string(),
+                default_dir: "".to_owned(),
+                filename: "".to_owned(),
+                width: size.0,
+                height: size.1,
+                codec_id,
+            };
+            match Recorder::new(ctx) {
+                Ok(r) => self.recorder = Some(r),
+                Err(err) => log::error!("#{} failed to record: {}", self.meta.conn_id, err),
+            }
+        }
+        let recorder = match self.recorder.as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+        // the pts restart with the video service, which a subscription restarts
+        let mut frame = frame.clone();
+        let pts = self.start.elapsed().as_millis() as i64;
+        match &mut frame {
+            video_frame::Union::Vp9s(x)
+            | video_frame::Union::H264s(x)
+            | video_frame::Union::H265s(x) => x.frames.iter_mut().for_each(|f| f.pts = pts),
+            _ => {}
+        }
+        recorder.write_frame(&frame).ok();
+        let name = Path::new(recorder.filename())
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if self.meta.files.last() != Some(&name) {
+            self.meta.files.push(name);
+            self.store();
+        }
+    }
+}
+
+/// Start recording the connection `meta.conn_id` if "allow-auto-record-incoming".
+pub fn start(mut meta: Metadata) {
+    if !options::get_bool("allow-auto-record-incoming") {
+        return;
+    }
+    if !crate::common::is_licensed("recording") {
+        log::warn!("#{} not recorded, not allowed by the license", meta.conn_id);
+        return;
+    }
+    let dir = directory();
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        log::error!("Failed to create {}: {}", dir.display(), err);
+        return;
+    }
+    let now = chrono::Local::now();
+    meta.start = now.to_rfc3339();
+    let name = format!(
+        "{}_{}_{}.json",
+        sanitize(&meta.peer_id),
+        meta.conn_id,
+        now.format("%Y%m%d%H%M%S")
+    );
+    let recording = Recording {
+        sidecar: dir.join(name),
+        dir: dir.clone(),
+        meta,
+        recorder: None,
+        size: None,
+        start: Instant::now(),
+    };
+    recording.store();
+    log::info!(
+        "#{} recorded to {}",
+        recording.meta.conn_id,
+        recording.sidecar.display()
+    );
+    RECORDINGS
+        .lock()
+        .unwrap()
+        .insert(recording.meta.conn_id, recording);
+    apply_retention(&dir);
+}
+
+/// Write a frame of the video service, `size` being the one of the display, to the
+/// recording of every connection.
+pub fn write(msg: &Message, size: (usize, usize)) {
+    let mut lock = RECORDINGS.lock().unwrap();
+    if lock.is_empty() {
+        return;
+    }
+    if let Some(message::Union::VideoFrame(vf)) = &msg.union {
+        if let Some(frame) = &vf.union {
+            for x in lock.values_mut() {
+                x.write(frame, size);
+            }
+        }
+    }
+}
+
+/// Finish the recording of the connection `conn_id`, if any.
+pub fn stop(conn_id: i32) {
+    let recording = RECORDINGS.lock().unwrap().remove(&conn_id);
+    if let Some(mut recording) = recording {
+        // dropping the recorder finalizes the file, or removes it if too short
+        recording.recorder = None;
+        recording.meta.end = chrono::Local::now().to_rfc3339();
+        let dir = recording.dir.clone();
+        recording.meta.files.retain(|x| dir.join(x).exists());
+        recording.store();
+        log::info!("#{} recording finished", conn_id);
+        apply_retention(&dir);
+    }
+}
+
+struct Session {
+    sidecar: PathBuf,
+    files: Vec<PathBuf>,
+    /// Seconds since the epoch.
+    modified: i64,
+    size: u64,
+}
+
+fn list_sessions(dir: &Path, active: &[PathBuf]) -> ResultType<Vec<Session>> {
+    let mut res = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let sidecar = entry?.path();
+        if sidecar.extension().map(|x| x != "json").unwrap_or(true) || active.contains(&sidecar) {
+            continue;
+        }
+        let meta: Metadata = match serde_json::from_slice(&std::fs::read(&sidecar)?) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let stat = std::fs::metadata(&sidecar)?;
+        let modified = stat
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs() as i64)
+            .unwrap_or_default();
+        let files: Vec<PathBuf> = meta
+            .files
+            .iter()
+            .filter_map(|x| Path::new(x).file_name())
+            .map(|x| dir.join(x))
+            .collect();
+        let size = stat.len()
+            + files
+                .iter()
+                .filter_map(|x| std::fs::metadata(x).ok())
+                .map(|x| x.len())
+                .sum::<u64>();
+        res.push(Session {
+            sidecar,
+            files,
+            modified,
+            size,
+        });
+    }
+    Ok(res)
+}
+
+/// Indexes of the sessions, (modified, size), to delete: the ones older than `max_age`
+/// seconds, and the ones older than the newest totaling `max_size` bytes, 0 meaning no
+/// limit.
+fn expired(sessions: &[(i64, u64)], now: i64, max_age: i64, max_size: u64) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..sessions.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sessions[i].0));
+    let mut total = 0;
+    let mut full = false;
+    let mut res = Vec::new();
+    for i in order {
+        let (modified, size) = sessions[i];
+        total += size;
+        full |= max_size > 0 && total > max_size;
+        if full || (max_age > 0 && now - modified > max_age) {
+            res.push(i);
+        }
+    }
+    res
+}
+
+fn apply_retention(dir: &Path) {
+    let max_days = options::get_number("incoming-record-max-days");
+    let max_size = options::get_number("incoming-record-max-size");
+    if max_days <= 0 && max_size <= 0 {
+        return;
+    }
+    let active: Vec<PathBuf> = RECORDINGS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|x| x.sidecar.clone())
+        .collect();
+    let sessions = match list_sessions(dir, &active) {
+        Ok(x) => x,
+        Err(err) => {
+            log::error!("Failed to list the recordings: {}", err);
+            return;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default();
+    let stats: Vec<(i64, u64)> = sessions.iter().map(|x| (x.modified, x.size)).collect();
+    for i in expired(
+        &stats,
+        now,
+        max_days.max(0) * 86400,
+        max_size.max(0) as u64 * 1024 * 1024,
+    ) {
+        let session = &sessions[i];
+        for file in session
+            .files
+            .iter()
+            .chain(std::iter::once(&session.sidecar))
+        {
+            if let Err(err) = std::fs::remove_file(file) {
+                if file.exists() {
+                    log::error!("Failed to remove {}: {}", file.display(), err);
+                }
+            }
+        }
+        log::info!(
+            "Removed the expired recording {}",
+            session.sidecar.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired() {
+        let day = 86400;
+        let now = 100 * day;
+        let sessions = [
+            (now - 10 * day, 300),
+            (now - day, 100),
+            (now - 40 * day, 10),
+            (now - 2 * day, 200),
+        ];
+        assert!(expired(&sessions, now, 0, 0).is_empty());
+        assert_eq!(expired(&sessions, now, 30 * day, 0), vec![2]);
+        // the newest ones are kept first
+        assert_eq!(expired(&sessions, now, 0, 350), vec![0, 2]);
+        assert_eq!(expired(&sessions, now, 0, 50), vec![1, 3, 0, 2]);
+        assert_eq!(expired(&sessions, now, 5 * day, 1000), vec![0, 2]);
+        assert_eq!(sanitize("../12 34"), "___12_34");
+    }
+}
@@ -0,0 +1,187 @@
+//! Brute-force protection of the logins.
+//!
+//! Wrong passwords and 2FA codes are counted per source ip and per peer id. Past
+//! "login-max-failures" failures, every further one bans both for "login-ban-time"
+//! seconds, doubled each time up to "login-max-ban-time". A successful login clears the
+//! counters, which are also forgotten after "login-max-ban-time" without failure.
+//!
+//! The counters are kept in `login_failures.toml` of the config directory, so that
+//! restarting the server does not lift the bans.
+use hbb_common::{
+    config::{load_path, store_path, Config},
+    get_time, log, options,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failures {
+    pub count: u32,
+    /// Seconds since the epoch.
+    pub last: i64,
+    pub banned_until: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Guard {
+    /// By "ip:<address>" and "id:<peer id>".
+    #[serde(default)]
+    failures: HashMap<String, Failures>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_failures: u32,
+    /// Seconds.
+    ban_time: i64,
+    max_ban_time: i64,
+}
+
+impl Limits {
+    fn get() -> Self {
+        Self {
+            max_failures: options::get_number("login-max-failures") as _,
+            ban_time: options::get_number("login-ban-time"),
+            max_ban_time: options::get_number("login-max-ban-time"),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref GUARD: Mutex<Option<Guard>> = Default::default();
+}
+
+fn path() -> PathBuf {
+    Config::path("login_failures.toml")
+}
+
+fn keys(ip: &str, id: &str) -> Vec<String> {
+    let mut res = vec![format!("ip:{}", ip)];
+    if !id.is_empty() {
+        res.push(format!("id:{}", id));
+    }
+    res
+}
+
+fn now() -> i64 {
+    get_time() / 1000
+}
+
+impl Guard {
+    fn banned_for(&self, keys: &[String], now: i64) -> i64 {
+        keys.iter()
+            .filter_map(|x| self.failures.get(x))
+            .map(|x| x.banned_until - now)
+            .max()
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    fn add_failure(&mut self, keys: &[String], now: i64, limits: Limits) -> i64 {
+        let mut ban = 0;
+        for key in keys {
+            let failures = self.failures.entry(key.clone()).or_default();
+            if now - failures.last > limits.max_ban_time {
+                *failures = Default::default();
+            }
+            failures.count += 1;
+            failures.last = now;
+            if failures.count > limits.max_failures {
+                let n = (failures.count - limits.max_failures - 1).min(32);
+                let time = limits
+                    .ban_time
+                    .saturating_mul(1 << n)
+                    .min(limits.max_ban_time);
+                failures.banned_until = failures.banned_until.max(now + time);
+            }
+            ban = ban.max(failures.banned_until - now);
+        }
+        self.forget(now, limits);
+        ban
+    }
+
+    /// Drop the counters nothing happened to for long.
+    fn forget(&mut self, now: i64, limits: Limits) {
+        self.failures
+            .retain(|_, x| now - x.last <= limits.max_ban_time || x.banned_until > now);
+    }
+}
+
+fn with_guard<T>(f: impl FnOnce(&mut Guard) -> (T, bool)) -> T {
+    let mut lock = GUARD.lock().unwrap();
+    let guard = lock.get_or_insert_with(|| load_path(path()));
+    let (res, changed) = f(guard);
+    if changed {
+        if let Err(err) = store_path(path(), &*guard) {
+            log::error!("Failed to store login failures: {}", err);
+        }
+    }
+    res
+}
+
+/// Seconds the logins of `ip` and `id` are still banned for, 0 if allowed.
+pub fn banned_for(ip: &str, id: &str) -> i64 {
+    with_guard(|guard| (guard.banned_for(&keys(ip, id), now()), false))
+}
+
+/// Count a failed login, returning the seconds it is banned for as a result.
+pub fn add_failure(ip: &str, id: &str) -> i64 {
+    let ban = with_guard(|guard| (guard.add_failure(&keys(ip, id), now(), Limits::get()), true));
+    if ban > 0 {
+        log::warn!("Logins of {} / {} banned for {}s", ip, id, ban);
+    }
+    ban
+}
+
+/// Clear the counters of `ip` and `id` after a successful login.
+pub fn clear(ip: &str, id: &str) {
+    with_guard(|guard| {
+        let mut changed = false;
+        for key in keys(ip, id) {
+            changed |= guard.failures.remove(&key).is_some();
+        }
+        ((), changed)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let limits = Limits {
+            max_failures: 3,
+            ban_time: 60,
+            max_ban_time: 600,
+        };
+        let mut guard = Guard::default();
+        let keys = keys("10.0.0.1", "123456789");
+        let mut now = 1_000_000;
+        for _ in 0..3 {
+            assert_eq!(guard.add_failure(&keys, now, limits), 0);
+        }
+        assert_eq!(guard.banned_for(&keys, now), 0);
+        assert_eq!(guard.add_failure(&keys, now, limits), 60);
+        assert_eq!(guard.add_failure(&keys, now, limits), 120);
+        assert_eq!(guard.add_failure(&keys, now, limits), 240);
+        assert_eq!(guard.add_failure(&keys, now, limits), 480);
+        assert_eq!(guard.add_failure(&keys, now, limits), 600);
+        assert_eq!(guard.banned_for(&keys, now + 100), 500);
+        // the id is banned from any ip, and an ip with any id
+        assert_eq!(
+            guard.banned_for(&super::keys("10.0.0.2", "123456789"), now),
+            600
+        );
+        assert_eq!(
+            guard.banned_for(&super::keys("10.0.0.1", "987654321"), now),
+            600
+        );
+        assert_eq!(guard.banned_for(&super::keys("10.0.0.2", ""), now), 0);
+
+        now += 2 * limits.max_ban_time;
+        assert_eq!(guard.banned_for(&keys, now), 0);
+        assert_eq!(guard.add_failure(&keys, now, limits), 0);
+        assert_eq!(guard.failures[&keys[0]].count, 1);
+    }
+}
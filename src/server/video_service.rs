@@ -25,7 +25,6 @@ use hbb_common::tokio::sync::{
 };
 use scrap::{
     codec::{Encoder, EncoderCfg, HwEncoderConfig},
-    record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     Capturer, Display, TraitCapturer,
 };
@@ -449,22 +448,6 @@ fn run(sp: GenericService) -> ResultType<()> {
     #[cfg(windows)]
     log::info!("gdi: {}", c.is_gdi());
     let codec_name = Encoder::current_hw_encoder_name();
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    let recorder = if !Config::get_option("allow-auto-record-incoming").is_empty() {
-        Recorder::new(RecorderContext {
-            id: "local".to_owned(),
-            default_dir: crate::ui_interface::default_video_save_directory(),
-            filename: "".to_owned(),
-            width: c.width,
-            height: c.height,
-            codec_id: scrap::record::RecordCodecID::VP9,
-        })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
-    } else {
-        Default::default()
-    };
-    #[cfg(any(target_os = "android", target_os = "ios"))]
-    let recorder: Arc<Mutex<Option<Recorder>>> = Default::default();
     #[cfg(windows)]
     start_uac_elevation_check();
 
@@ -530,7 +513,7 @@ fn run(sp: GenericService) -> ResultType<()> {
                     scrap::Frame::RAW(data) => {
                         if (data.len() != 0) {
                             let send_conn_ids =
-                                handle_one_frame(&sp, data, ms, &mut encoder, recorder.clone())?;
+                                handle_one_frame(&sp, data, ms, &mut encoder, (c.width, c.height))?;
                             frame_controller.set_send(now, send_conn_ids);
                         }
                     }
@@ -547,7 +530,7 @@ fn run(sp: GenericService) -> ResultType<()> {
                 let time = now - start;
                 let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
                 let send_conn_ids =
-                    handle_one_frame(&sp, &frame, ms, &mut encoder, recorder.clone())?;
+                    handle_one_frame(&sp, &frame, ms, &mut encoder, (c.width, c.height))?;
                 frame_controller.set_send(now, send_conn_ids);
                 #[cfg(windows)]
                 {
@@ -662,7 +645,7 @@ fn handle_one_frame(
     frame: &[u8],
     ms: i64,
     encoder: &mut Encoder,
-    recorder: Arc<Mutex<Option<Recorder>>>,
+    #[allow(unused_variables)] size: (usize, usize),
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -675,11 +658,7 @@ fn handle_one_frame(
     let mut send_conn_ids: HashSet<i32> = Default::default();
     if let Ok(msg) = encoder.encode_to_message(frame, ms) {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-        recorder
-            .lock()
-            .unwrap()
-            .as_mut()
-            .map(|r| r.write_message(&msg));
+        super::host_record::write(&msg, size);
         send_conn_ids = sp.send_video_frame(msg);
     }
     Ok(send_conn_ids)
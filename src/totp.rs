@@ -0,0 +1,113 @@
+//! Time-based one-time passwords (RFC 6238), the optional second factor of logins.
+//!
+//! The host enrolls a secret, shown as an `otpauth://` URI for authenticator apps and
+//! stored encrypted in the config. Once a client gave a valid password, it is asked for
+//! the current code with [`LOGIN_MSG_2FA_REQUIRED`], which is checked by [`verify`].
+use hbb_common::{config::Config, sodiumoxide::randombytes};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+pub const LOGIN_MSG_2FA_REQUIRED: &str = "2FA Required";
+pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
+/// Seconds a code is valid for.
+pub const STEP: u64 = 30;
+pub const DIGITS: u32 = 6;
+/// Steps before and after the current one also accepted, for clocks a bit off.
+pub const SKEW: u64 = 1;
+
+const ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new secret, as base32 like authenticator apps take it.
+pub fn gen_secret() -> String {
+    base32::encode(ALPHABET, &randombytes::randombytes(20))
+}
+
+/// The URI to enroll `secret` in an authenticator app, usually as a QR code.
+pub fn uri(secret: &str, account: &str) -> String {
+    let issuer = crate::get_app_name().replace(' ', "%20");
+    format!(
+        "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&digits={}&period={}",
+        account.replace(' ', "%20"),
+        secret,
+        DIGITS,
+        STEP,
+        issuer = issuer
+    )
+}
+
+fn code(key: &[u8], step: u64) -> String {
+    let mut mac = match Hmac::<Sha1>::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(_) => return "".to_owned(),
+    };
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let mut value = [0u8; 4];
+    value.copy_from_slice(&hash[offset..offset + 4]);
+    let value = u32::from_be_bytes(value) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// The step `code` of `secret` is valid for at `now`, in seconds.
+fn find_step(secret: &str, code: &str, now: u64) -> Option<u64> {
+    let key = base32::decode(ALPHABET, &secret.to_uppercase())?;
+    let code = code.trim().replace(' ', "");
+    if key.is_empty() || code.len() != DIGITS as usize {
+        return None;
+    }
+    let current = now / STEP;
+    (current.saturating_sub(SKEW)..=current + SKEW).find(|step| self::code(&key, *step) == code)
+}
+
+/// Whether `code` is valid for `secret` now, for the enrollment.
+pub fn check(secret: &str, code: &str) -> bool {
+    find_step(secret, code, now()).is_some()
+}
+
+fn now() -> u64 {
+    hbb_common::get_time() as u64 / 1000
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    !Config::get_totp_secret().is_empty()
+}
+
+/// Whether `code` is valid for the enrolled secret and newer than the last accepted,
+/// which is kept in the config so that a restart does not allow it again.
+pub fn verify(code: &str) -> bool {
+    let step = match find_step(&Config::get_totp_secret(), code, now()) {
+        Some(step) => step,
+        None => return false,
+    };
+    if !Config::set_totp_last_step(step) {
+        hbb_common::log::warn!("Refused a 2FA code already used");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        // RFC 6238 test vectors, the last 6 digits
+        let secret = base32::encode(ALPHABET, b"12345678901234567890");
+        assert_eq!(find_step(&secret, "287082", 59), Some(1));
+        assert_eq!(find_step(&secret, "081804", 1111111109), Some(37037036));
+        assert_eq!(find_step(&secret, "050471", 1111111111), Some(37037037));
+        // one step of skew either way, not two
+        assert_eq!(find_step(&secret, "287082", 59 + STEP), Some(1));
+        assert_eq!(find_step(&secret, "287082", 59 + 2 * STEP), None);
+        assert_eq!(find_step(&secret, "28708", 59), None);
+        assert_eq!(find_step("not base32!", "287082", 59), None);
+        assert_eq!(gen_secret().len(), 32);
+    }
+}
@@ -125,6 +125,20 @@ pub fn start(args: &mut [String]) {
             .to_owned();
         args[1] = id;
     }
+    if args.len() > 1 && args[0] == "--replay" {
+        // shown as a remote control session to the peer of the capture
+        match hbb_common::capture::load(&args[1]) {
+            Ok((header, _)) => {
+                args.insert(0, "--connect".to_owned());
+                args.insert(1, header.id);
+                args.insert(2, "".to_owned());
+            }
+            Err(err) => {
+                log::error!("Failed to load capture {}: {}", args[1], err);
+                return;
+            }
+        }
+    }
     if args.is_empty() {
         let child: Childs = Default::default();
         std::thread::spawn(move || check_zombie(child));
@@ -216,12 +230,12 @@ impl UI {
         update_temporary_password()
     }
 
-    fn permanent_password(&self) -> String {
-        permanent_password()
+    fn has_permanent_password(&self) -> bool {
+        has_permanent_password()
     }
 
-    fn set_permanent_password(&self, password: String) {
-        set_permanent_password(password);
+    fn set_permanent_password(&self, password: String) -> String {
+        set_permanent_password(password)
     }
 
     fn get_remote_id(&mut self) -> String {
@@ -280,6 +294,82 @@ impl UI {
         forget_password(id)
     }
 
+    fn is_password_store_locked(&self) -> bool {
+        is_password_store_locked()
+    }
+
+    fn unlock_password_store(&self, passphrase: String) -> String {
+        unlock_password_store(passphrase)
+    }
+
+    fn get_peer_key(&self, id: String) -> String {
+        get_peer_key(id)
+    }
+
+    fn accept_peer_key(&self, id: String) -> String {
+        accept_peer_key(id)
+    }
+
+    fn forget_peer_key(&self, id: String) {
+        forget_peer_key(id)
+    }
+
+    fn get_address_book(&self, filter: String) -> String {
+        get_address_book(filter)
+    }
+
+    fn get_address_book_tags(&self) -> Value {
+        Value::from_iter(get_address_book_tags())
+    }
+
+    fn get_address_book_groups(&self) -> Value {
+        Value::from_iter(get_address_book_groups())
+    }
+
+    fn set_address_book_entry(&self, entry: String) -> String {
+        set_address_book_entry(entry)
+    }
+
+    fn remove_address_book_entry(&self, id: String) {
+        remove_address_book_entry(id)
+    }
+
+    fn import_address_book(&self, path: String) -> String {
+        import_address_book(path)
+    }
+
+    fn export_address_book(&self, path: String) -> String {
+        export_address_book(path)
+    }
+
+    fn is_2fa_enabled(&self) -> bool {
+        is_2fa_enabled()
+    }
+
+    fn enroll_2fa(&self) -> String {
+        enroll_2fa()
+    }
+
+    fn confirm_2fa(&self, code: String) -> String {
+        confirm_2fa(code)
+    }
+
+    fn disable_2fa(&self) {
+        disable_2fa()
+    }
+
+    fn get_client_key(&self) -> String {
+        get_client_key()
+    }
+
+    fn get_authorized_keys(&self) -> String {
+        get_authorized_keys()
+    }
+
+    fn set_authorized_keys(&self, keys: String) -> String {
+        set_authorized_keys(keys)
+    }
+
     fn get_peer_option(&self, id: String, name: String) -> String {
         get_peer_option(id, name)
     }
@@ -309,7 +399,7 @@ impl UI {
         Value::from_iter(get_sound_inputs())
     }
 
-    fn set_options(&self, v: Value) {
+    fn set_options(&self, v: Value) -> String {
         let mut m = HashMap::new();
         for (k, v) in v.items() {
             if let Some(k) = k.as_string() {
@@ -320,11 +410,11 @@ impl UI {
                 }
             }
         }
-        set_options(m);
+        set_options(m)
     }
 
-    fn set_option(&self, key: String, value: String) {
-        set_option(key, value);
+    fn set_option(&self, key: String, value: String) -> String {
+        set_option(key, value)
     }
 
     fn install_path(&mut self) -> String {
@@ -596,7 +686,7 @@ impl sciter::EventHandler for UI {
         fn get_id();
         fn temporary_password();
         fn update_temporary_password();
-        fn permanent_password();
+        fn has_permanent_password();
         fn set_permanent_password(String);
         fn get_remote_id();
         fn set_remote_id(String);
@@ -642,6 +732,25 @@ impl sciter::EventHandler for UI {
         fn get_peer_option(String, String);
         fn peer_has_password(String);
         fn forget_password(String);
+        fn is_password_store_locked();
+        fn unlock_password_store(String);
+        fn get_peer_key(String);
+        fn accept_peer_key(String);
+        fn forget_peer_key(String);
+        fn is_2fa_enabled();
+        fn enroll_2fa();
+        fn confirm_2fa(String);
+        fn disable_2fa();
+        fn get_client_key();
+        fn get_authorized_keys();
+        fn set_authorized_keys(String);
+        fn get_address_book(String);
+        fn get_address_book_tags();
+        fn get_address_book_groups();
+        fn set_address_book_entry(String);
+        fn remove_address_book_entry(String);
+        fn import_address_book(String);
+        fn export_address_book(String);
         fn set_peer_option(String, String, String);
         fn has_rendezvous_service();
         fn get_license();
@@ -28,7 +28,7 @@ use hbb_common::{
 use crate::clipboard_file::*;
 use crate::{
     client::*,
-    ui_interface::has_hwcodec,
+    ui_interface::{has_hwcodec, is_password_store_locked, unlock_password_store},
     ui_session_interface::{InvokeUiSession, Session},
 };
 
@@ -351,6 +351,7 @@ impl sciter::EventHandler for SciterSession {
         fn is_port_forward();
         fn is_rdp();
         fn login(String, bool);
+        fn login_2fa(String);
         fn new_rdp();
         fn send_mouse(i32, i32, i32, bool, bool, bool, bool);
         fn enter();
@@ -399,6 +400,8 @@ impl sciter::EventHandler for SciterSession {
         fn is_privacy_mode_supported();
         fn toggle_option(String);
         fn get_remember();
+        fn is_password_store_locked();
+        fn unlock_password_store(String);
         fn peer_platform();
         fn set_write_override(i32, i32, bool, bool, bool);
         fn get_keyboard_mode();
@@ -446,6 +449,14 @@ impl SciterSession {
         has_hwcodec()
     }
 
+    fn is_password_store_locked(&self) -> bool {
+        is_password_store_locked()
+    }
+
+    fn unlock_password_store(&self, passphrase: String) -> String {
+        unlock_password_store(passphrase)
+    }
+
     pub fn t(&self, name: String) -> String {
         crate::client::translate(name)
     }
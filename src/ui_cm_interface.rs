@@ -13,8 +13,8 @@ use serde_derive::Serialize;
 use crate::ipc::Data;
 use crate::ipc::{self, new_listener, Connection};
 use hbb_common::{
-    allow_err,
-    config::Config,
+    allow_err, compress,
+    config::{Config, COMPRESS_LEVEL},
     fs::is_write_need_confirmation,
     fs::{self, get_string, new_send_confirm, DigestCheckResult},
     log,
@@ -42,6 +42,7 @@ pub struct Client {
     pub file: bool,
     pub restart: bool,
     pub recording: bool,
+    pub view_only: bool,
     #[serde(skip)]
     tx: UnboundedSender<Data>,
 }
@@ -97,6 +98,7 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         file: bool,
         restart: bool,
         recording: bool,
+        view_only: bool,
         tx: mpsc::UnboundedSender<Data>,
     ) {
         let client = Client {
@@ -113,6 +115,7 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             file,
             restart,
             recording,
+            view_only,
             tx,
         };
         CLIENTS
@@ -273,11 +276,11 @@ pub async fn start_ipc<T: InvokeUiCM>(cm: ConnectionManager<T>) {
                                             }
                                             Ok(Some(data)) => {
                                                 match data {
-                                                    Data::Login{id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled, restart, recording} => {
+                                                    Data::Login{id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled, restart, recording, view_only} => {
                                                         log::debug!("conn_id: {}", id);
                                                         conn_id = id;
                                                         tx_file.send(ClipboardFileData::Enable((id, file_transfer_enabled))).ok();
-                                                        cm.add_connection(id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, tx.clone());
+                                                        cm.add_connection(id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, view_only, tx.clone());
                                                     }
                                                     Data::Close => {
                                                         tx_file.send(ClipboardFileData::Enable((conn_id, false))).ok();
@@ -379,6 +382,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                 file,
                 restart,
                 recording,
+                view_only,
                 ..
             }) => {
                 current_id = id;
@@ -395,6 +399,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                     file,
                     restart,
                     recording,
+                    view_only,
                     tx.clone(),
                 );
             }
@@ -421,8 +426,9 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
         ipc::FS::ReadDir {
             dir,
             include_hidden,
+            dictionary,
         } => {
-            read_dir(&dir, include_hidden, tx).await;
+            read_dir(&dir, include_hidden, dictionary, tx).await;
         }
         ipc::FS::RemoveDir {
             path,
@@ -482,6 +488,7 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
             file_num,
             data,
             compressed,
+            blk_id,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -491,6 +498,7 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                             file_num,
                             data,
                             compressed,
+                            blk_id,
                             ..Default::default()
                         },
                         None,
@@ -542,8 +550,12 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                                     send_raw(msg_out, &tx);
                                 }
                                 DigestCheckResult::NoSuchFile => {
-                                    let msg_out = new_send_confirm(req);
-                                    send_raw(msg_out, &tx);
+                                    // not to hold the connection while hashing a partial download
+                                    let tx = tx.clone();
+                                    tokio::spawn(async move {
+                                        let req = fs::new_write_confirm(id, file_num, &path).await;
+                                        send_raw(new_send_confirm(req), &tx);
+                                    });
                                 }
                             }
                         }
@@ -558,7 +570,12 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
     }
 }
 
-async fn read_dir(dir: &str, include_hidden: bool, tx: &UnboundedSender<Data>) {
+async fn read_dir(
+    dir: &str,
+    include_hidden: bool,
+    dictionary: Option<u32>,
+    tx: &UnboundedSender<Data>,
+) {
     let path = {
         if dir.is_empty() {
             Config::get_home()
@@ -569,7 +586,12 @@ async fn read_dir(dir: &str, include_hidden: bool, tx: &UnboundedSender<Data>) {
     if let Ok(Ok(fd)) = spawn_blocking(move || fs::read_dir(&path, include_hidden)).await {
         let mut msg_out = Message::new();
         let mut file_response = FileResponse::new();
-        file_response.set_dir(fd);
+        match (dictionary, fd.write_to_bytes()) {
+            (Some(id), Ok(bytes)) => file_response.set_compressed_dir(
+                compress::compress_with(&bytes, COMPRESS_LEVEL, Some(id)).into(),
+            ),
+            _ => file_response.set_dir(fd),
+        }
         msg_out.set_file_response(file_response);
         send_raw(msg_out, tx);
     }
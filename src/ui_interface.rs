@@ -9,15 +9,19 @@ use std::{
 use hbb_common::password_security;
 use hbb_common::{
     allow_err,
-    config::{self, Config, LocalConfig, PeerConfig, RENDEZVOUS_PORT, RENDEZVOUS_TIMEOUT},
+    config::{
+        self, address_book::AddressBook, Config, LocalConfig, PeerConfig, RENDEZVOUS_PORT,
+        RENDEZVOUS_TIMEOUT,
+    },
     directories_next,
     futures::future::join_all,
     log,
     protobuf::Message as _,
     rendezvous_proto::*,
-    sleep,
+    secret_store, sleep,
     tcp::FramedStream,
     tokio::{self, sync::mpsc, time},
+    ResultType,
 };
 
 use crate::ipc;
@@ -34,6 +38,7 @@ lazy_static::lazy_static! {
     pub static ref OPTIONS : Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(Config::get_options()));
     pub static ref ASYNC_JOB_STATUS : Arc<Mutex<String>> = Default::default();
     pub static ref TEMPORARY_PASSWD : Arc<Mutex<String>> = Arc::new(Mutex::new("".to_owned()));
+    static ref PENDING_TOTP_SECRET : Mutex<String> = Default::default();
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -133,7 +138,9 @@ pub fn show_run_without_install() -> bool {
 #[inline]
 pub fn has_rendezvous_service() -> bool {
     #[cfg(all(windows, feature = "hbbs"))]
-    return crate::platform::is_win_server() && crate::platform::windows::get_license().is_some();
+    return crate::platform::is_win_server()
+        && crate::platform::windows::get_license().is_some()
+        && crate::common::is_licensed("rendezvous-service");
     return false;
 }
 
@@ -141,10 +148,20 @@ pub fn has_rendezvous_service() -> bool {
 pub fn get_license() -> String {
     #[cfg(windows)]
     if let Some(lic) = crate::platform::windows::get_license() {
-        return format!(
+        let mut res = format!(
             "<br /> Key: {} <br /> Host: {} Api: {}",
             lic.key, lic.host, lic.api
         );
+        if !lic.org.is_empty() {
+            res += &format!("<br /> Organization: {}", lic.org);
+        }
+        if !lic.expiry.is_empty() {
+            res += &format!("<br /> Expiry: {}", lic.expiry);
+        }
+        if !lic.features.is_empty() {
+            res += &format!("<br /> Features: {}", lic.features.join(", "));
+        }
+        return res;
     }
     Default::default()
 }
@@ -176,16 +193,158 @@ pub fn set_local_option(key: String, value: String) {
 
 #[inline]
 pub fn peer_has_password(id: String) -> bool {
-    !PeerConfig::load(&id).password.is_empty()
+    PeerConfig::load(&id).has_password()
 }
 
 #[inline]
 pub fn forget_password(id: String) {
     let mut c = PeerConfig::load(&id);
-    c.password.clear();
+    c.forget_password();
     c.store(&id);
 }
 
+#[inline]
+pub fn is_password_store_locked() -> bool {
+    secret_store::is_locked()
+}
+
+/// Unlock the store of the remembered passwords, returning the error if any.
+#[inline]
+pub fn unlock_password_store(passphrase: String) -> String {
+    match secret_store::unlock(&passphrase) {
+        Ok(_) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Fingerprints of the pinned key of the peer and of a new one to review, as JSON,
+/// empty if none.
+pub fn get_peer_key(id: String) -> String {
+    let c = PeerConfig::load(&id);
+    let fingerprint = |key: &str| {
+        if key.is_empty() {
+            "".to_owned()
+        } else {
+            config::key_fingerprint(key)
+        }
+    };
+    serde_json::json!({
+        "pinned": fingerprint(&c.pinned_key),
+        "pending": fingerprint(&c.pending_key),
+    })
+    .to_string()
+}
+
+/// Pin the new key of the peer, returning the error if any.
+#[inline]
+pub fn accept_peer_key(id: String) -> String {
+    match PeerConfig::accept_key(&id) {
+        Ok(_) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[inline]
+pub fn forget_peer_key(id: String) {
+    PeerConfig::forget_key(&id)
+}
+
+/// The address book entries matching `filter`, the JSON of a
+/// [`address_book::Filter`], as JSON. An empty filter matches everything.
+pub fn get_address_book(filter: String) -> String {
+    let filter = if filter.is_empty() {
+        Default::default()
+    } else {
+        match serde_json::from_str(&filter) {
+            Ok(filter) => filter,
+            Err(err) => {
+                log::error!("Invalid address book filter {}: {}", filter, err);
+                return "[]".to_owned();
+            }
+        }
+    };
+    serde_json::to_string(&AddressBook::load().search(&filter)).unwrap_or_default()
+}
+
+#[inline]
+pub fn get_address_book_tags() -> Vec<String> {
+    AddressBook::load().tags()
+}
+
+#[inline]
+pub fn get_address_book_groups() -> Vec<String> {
+    AddressBook::load().groups()
+}
+
+/// Add or replace the entry of the JSON `entry`, returning the error if any.
+pub fn set_address_book_entry(entry: String) -> String {
+    let mut book = AddressBook::load();
+    let res = serde_json::from_str(&entry)
+        .map_err(|err| err.into())
+        .and_then(|entry| book.set(entry));
+    match res {
+        Ok(_) => {
+            book.store();
+            "".to_owned()
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
+#[inline]
+pub fn remove_address_book_entry(id: String) {
+    let mut book = AddressBook::load();
+    if book.remove(&id) {
+        book.store();
+    }
+}
+
+fn is_csv(path: &str) -> bool {
+    path.to_lowercase().ends_with(".csv")
+}
+
+/// Merge the entries of the JSON or CSV file at `path`, by its extension, returning
+/// the error if any.
+pub fn import_address_book(path: String) -> String {
+    let res = std::fs::read_to_string(&path)
+        .map_err(|err| err.into())
+        .and_then(|data| {
+            if is_csv(&path) {
+                AddressBook::from_csv(&data)
+            } else {
+                AddressBook::from_json(&data)
+            }
+        })
+        .and_then(|entries| {
+            let mut book = AddressBook::load();
+            let n = book.merge(entries)?;
+            book.store();
+            Ok(n)
+        });
+    match res {
+        Ok(n) => {
+            log::info!("Imported {} address book entries from {}", n, path);
+            "".to_owned()
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Write the address book to `path`, as CSV or JSON by its extension, returning the
+/// error if any.
+pub fn export_address_book(path: String) -> String {
+    let book = AddressBook::load();
+    let res = if is_csv(&path) {
+        Ok(book.to_csv())
+    } else {
+        book.to_json()
+    };
+    match res.and_then(|data| Ok(std::fs::write(&path, data)?)) {
+        Ok(_) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
 #[inline]
 pub fn get_peer_option(id: String, name: String) -> String {
     let c = PeerConfig::load(&id);
@@ -270,34 +429,52 @@ pub fn get_sound_inputs() -> Vec<String> {
     a
 }
 
+/// Set the options, returning the errors of the invalid values, which are not set.
 #[inline]
-pub fn set_options(m: HashMap<String, String>) {
+pub fn set_options(m: HashMap<String, String>) -> String {
     *OPTIONS.lock().unwrap() = m.clone();
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    ipc::set_options(m).ok();
+    let res = ipc::set_options(m);
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    Config::set_options(m);
+    let res = Config::set_options(m);
+    match res {
+        Ok(_) => "".to_owned(),
+        Err(err) => {
+            // the invalid values were not set
+            *OPTIONS.lock().unwrap() = Config::get_options();
+            err.to_string()
+        }
+    }
 }
 
+/// Set an option, returning the error if the value is invalid, which is not set.
 #[inline]
-pub fn set_option(key: String, value: String) {
+pub fn set_option(key: String, value: String) -> String {
     let mut options = OPTIONS.lock().unwrap();
     #[cfg(target_os = "macos")]
     if &key == "stop-service" {
         let is_stop = value == "Y";
         if is_stop && crate::platform::macos::uninstall() {
-            return;
+            return "".to_owned();
         }
     }
+    let old = options.clone();
     if value.is_empty() {
         options.remove(&key);
     } else {
         options.insert(key.clone(), value.clone());
     }
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    ipc::set_options(options.clone()).ok();
+    let res = ipc::set_options(options.clone());
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    Config::set_option(key, value);
+    let res = Config::set_option(key, value);
+    match res {
+        Ok(_) => "".to_owned(),
+        Err(err) => {
+            *options = old;
+            err.to_string()
+        }
+    }
 }
 
 #[inline]
@@ -440,20 +617,111 @@ pub fn update_temporary_password() {
     allow_err!(ipc::update_temporary_password());
 }
 
+/// Whether a permanent password is set, which is never shown as only its verifiers are
+/// stored.
 #[inline]
-pub fn permanent_password() -> String {
+pub fn has_permanent_password() -> bool {
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    return Config::get_permanent_password();
+    return Config::has_permanent_password();
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    return ipc::get_permanent_password();
+    return ipc::has_permanent_password();
 }
 
+/// Set the permanent password, returning the error if it could not be.
 #[inline]
-pub fn set_permanent_password(password: String) {
+pub fn set_permanent_password(password: String) -> String {
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    Config::set_permanent_password(&password);
+    let res = Config::set_permanent_password(&password);
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    allow_err!(ipc::set_permanent_password(password));
+    let res = ipc::set_permanent_password(password);
+    match res {
+        Ok(_) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[inline]
+pub fn is_2fa_enabled() -> bool {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return crate::totp::is_enabled();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::is_2fa_enabled();
+}
+
+/// Start enrolling a new 2FA secret, returning its URI for an authenticator app.
+/// It is enabled once [`confirm_2fa`] gets a valid code of it.
+pub fn enroll_2fa() -> String {
+    let secret = crate::totp::gen_secret();
+    *PENDING_TOTP_SECRET.lock().unwrap() = secret.clone();
+    crate::totp::uri(&secret, &get_id())
+}
+
+/// Enable the 2FA secret of [`enroll_2fa`] if `code` is valid for it, returning the
+/// error if any.
+pub fn confirm_2fa(code: String) -> String {
+    let secret = PENDING_TOTP_SECRET.lock().unwrap().clone();
+    if secret.is_empty() || !crate::totp::check(&secret, &code) {
+        return crate::totp::LOGIN_MSG_2FA_WRONG.to_owned();
+    }
+    match set_totp_secret(secret) {
+        Ok(_) => {
+            PENDING_TOTP_SECRET.lock().unwrap().clear();
+            "".to_owned()
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
+#[inline]
+pub fn disable_2fa() {
+    allow_err!(set_totp_secret("".to_owned()));
+}
+
+fn set_totp_secret(secret: String) -> ResultType<()> {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        Config::set_totp_secret(&secret);
+        return Ok(());
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::set_totp_secret(secret);
+}
+
+/// The public key of this machine as a client, to be authorized by hosts.
+#[inline]
+pub fn get_client_key() -> String {
+    base64::encode(Config::get_key_pair().1)
+}
+
+/// The client keys authorized on this machine, as JSON.
+pub fn get_authorized_keys() -> String {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let keys: ResultType<_> = Ok(config::authorized_keys::AuthorizedKeys::load().keys);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let keys = ipc::get_authorized_keys();
+    match keys {
+        Ok(keys) => serde_json::to_string(&keys).unwrap_or_default(),
+        Err(err) => {
+            log::error!("Failed to get authorized keys: {}", err);
+            "[]".to_owned()
+        }
+    }
+}
+
+/// Replace the authorized client keys with the JSON `keys`, returning the error if any.
+pub fn set_authorized_keys(keys: String) -> String {
+    let res = serde_json::from_str(&keys)
+        .map_err(|err| err.into())
+        .and_then(|keys| {
+            #[cfg(any(target_os = "android", target_os = "ios"))]
+            return config::authorized_keys::AuthorizedKeys::store(keys);
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            return ipc::set_authorized_keys(keys);
+        });
+    match res {
+        Ok(_) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
 }
 
 #[inline]
@@ -2,9 +2,9 @@
 use crate::client::get_key_state;
 use crate::client::io_loop::Remote;
 use crate::client::{
-    check_if_retry, handle_hash, handle_login_from_ui, handle_test_delay, input_os_password,
-    load_config, send_mouse, start_video_audio_threads, FileManager, Key, LoginConfigHandler,
-    QualityStatus, KEY_MAP, SERVER_KEYBOARD_ENABLED,
+    check_if_retry, handle_hash, handle_login_2fa, handle_login_from_ui, handle_test_delay,
+    input_os_password, load_config, send_mouse, start_video_audio_threads, FileManager, Key,
+    LoginConfigHandler, QualityStatus, KEY_MAP, SERVER_KEYBOARD_ENABLED,
 };
 use crate::common::IS_X11;
 use crate::{client::Data, client::Interface};
@@ -17,7 +17,7 @@ use rdev::Keyboard as RdevKeyboard;
 use rdev::{Event, EventType, EventType::*, Key as RdevKey, KeyboardState};
 
 use hbb_common::{allow_err, message_proto::*};
-use hbb_common::{fs, get_version_number, log, Stream};
+use hbb_common::{capability, fs, log, secret_store, Stream};
 use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
@@ -80,6 +80,12 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().custom_image_quality.clone()
     }
 
+    /// Capture to replay instead of connecting, given by `--replay <file>`.
+    pub fn get_replay(&self) -> Option<String> {
+        let i = self.args.iter().position(|x| x == "--replay")?;
+        self.args.get(i + 1).cloned()
+    }
+
     pub fn get_keyboard_mode(&self) -> String {
         global_get_keyboard_mode()
     }
@@ -1021,6 +1027,10 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Login((password, remember)));
     }
 
+    pub fn login_2fa(&self, code: String) {
+        self.send(Data::Login2fa(code));
+    }
+
     pub fn new_rdp(&self) {
         self.send(Data::NewRDP);
     }
@@ -1152,7 +1162,10 @@ impl<T: InvokeUiSession> Interface for Session<T> {
         if pi.current_display as usize >= pi.displays.len() {
             pi.current_display = 0;
         }
-        if get_version_number(&pi.version) < get_version_number("1.1.10") {
+        if !pi
+            .get_capabilities()
+            .has_session(capability::SESSION_RESTART)
+        {
             self.set_permission("restart", false);
         }
         if self.is_file_transfer() {
@@ -1205,9 +1218,23 @@ impl<T: InvokeUiSession> Interface for Session<T> {
     }
 
     async fn handle_login_from_ui(&mut self, password: String, remember: bool, peer: &mut Stream) {
+        if remember && secret_store::is_locked() {
+            // the password could not be sealed, ask again rather than forget it silently
+            self.msgbox(
+                "input-password",
+                "Password Required",
+                &secret_store::Locked.to_string(),
+                "",
+            );
+            return;
+        }
         handle_login_from_ui(self.lc.clone(), password, remember, peer).await;
     }
 
+    async fn handle_login_2fa(&mut self, code: String, peer: &mut Stream) {
+        handle_login_2fa(self.lc.clone(), code, peer).await;
+    }
+
     async fn handle_test_delay(&mut self, t: TestDelay, peer: &mut Stream) {
         if !t.from_client {
             self.update_quality_status(QualityStatus {